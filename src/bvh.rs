@@ -1,13 +1,16 @@
+use crate::scene_components::{CurveType, Primitive};
 use crate::utils::inverse_frame;
-use crate::{scene::Scene, trace::Ray, zero2};
-use glm::{vec2, vec4};
-use glm::{Vec2, Vec4};
+use crate::{scene::Scene, trace::Ray, zero2, zero3};
+use glm::{vec2, vec3, vec4};
+use glm::{Vec2, Vec3, Vec4};
 
 #[derive(Debug)]
 pub struct BvhIntersection {
     pub instance: usize,
     pub element: usize,
     pub uv: Vec2,
+    // unnormalized geometric normal in the shape frame
+    pub normal: Vec3,
     pub distance: f32,
     pub hit: bool,
 }
@@ -18,6 +21,7 @@ impl Default for BvhIntersection {
             instance: usize::MAX,
             element: usize::MAX,
             uv: zero2!(),
+            normal: zero3!(),
             distance: 0.0,
             hit: false,
         }
@@ -27,6 +31,63 @@ impl Default for BvhIntersection {
 pub struct BvhData<'a> {
     pub scene: embree::Scene<'a>,
     pub shapes: Vec<embree::Scene<'a>>,
+    // user data of the analytic shapes, kept alive for embree callbacks
+    pub primitives: Vec<Box<Primitive>>,
+}
+
+unsafe extern "C" fn primitive_bounds(args: *const embree::sys::RTCBoundsFunctionArguments) {
+    let args = &*args;
+    let primitive = &*(args.geometryUserPtr as *const Primitive);
+    let (lower, upper) = primitive.bounds();
+    let bounds = &mut *args.bounds_o;
+    bounds.lower_x = lower.x;
+    bounds.lower_y = lower.y;
+    bounds.lower_z = lower.z;
+    bounds.upper_x = upper.x;
+    bounds.upper_y = upper.y;
+    bounds.upper_z = upper.z;
+}
+
+// rays are traced one at a time, so packets hold a single ray
+unsafe extern "C" fn primitive_intersect(args: *const embree::sys::RTCIntersectFunctionNArguments) {
+    let args = &*args;
+    if *args.valid == 0 {
+        return;
+    }
+    let primitive = &*(args.geometryUserPtr as *const Primitive);
+    let ray_hit = &mut *(args.rayhit as *mut embree::sys::RTCRayHit);
+    let origin = vec3(ray_hit.ray.org_x, ray_hit.ray.org_y, ray_hit.ray.org_z);
+    let direction = vec3(ray_hit.ray.dir_x, ray_hit.ray.dir_y, ray_hit.ray.dir_z);
+    if let Some((distance, uv, normal)) =
+        primitive.intersect(&origin, &direction, ray_hit.ray.tnear, ray_hit.ray.tfar)
+    {
+        ray_hit.ray.tfar = distance;
+        ray_hit.hit.u = uv.x;
+        ray_hit.hit.v = uv.y;
+        ray_hit.hit.Ng_x = normal.x;
+        ray_hit.hit.Ng_y = normal.y;
+        ray_hit.hit.Ng_z = normal.z;
+        ray_hit.hit.primID = args.primID;
+        ray_hit.hit.geomID = args.geomID;
+        ray_hit.hit.instID[0] = (*args.context).instID[0];
+    }
+}
+
+unsafe extern "C" fn primitive_occluded(args: *const embree::sys::RTCOccludedFunctionNArguments) {
+    let args = &*args;
+    if *args.valid == 0 {
+        return;
+    }
+    let primitive = &*(args.geometryUserPtr as *const Primitive);
+    let ray = &mut *(args.ray as *mut embree::sys::RTCRay);
+    let origin = vec3(ray.org_x, ray.org_y, ray.org_z);
+    let direction = vec3(ray.dir_x, ray.dir_y, ray.dir_z);
+    if primitive
+        .intersect(&origin, &direction, ray.tnear, ray.tfar)
+        .is_some()
+    {
+        ray.tfar = f32::NEG_INFINITY;
+    }
 }
 
 impl BvhData<'_> {
@@ -36,6 +97,7 @@ impl BvhData<'_> {
         highquality: bool,
     ) -> BvhData<'a> {
         let mut bvh_shapes = Vec::with_capacity(scene.shapes.len());
+        let mut primitives = Vec::new();
 
         for shape in &scene.shapes {
             let escene = embree::Scene::new(device);
@@ -49,12 +111,36 @@ impl BvhData<'_> {
                     embree::sys::rtcSetSceneFlags(escene.handle(), embree::SceneFlags::COMPACT);
                 }
             }
-            if !shape.lines.is_empty() {
+            if let Some(primitive) = shape.primitive {
+                let mut primitive = Box::new(primitive);
+                unsafe {
+                    use embree::sys::*;
+                    use embree::*;
+                    let egeometry = rtcNewGeometry(device.handle, GeometryType::USER);
+                    rtcSetGeometryUserPrimitiveCount(egeometry, 1);
+                    let user_data = primitive.as_mut() as *mut Primitive as *mut std::ffi::c_void;
+                    rtcSetGeometryUserData(egeometry, user_data);
+                    rtcSetGeometryBoundsFunction(egeometry, Some(primitive_bounds), user_data);
+                    rtcSetGeometryIntersectFunction(egeometry, Some(primitive_intersect));
+                    rtcSetGeometryOccludedFunction(egeometry, Some(primitive_occluded));
+                    rtcCommitGeometry(egeometry);
+                    rtcAttachGeometryByID(escene.handle(), egeometry, 0);
+                    rtcReleaseGeometry(egeometry);
+                }
+                primitives.push(primitive);
+            } else if !shape.lines.is_empty() {
                 let mut elines: Vec<i32> = Vec::new();
                 let mut epositions: Vec<Vec4> = Vec::new();
                 let mut last_index = -1;
-                for l in &shape.lines {
-                    if last_index == l.x {
+                for (element, l) in shape.lines.iter().enumerate() {
+                    if shape.curve.is_cubic() {
+                        // cubic segments read four consecutive control points
+                        elines.push(epositions.len() as i32);
+                        for control in shape.curve_controls(element) {
+                            let pos = &shape.positions[control];
+                            epositions.push(vec4(pos.x, pos.y, pos.z, shape.radius[control]));
+                        }
+                    } else if last_index == l.x {
                         elines.push(epositions.len() as i32 - 1);
                         let posy = &shape.positions[l.y as usize];
                         let rady = shape.radius[l.y as usize];
@@ -73,7 +159,14 @@ impl BvhData<'_> {
                 unsafe {
                     use embree::sys::*;
                     use embree::*;
-                    let egeometry = rtcNewGeometry(device.handle, GeometryType::FLAT_LINEAR_CURVE);
+                    let curve_type = match shape.curve {
+                        CurveType::Flat => GeometryType::FLAT_LINEAR_CURVE,
+                        CurveType::Round => GeometryType::ROUND_LINEAR_CURVE,
+                        CurveType::Bezier => GeometryType::ROUND_BEZIER_CURVE,
+                        CurveType::Bspline => GeometryType::ROUND_BSPLINE_CURVE,
+                        CurveType::Catmullrom => GeometryType::ROUND_CATMULL_ROM_CURVE,
+                    };
+                    let egeometry = rtcNewGeometry(device.handle, curve_type);
                     rtcSetGeometryVertexAttributeCount(egeometry, 1);
 
                     let embree_positions = rtcSetNewGeometryBuffer(
@@ -210,6 +303,7 @@ impl BvhData<'_> {
         BvhData {
             scene: escene,
             shapes: bvh_shapes,
+            primitives,
         }
     }
 
@@ -223,6 +317,7 @@ impl BvhData<'_> {
                 instance: ray_hit.hit.instID[0] as usize,
                 element: ray_hit.hit.primID as usize,
                 uv: vec2(ray_hit.hit.u, ray_hit.hit.v),
+                normal: vec3(ray_hit.hit.Ng_x, ray_hit.hit.Ng_y, ray_hit.hit.Ng_z),
                 distance: ray_hit.ray.tfar,
                 hit: true,
             }
@@ -253,6 +348,7 @@ impl BvhData<'_> {
                 instance: instance_idx,
                 element: ray_hit.hit.primID as usize,
                 uv: vec2(ray_hit.hit.u, ray_hit.hit.v),
+                normal: vec3(ray_hit.hit.Ng_x, ray_hit.hit.Ng_y, ray_hit.hit.Ng_z),
                 distance: ray_hit.ray.tfar,
                 hit: true,
             }
@@ -8,5 +8,6 @@ pub mod model_io;
 pub mod scene;
 pub mod scene_components;
 pub mod shading;
+pub mod texture_cache;
 pub mod trace;
 pub mod utils;
@@ -1,6 +1,7 @@
 use indicatif::ProgressBar;
 use rtrace::bvh::*;
 use rtrace::scene::*;
+use rtrace::texture_cache::CacheOptions;
 use rtrace::trace;
 use rtrace::utils::*;
 
@@ -16,7 +17,11 @@ pub fn main() {
     scene_bar.inc(0);
 
     // load scene
-    let scene = Scene::from_json(scene_path);
+    let cache_options = (params.texture_cache > 0).then(|| CacheOptions {
+        budget: params.texture_cache * 1024 * 1024,
+        convert: params.texture_convert,
+    });
+    let scene = Scene::from_file(scene_path, cache_options);
     let device = embree::Device::new();
     let bvh = BvhData::from_scene(&device, &scene, false);
     let mut state = RaytraceState::from_scene(&scene, &params);
@@ -38,6 +43,16 @@ pub fn main() {
         samples_bar.inc(1);
     }
     samples_bar.finish();
+    if let Some(cache) = &scene.texture_cache {
+        let stats = cache.stats();
+        println!(
+            "Texture cache: {} hits, {} misses, {} evictions, {:.1} MB in use",
+            stats.hits,
+            stats.misses,
+            stats.evictions,
+            stats.memory as f64 / (1024.0 * 1024.0)
+        );
+    }
 
     // output final image
     let output_path = args.value_of("output").unwrap();
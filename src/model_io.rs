@@ -5,153 +5,1938 @@ pub mod ply {
     use glm::{TVec2, Vec2, Vec3, Vec4};
     use linked_hash_map::LinkedHashMap;
     use ply_rs::ply::Property;
+    use std::fs::File;
+    use std::io::{BufReader, Error, ErrorKind, Result};
+    use std::path::Path;
 
-    #[inline(always)]
-    pub fn get_positions(vertex: &LinkedHashMap<String, Property>, positions: &mut Vec<Vec3>) {
-        if vertex.get("x").is_none() || vertex.get("y").is_none() || vertex.get("z").is_none() {
-            return;
+    fn invalid_data<E: std::fmt::Display>(error: E) -> Error {
+        Error::new(ErrorKind::InvalidData, error.to_string())
+    }
+
+    // scalar property of any type, none when the element does not have it
+    fn get_scalar(element: &LinkedHashMap<String, Property>, name: &str) -> Result<Option<f32>> {
+        let value = match element.get(name) {
+            None => return Ok(None),
+            Some(Property::Char(c)) => *c as f32,
+            Some(Property::UChar(c)) => *c as f32,
+            Some(Property::Short(c)) => *c as f32,
+            Some(Property::UShort(c)) => *c as f32,
+            Some(Property::Int(c)) => *c as f32,
+            Some(Property::UInt(c)) => *c as f32,
+            Some(Property::Float(c)) => *c,
+            Some(Property::Double(c)) => *c as f32,
+            Some(_) => return Err(invalid_data(format!("{} is a list", name))),
+        };
+        Ok(Some(value))
+    }
+
+    // color channel, with unsigned integers spanning [0, 1]
+    fn get_channel(element: &LinkedHashMap<String, Property>, name: &str) -> Result<Option<f32>> {
+        match element.get(name) {
+            Some(Property::UChar(c)) => Ok(Some(*c as f32 / 255.0)),
+            Some(Property::UShort(c)) => Ok(Some(*c as f32 / 65535.0)),
+            _ => get_scalar(element, name),
         }
-        let x = match vertex["x"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+    }
+
+    // vertex indices stored in lists of any integer type
+    fn get_indices(
+        element: &LinkedHashMap<String, Property>,
+        name: &str,
+    ) -> Result<Option<Vec<i32>>> {
+        let indices = match element.get(name) {
+            None => return Ok(None),
+            Some(Property::ListChar(c)) => c.iter().map(|&i| i as i32).collect(),
+            Some(Property::ListUChar(c)) => c.iter().map(|&i| i as i32).collect(),
+            Some(Property::ListShort(c)) => c.iter().map(|&i| i as i32).collect(),
+            Some(Property::ListUShort(c)) => c.iter().map(|&i| i as i32).collect(),
+            Some(Property::ListInt(c)) => c.clone(),
+            Some(Property::ListUInt(c)) => c
+                .iter()
+                .map(|&i| i32::try_from(i).map_err(invalid_data))
+                .collect::<Result<_>>()?,
+            Some(_) => return Err(invalid_data(format!("{} is not an index list", name))),
         };
-        let y = match vertex["y"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        Ok(Some(indices))
+    }
+
+    fn get_element_indices(element: &LinkedHashMap<String, Property>) -> Result<Option<Vec<i32>>> {
+        match get_indices(element, "vertex_indices")? {
+            Some(indices) => Ok(Some(indices)),
+            None => get_indices(element, "vertex_index"),
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_positions(
+        vertex: &LinkedHashMap<String, Property>,
+        positions: &mut Vec<Vec3>,
+    ) -> Result<()> {
+        if let (Some(x), Some(y), Some(z)) = (
+            get_scalar(vertex, "x")?,
+            get_scalar(vertex, "y")?,
+            get_scalar(vertex, "z")?,
+        ) {
+            positions.push(vec3(x, y, z));
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn get_normals(
+        vertex: &LinkedHashMap<String, Property>,
+        normals: &mut Vec<Vec3>,
+    ) -> Result<()> {
+        if let (Some(nx), Some(ny), Some(nz)) = (
+            get_scalar(vertex, "nx")?,
+            get_scalar(vertex, "ny")?,
+            get_scalar(vertex, "nz")?,
+        ) {
+            normals.push(vec3(nx, ny, nz));
+        }
+        Ok(())
+    }
+
+    // texcoords go by several names across exporters
+    #[inline(always)]
+    pub fn get_texcoords(
+        vertex: &LinkedHashMap<String, Property>,
+        texcoords: &mut Vec<Vec2>,
+    ) -> Result<()> {
+        for (u, v) in [
+            ("u", "v"),
+            ("s", "t"),
+            ("texture_u", "texture_v"),
+            ("texture_s", "texture_t"),
+        ] {
+            if let (Some(u), Some(v)) = (get_scalar(vertex, u)?, get_scalar(vertex, v)?) {
+                // flipping
+                texcoords.push(vec2(u, 1.0 - v));
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn get_radius(
+        vertex: &LinkedHashMap<String, Property>,
+        radii: &mut Vec<f32>,
+    ) -> Result<()> {
+        if let Some(radius) = get_scalar(vertex, "radius")? {
+            radii.push(radius);
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn get_colors(
+        vertex: &LinkedHashMap<String, Property>,
+        colors: &mut Vec<Vec4>,
+    ) -> Result<()> {
+        if let (Some(red), Some(green), Some(blue)) = (
+            get_channel(vertex, "red")?,
+            get_channel(vertex, "green")?,
+            get_channel(vertex, "blue")?,
+        ) {
+            let alpha = get_channel(vertex, "alpha")?.unwrap_or(1.0);
+            colors.push(vec4(red, green, blue, alpha));
+        }
+        Ok(())
+    }
+
+    // polygons larger than quads are split in fans
+    #[inline(always)]
+    pub fn get_faces(face: &LinkedHashMap<String, Property>, shape: &mut Shape) -> Result<()> {
+        let Some(indices) = get_element_indices(face)? else {
+            return Ok(());
         };
-        let z = match vertex["z"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        match indices.len() {
+            0..=2 => return Err(invalid_data("face with less than three vertices")),
+            4 => shape
+                .quads
+                .push(vec4(indices[0], indices[1], indices[2], indices[3])),
+            _ => {
+                for i in 2..indices.len() {
+                    shape
+                        .triangles
+                        .push(vec3(indices[0], indices[i - 1], indices[i]));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn get_points(
+        point: &LinkedHashMap<String, Property>,
+        points: &mut Vec<i32>,
+    ) -> Result<()> {
+        if let Some(indices) = get_element_indices(point)? {
+            points.extend(indices);
+        } else if let Some(index) = get_scalar(point, "vertex_indices")? {
+            points.push(index as i32);
+        }
+        Ok(())
+    }
+
+    // polylines are split in segments
+    #[inline(always)]
+    pub fn get_lines(
+        line: &LinkedHashMap<String, Property>,
+        lines: &mut Vec<TVec2<i32>>,
+    ) -> Result<()> {
+        let Some(indices) = get_element_indices(line)? else {
+            return Ok(());
         };
-        positions.push(vec3(x, y, z));
+        if indices.len() < 2 {
+            return Err(invalid_data("line with less than two vertices"));
+        }
+        lines.extend(
+            indices
+                .windows(2)
+                .map(|segment| vec2(segment[0], segment[1])),
+        );
+        Ok(())
+    }
+
+    // elements and vertex data of a ply file in ascii or binary format
+    pub fn read_shape(path: &Path, shape: &mut Shape) -> Result<()> {
+        let mut file = BufReader::new(File::open(path)?);
+        let parser = ply_rs::parser::Parser::<ply_rs::ply::DefaultElement>::new();
+        let ply = parser.read_ply(&mut file)?;
+        for vertex in ply.payload.get("vertex").into_iter().flatten() {
+            get_positions(vertex, &mut shape.positions)?;
+            get_normals(vertex, &mut shape.normals)?;
+            get_texcoords(vertex, &mut shape.texcoords)?;
+            get_colors(vertex, &mut shape.colors)?;
+            get_radius(vertex, &mut shape.radius)?;
+        }
+        for face in ply.payload.get("face").into_iter().flatten() {
+            get_faces(face, shape)?;
+        }
+        for line in ply.payload.get("line").into_iter().flatten() {
+            get_lines(line, &mut shape.lines)?;
+        }
+        for point in ply.payload.get("point").into_iter().flatten() {
+            get_points(point, &mut shape.points)?;
+        }
+
+        // shapes hold a single kind of face, so next to quads triangles
+        // become quads repeating their last vertex
+        if !shape.quads.is_empty() && !shape.triangles.is_empty() {
+            let triangles = std::mem::take(&mut shape.triangles);
+            let quads = triangles.iter().map(|t| vec4(t.x, t.y, t.z, t.z));
+            shape.quads.extend(quads);
+        }
+        let count = shape.positions.len() as i32;
+        let mut indices = shape.points.iter().chain(shape.lines.iter().flatten());
+        let mut faces = shape
+            .triangles
+            .iter()
+            .flatten()
+            .chain(shape.quads.iter().flatten());
+        let out_of_bounds = |index: &&i32| **index < 0 || **index >= count;
+        if let Some(index) = indices
+            .find(out_of_bounds)
+            .or_else(|| faces.find(out_of_bounds))
+        {
+            return Err(invalid_data(format!(
+                "vertex index {} out of bounds",
+                index
+            )));
+        }
+        Ok(())
     }
+}
+
+pub mod vol {
+    use crate::scene_components::*;
+    use glm::vec3;
+    use std::fs::File;
+    use std::io::{BufReader, Error, ErrorKind, Read, Result};
+    use std::path::Path;
+
+    #[inline(always)]
+    fn read_i32<R: Read>(reader: &mut R) -> Result<i32> {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(i32::from_le_bytes(bytes))
+    }
+
+    #[inline(always)]
+    fn read_f32<R: Read>(reader: &mut R) -> Result<f32> {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(f32::from_le_bytes(bytes))
+    }
+
+    // grid chosen by extension
+    pub fn read_volume(path: &Path, volume: &mut Volume) -> Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|os_str| os_str.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("vol") => read_vol(&mut BufReader::new(File::open(path)?), volume),
+            Some("raw") => read_raw(&mut BufReader::new(File::open(path)?), volume),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "unsupported volume format",
+            )),
+        }
+    }
+
+    // Mitsuba grid volume format: "VOL" + version 3, encoding, resolution,
+    // channels, bounding box, followed by the voxels with x varying fastest
+    pub fn read_vol<R: Read>(reader: &mut R, volume: &mut Volume) -> Result<()> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic[0..3] != b"VOL" || magic[3] != 3 {
+            return Err(Error::new(ErrorKind::InvalidData, "not a VOL v3 file"));
+        }
+        let encoding = read_i32(reader)?;
+        let width = read_i32(reader)?;
+        let height = read_i32(reader)?;
+        let depth = read_i32(reader)?;
+        let channels = read_i32(reader)?;
+        if width <= 0 || height <= 0 || depth <= 0 || channels <= 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid VOL resolution"));
+        }
+        let mut bbox = [0.0; 6];
+        for value in bbox.iter_mut() {
+            *value = read_f32(reader)?;
+        }
+        let voxels = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|voxels| voxels.checked_mul(depth as usize))
+            .filter(|voxels| voxels.checked_mul(channels as usize).is_some())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid VOL resolution"))?;
+        // the header is not trusted for the allocation, values grow as read
+        let mut values = Vec::new();
+        match encoding {
+            // float32
+            1 => {
+                for _ in 0..voxels * channels as usize {
+                    values.push(read_f32(reader)?);
+                }
+            }
+            // uint8
+            3 => {
+                let size = voxels * channels as usize;
+                let mut bytes = Vec::new();
+                reader.take(size as u64).read_to_end(&mut bytes)?;
+                if bytes.len() != size {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "truncated VOL voxels"));
+                }
+                values.extend(bytes.iter().map(|&b| b as f32 / 255.0));
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "unsupported VOL encoding",
+                ))
+            }
+        }
+        // multi-channel grids are reduced to their average
+        volume.density = values
+            .chunks(channels as usize)
+            .map(|c| c.iter().sum::<f32>() / channels as f32)
+            .collect();
+        volume.width = width as u32;
+        volume.height = height as u32;
+        volume.depth = depth as u32;
+        volume.bbox_min = vec3(bbox[0], bbox[1], bbox[2]);
+        volume.bbox_max = vec3(bbox[3], bbox[4], bbox[5]);
+        Ok(())
+    }
+
+    // headerless little-endian float32 voxels, resolution taken from the scene
+    pub fn read_raw<R: Read>(reader: &mut R, volume: &mut Volume) -> Result<()> {
+        let voxels = (volume.width as usize)
+            .checked_mul(volume.height as usize)
+            .and_then(|voxels| voxels.checked_mul(volume.depth as usize))
+            .filter(|&voxels| voxels != 0)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "raw volumes need width, height and depth",
+                )
+            })?;
+        volume.density = Vec::new();
+        for _ in 0..voxels {
+            volume.density.push(read_f32(reader)?);
+        }
+        Ok(())
+    }
+}
+
+pub mod brdf {
+    use crate::scene_components::*;
+    use glm::vec3;
+    use std::fs::File;
+    use std::io::{BufReader, Error, ErrorKind, Read, Result};
+    use std::path::Path;
+
+    // scale of the MERL channels to reflectance
+    const MERL_SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
 
     #[inline(always)]
-    pub fn get_normals(vertex: &LinkedHashMap<String, Property>, normals: &mut Vec<Vec3>) {
-        if vertex.get("nx").is_none() || vertex.get("ny").is_none() || vertex.get("nz").is_none() {
-            return;
+    fn read_i32<R: Read>(reader: &mut R) -> Result<i32> {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(i32::from_le_bytes(bytes))
+    }
+
+    // table chosen by extension
+    pub fn read_brdf(path: &Path, brdf: &mut MeasuredBrdf) -> Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|os_str| os_str.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("binary") => read_merl(&mut BufReader::new(File::open(path)?), brdf),
+            Some("raw") => read_tabulated(&mut BufReader::new(File::open(path)?), brdf),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "unsupported brdf format",
+            )),
+        }
+    }
+
+    // number of table entries, none when the product overflows
+    fn table_size(theta_half: u32, theta_diff: u32, phi_diff: u32) -> Option<usize> {
+        (theta_half as usize)
+            .checked_mul(theta_diff as usize)?
+            .checked_mul(phi_diff as usize)
+    }
+
+    // exactly `size` bytes, read without trusting the size for the allocation
+    fn read_bytes<R: Read>(reader: &mut R, size: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        reader.take(size as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != size {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated brdf table"));
+        }
+        Ok(bytes)
+    }
+
+    // MERL binary format: the three resolutions of the half/difference angles
+    // followed by the red, green and blue planes as float64
+    pub fn read_merl<R: Read>(reader: &mut R, brdf: &mut MeasuredBrdf) -> Result<()> {
+        let theta_half = read_i32(reader)?;
+        let theta_diff = read_i32(reader)?;
+        let phi_diff = read_i32(reader)?;
+        if theta_half <= 0 || theta_diff <= 0 || phi_diff <= 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "invalid MERL resolution",
+            ));
         }
-        let nx = match vertex["nx"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        let (theta_half, theta_diff, phi_diff) =
+            (theta_half as u32, theta_diff as u32, phi_diff as u32);
+        let samples = table_size(theta_half, theta_diff, phi_diff)
+            .filter(|samples| samples.checked_mul(3 * 8).is_some())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid MERL resolution"))?;
+        let bytes = read_bytes(reader, samples * 3 * 8)?;
+        let channel = |c: usize, i: usize| {
+            let offset = (c * samples + i) * 8;
+            let value = f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+            // negative entries mark directions below the horizon
+            f64::max(value * MERL_SCALE[c], 0.0) as f32
         };
-        let ny = match vertex["ny"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        brdf.values = (0..samples)
+            .map(|i| vec3(channel(0, i), channel(1, i), channel(2, i)))
+            .collect();
+        brdf.theta_half = theta_half;
+        brdf.theta_diff = theta_diff;
+        brdf.phi_diff = phi_diff;
+        Ok(())
+    }
+
+    // headerless little-endian float32 rgb triples in the MERL layout and
+    // parametrization, resolution taken from the scene
+    pub fn read_tabulated<R: Read>(reader: &mut R, brdf: &mut MeasuredBrdf) -> Result<()> {
+        let samples = table_size(brdf.theta_half, brdf.theta_diff, brdf.phi_diff)
+            .filter(|&samples| samples != 0 && samples.checked_mul(3 * 4).is_some())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "tabulated brdfs need theta_half, theta_diff and phi_diff",
+                )
+            })?;
+        let bytes = read_bytes(reader, samples * 3 * 4)?;
+        let value = |i: usize| f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+        brdf.values = (0..samples)
+            .map(|i| vec3(value(3 * i), value(3 * i + 1), value(3 * i + 2)))
+            .collect();
+        Ok(())
+    }
+}
+
+pub mod texture {
+    use crate::scene_components::*;
+    use image::GenericImageView;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Seek};
+    use std::path::Path;
+
+    fn invalid_data<E: std::fmt::Display>(error: E) -> Error {
+        Error::new(ErrorKind::InvalidData, error.to_string())
+    }
+
+    // image chosen by extension, other formats are left to `image`, which
+    // fails on unknown ones
+    pub fn read_texture(path: &Path, texture: &mut Texture) -> Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|os_str| os_str.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hdr") => read_hdr(BufReader::new(File::open(path)?), texture),
+            Some("exr") => read_exr(path, texture),
+            Some("tif") | Some("tiff") => read_tiff(path, texture),
+            _ => read_image(path, texture),
+        }
+    }
+
+    // size of an image and whether it is linear, reading only its header
+    pub fn read_texture_size(path: &Path) -> Result<(u32, u32, bool)> {
+        let extension = path
+            .extension()
+            .and_then(|os_str| os_str.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hdr") => {
+                let file = BufReader::new(File::open(path)?);
+                let decoder = image::codecs::hdr::HdrDecoder::new(file).map_err(invalid_data)?;
+                let metadata = decoder.metadata();
+                Ok((metadata.width, metadata.height, true))
+            }
+            Some("exr") => {
+                let metadata =
+                    exr::meta::MetaData::read_from_file(path, false).map_err(invalid_data)?;
+                let size = metadata.headers[0].layer_size;
+                Ok((size.width() as u32, size.height() as u32, true))
+            }
+            Some("tif") | Some("tiff") => {
+                let mut decoder = tiff::decoder::Decoder::new(BufReader::new(File::open(path)?))
+                    .map_err(invalid_data)?;
+                let (width, height) = decoder.dimensions().map_err(invalid_data)?;
+                Ok((width, height, is_float_tiff(&mut decoder)?))
+            }
+            _ => {
+                let (width, height) = image::image_dimensions(path).map_err(invalid_data)?;
+                Ok((width, height, false))
+            }
+        }
+    }
+
+    // files of a udim set, found by replacing the <UDIM> token in the file
+    // name with four digit tile numbers from 1001
+    pub fn find_udim_tiles(path: &Path) -> Result<HashMap<u32, TextureTile>> {
+        let pattern = path
+            .file_name()
+            .and_then(|os_str| os_str.to_str())
+            .ok_or_else(|| invalid_data("invalid udim path"))?;
+        let (prefix, suffix) = pattern
+            .split_once("<UDIM>")
+            .ok_or_else(|| invalid_data("missing <UDIM> token"))?;
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut tiles = HashMap::new();
+        for entry in std::fs::read_dir(directory)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let tile = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(prefix))
+                .and_then(|name| name.strip_suffix(suffix))
+                .filter(|digits| digits.len() == 4)
+                .and_then(|digits| digits.parse::<u32>().ok())
+                .filter(|tile| *tile >= 1001);
+            if let Some(tile) = tile {
+                let texture_tile = TextureTile {
+                    path: entry.path(),
+                    ..Default::default()
+                };
+                tiles.insert(tile, texture_tile);
+            }
+        }
+        if tiles.is_empty() {
+            return Err(invalid_data(format!("no tiles match {}", pattern)));
+        }
+        Ok(tiles)
+    }
+
+    // 8-bit and 16-bit images in any format known to `image`, kept at their
+    // precision; grayscale is replicated over the color channels so that
+    // single-channel maps read the same from any channel, and missing alpha
+    // is opaque
+    pub fn read_image(path: &Path, texture: &mut Texture) -> Result<()> {
+        let image = image::open(path).map_err(invalid_data)?;
+        set_image(image, texture);
+        Ok(())
+    }
+
+    // images embedded in other files, with the format guessed from the data
+    pub fn read_image_from_memory(data: &[u8], texture: &mut Texture) -> Result<()> {
+        let image = image::load_from_memory(data).map_err(invalid_data)?;
+        set_image(image, texture);
+        Ok(())
+    }
+
+    fn set_image(image: image::DynamicImage, texture: &mut Texture) {
+        texture.width = image.width();
+        texture.height = image.height();
+        texture.linear = false;
+        match image.color() {
+            image::ColorType::L16
+            | image::ColorType::La16
+            | image::ColorType::Rgb16
+            | image::ColorType::Rgba16 => texture.shorts = image.into_rgba16(),
+            _ => texture.bytes = image.into_rgba8(),
+        }
+    }
+
+    pub fn read_hdr<R: BufRead>(reader: R, texture: &mut Texture) -> Result<()> {
+        let decoder = image::codecs::hdr::HdrDecoder::new(reader).map_err(invalid_data)?;
+        let metadata = decoder.metadata();
+        texture.width = metadata.width;
+        texture.height = metadata.height;
+        texture.linear = true;
+        texture.hdr = decoder
+            .read_image_hdr()
+            .map_err(invalid_data)?
+            .into_iter()
+            .map(|image::Rgb([r, g, b])| image::Rgba([r, g, b, 1.0]))
+            .collect();
+        Ok(())
+    }
+
+    fn is_float_tiff<R: Read + Seek>(decoder: &mut tiff::decoder::Decoder<R>) -> Result<bool> {
+        let formats = decoder
+            .find_tag_unsigned_vec::<u16>(tiff::tags::Tag::SampleFormat)
+            .map_err(invalid_data)?
+            .unwrap_or_default();
+        Ok(formats.into_iter().any(|format| {
+            tiff::tags::SampleFormat::from_u16(format) == Some(tiff::tags::SampleFormat::IEEEFP)
+        }))
+    }
+
+    // float tiffs, which `image` does not decode, are read as linear hdr
+    // with the channels laid out as for exr; others are left to `image`
+    pub fn read_tiff(path: &Path, texture: &mut Texture) -> Result<()> {
+        let mut decoder =
+            tiff::decoder::Decoder::new(BufReader::new(File::open(path)?)).map_err(invalid_data)?;
+        if !is_float_tiff(&mut decoder)? {
+            return read_image(path, texture);
+        }
+        let (width, height) = decoder.dimensions().map_err(invalid_data)?;
+        let channels = match decoder.colortype().map_err(invalid_data)? {
+            tiff::ColorType::Gray(32) => 1,
+            tiff::ColorType::GrayA(32) => 2,
+            tiff::ColorType::RGB(32) => 3,
+            tiff::ColorType::RGBA(32) => 4,
+            color => return Err(invalid_data(format!("unsupported tiff color {:?}", color))),
         };
-        let nz = match vertex["nz"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        let values = match decoder.read_image().map_err(invalid_data)? {
+            tiff::decoder::DecodingResult::F32(values) => values,
+            _ => return Err(invalid_data("unsupported tiff sample format")),
         };
-        normals.push(vec3(nx, ny, nz));
+        if values.len() != width as usize * height as usize * channels {
+            return Err(invalid_data("truncated tiff"));
+        }
+        texture.width = width;
+        texture.height = height;
+        texture.linear = true;
+        texture.hdr = values.chunks(channels).map(hdr_texel).collect();
+        Ok(())
     }
 
-    #[inline(always)]
-    pub fn get_texcoords(vertex: &LinkedHashMap<String, Property>, texcoords: &mut Vec<Vec2>) {
-        if vertex.get("u").is_none() || vertex.get("v").is_none() {
-            return;
+    // luminance is replicated over the color channels and missing alpha is
+    // opaque, as for 8-bit images
+    fn hdr_texel(channels: &[f32]) -> image::Rgba<f32> {
+        match *channels {
+            [y] => image::Rgba([y, y, y, 1.0]),
+            [y, a] => image::Rgba([y, y, y, a]),
+            [r, g, b] => image::Rgba([r, g, b, 1.0]),
+            [r, g, b, a, ..] => image::Rgba([r, g, b, a]),
+            _ => image::Rgba([0.0, 0.0, 0.0, 1.0]),
         }
-        let u = match vertex["u"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+    }
+
+    // first layer of an openexr image, from its rgb or luminance channels
+    // with optional alpha
+    pub fn read_exr(path: &Path, texture: &mut Texture) -> Result<()> {
+        let image = exr::prelude::read_first_flat_layer_from_file(path).map_err(invalid_data)?;
+        let layer = image.layer_data;
+        let (width, height) = (layer.size.width(), layer.size.height());
+        let channel = |name: &str| {
+            layer
+                .channel_data
+                .list
+                .iter()
+                .find(|channel| channel.name == *name)
+                .map(|channel| &channel.sample_data)
         };
-        let v = match vertex["v"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        let names: &[&str] = match (channel("R"), channel("G"), channel("B"), channel("Y")) {
+            (Some(_), Some(_), Some(_), _) => &["R", "G", "B"],
+            (_, _, _, Some(_)) => &["Y"],
+            _ => return Err(invalid_data("exr without rgb or luminance channels")),
         };
-        // flipping
-        texcoords.push(vec2(u, 1.0 - v));
+        let mut channels = names
+            .iter()
+            .filter_map(|&name| channel(name))
+            .collect::<Vec<_>>();
+        channels.extend(channel("A"));
+        if channels
+            .iter()
+            .any(|samples| samples.len() != width * height)
+        {
+            return Err(invalid_data("subsampled exr channels"));
+        }
+        texture.width = width as u32;
+        texture.height = height as u32;
+        texture.linear = true;
+        texture.hdr = (0..width * height)
+            .map(|index| {
+                let values = channels
+                    .iter()
+                    .map(|samples| samples.value_by_flat_index(index).to_f32())
+                    .collect::<Vec<_>>();
+                hdr_texel(&values)
+            })
+            .collect();
+        Ok(())
     }
+}
 
-    #[inline(always)]
-    pub fn get_radius(vertex: &LinkedHashMap<String, Property>, radii: &mut Vec<f32>) {
-        if vertex.get("radius").is_none() {
-            return;
+pub mod gltf {
+    use crate::scene::Scene;
+    use crate::scene_components::*;
+    use glm::{vec3, vec4, Mat3x4, Mat4, TVec2, TVec3, Vec2, Vec3, Vec4};
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::io::{Error, ErrorKind, Result};
+    use std::path::Path;
+    const INVALID: usize = usize::MAX;
+
+    fn invalid_data<E: std::fmt::Display>(error: E) -> Error {
+        Error::new(ErrorKind::InvalidData, error.to_string())
+    }
+
+    // the subset of the glTF 2.0 schema that maps onto scene components
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "camelCase")]
+    struct Gltf {
+        scene: Option<usize>,
+        scenes: Vec<GltfScene>,
+        nodes: Vec<GltfNode>,
+        meshes: Vec<GltfMesh>,
+        accessors: Vec<GltfAccessor>,
+        buffer_views: Vec<GltfBufferView>,
+        buffers: Vec<GltfBuffer>,
+        materials: Vec<GltfMaterial>,
+        textures: Vec<GltfTexture>,
+        images: Vec<GltfImage>,
+        samplers: Vec<GltfSampler>,
+        cameras: Vec<GltfCamera>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfScene {
+        nodes: Vec<usize>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default)]
+    struct GltfNode {
+        children: Vec<usize>,
+        mesh: Option<usize>,
+        camera: Option<usize>,
+        matrix: Option<[f32; 16]>,
+        translation: [f32; 3],
+        rotation: [f32; 4],
+        scale: [f32; 3],
+    }
+
+    impl Default for GltfNode {
+        fn default() -> Self {
+            GltfNode {
+                children: Vec::new(),
+                mesh: None,
+                camera: None,
+                matrix: None,
+                translation: [0.0, 0.0, 0.0],
+                rotation: [0.0, 0.0, 0.0, 1.0],
+                scale: [1.0, 1.0, 1.0],
+            }
+        }
+    }
+
+    impl GltfNode {
+        // local transform, either as a column-major matrix or as translation,
+        // rotation and scale applied in reverse order
+        fn transform(&self) -> Mat4 {
+            if let Some(matrix) = &self.matrix {
+                return glm::make_mat4(matrix);
+            }
+            let [x, y, z, w] = self.rotation;
+            glm::translation(&glm::make_vec3(&self.translation))
+                * glm::quat_to_mat4(&glm::quat(x, y, z, w))
+                * glm::scaling(&glm::make_vec3(&self.scale))
+        }
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfMesh {
+        primitives: Vec<GltfPrimitive>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default)]
+    struct GltfPrimitive {
+        attributes: HashMap<String, usize>,
+        indices: Option<usize>,
+        material: Option<usize>,
+        mode: u32,
+    }
+
+    impl Default for GltfPrimitive {
+        fn default() -> Self {
+            GltfPrimitive {
+                attributes: HashMap::new(),
+                indices: None,
+                material: None,
+                // triangles
+                mode: 4,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfAccessor {
+        buffer_view: Option<usize>,
+        byte_offset: usize,
+        component_type: u32,
+        normalized: bool,
+        count: usize,
+        #[serde(rename = "type")]
+        a_type: String,
+        sparse: Option<serde::de::IgnoredAny>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfBufferView {
+        buffer: usize,
+        byte_offset: usize,
+        byte_length: usize,
+        byte_stride: Option<usize>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfBuffer {
+        uri: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfMaterial {
+        pbr_metallic_roughness: GltfPbr,
+        normal_texture: Option<GltfTextureInfo>,
+        emissive_texture: Option<GltfTextureInfo>,
+        emissive_factor: [f32; 3],
+        alpha_mode: String,
+        extensions: GltfExtensions,
+    }
+
+    impl Default for GltfMaterial {
+        fn default() -> Self {
+            GltfMaterial {
+                pbr_metallic_roughness: GltfPbr::default(),
+                normal_texture: None,
+                emissive_texture: None,
+                emissive_factor: [0.0, 0.0, 0.0],
+                alpha_mode: "OPAQUE".to_string(),
+                extensions: GltfExtensions::default(),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfPbr {
+        base_color_factor: [f32; 4],
+        base_color_texture: Option<GltfTextureInfo>,
+        metallic_factor: f32,
+        roughness_factor: f32,
+        metallic_roughness_texture: Option<GltfTextureInfo>,
+    }
+
+    impl Default for GltfPbr {
+        fn default() -> Self {
+            GltfPbr {
+                base_color_factor: [1.0, 1.0, 1.0, 1.0],
+                base_color_texture: None,
+                metallic_factor: 1.0,
+                roughness_factor: 1.0,
+                metallic_roughness_texture: None,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfTextureInfo {
+        index: usize,
+    }
+
+    // supported material extensions, others are ignored
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfExtensions {
+        #[serde(rename = "KHR_materials_transmission")]
+        transmission: Option<GltfTransmission>,
+        #[serde(rename = "KHR_materials_emissive_strength")]
+        emissive_strength: Option<GltfEmissiveStrength>,
+        #[serde(rename = "KHR_materials_clearcoat")]
+        clearcoat: Option<GltfClearcoat>,
+        #[serde(rename = "KHR_materials_ior")]
+        ior: Option<GltfIor>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfTransmission {
+        transmission_factor: f32,
+        transmission_texture: Option<GltfTextureInfo>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfEmissiveStrength {
+        emissive_strength: f32,
+    }
+
+    impl Default for GltfEmissiveStrength {
+        fn default() -> Self {
+            GltfEmissiveStrength {
+                emissive_strength: 1.0,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfClearcoat {
+        clearcoat_factor: f32,
+        clearcoat_texture: Option<GltfTextureInfo>,
+        clearcoat_roughness_factor: f32,
+        clearcoat_roughness_texture: Option<GltfTextureInfo>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default)]
+    struct GltfIor {
+        ior: f32,
+    }
+
+    impl Default for GltfIor {
+        fn default() -> Self {
+            GltfIor { ior: 1.5 }
+        }
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfTexture {
+        sampler: Option<usize>,
+        source: Option<usize>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfImage {
+        uri: String,
+        buffer_view: Option<usize>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfSampler {
+        wrap_s: u32,
+        wrap_t: u32,
+    }
+
+    impl Default for GltfSampler {
+        fn default() -> Self {
+            GltfSampler {
+                // repeat
+                wrap_s: 10497,
+                wrap_t: 10497,
+            }
+        }
+    }
+
+    impl GltfSampler {
+        // textures wrap both axes the same way, so the more restrictive of
+        // the two sampler modes applies to both
+        fn wrap_mode(&self) -> WrapMode {
+            let mode = |wrap: u32| match wrap {
+                33071 => (2, WrapMode::Clamp),
+                33648 => (1, WrapMode::Mirror),
+                _ => (0, WrapMode::Repeat),
+            };
+            std::cmp::max_by_key(mode(self.wrap_s), mode(self.wrap_t), |(rank, _)| *rank).1
         }
-        let radius = match vertex["radius"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfCamera {
+        perspective: Option<GltfPerspective>,
+        orthographic: Option<GltfOrthographic>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "camelCase")]
+    struct GltfPerspective {
+        yfov: f32,
+        aspect_ratio: Option<f32>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct GltfOrthographic {
+        xmag: f32,
+        ymag: f32,
+    }
+
+    // json and binary chunks of a glb container
+    fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+        let read_u32 = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+                .ok_or_else(|| invalid_data("truncated glb"))
         };
-        radii.push(radius);
+        if read_u32(4)? != 2 {
+            return Err(invalid_data("unsupported glb version"));
+        }
+        let length = usize::min(read_u32(8)?, data.len());
+        let (mut json, mut bin) = (None, None);
+        let mut offset = 12;
+        while offset + 8 <= length {
+            let (chunk_length, chunk_type) = (read_u32(offset)?, read_u32(offset + 4)?);
+            let chunk = data
+                .get(offset + 8..offset + 8 + chunk_length)
+                .ok_or_else(|| invalid_data("truncated glb"))?;
+            match chunk_type {
+                0x4e4f534a => json = json.or(Some(chunk)),
+                0x004e4942 => bin = bin.or(Some(chunk)),
+                _ => {}
+            }
+            offset += 8 + chunk_length;
+        }
+        Ok((json.ok_or_else(|| invalid_data("missing glb json"))?, bin))
     }
 
-    #[inline(always)]
-    pub fn get_colors(vertex: &LinkedHashMap<String, Property>, colors: &mut Vec<Vec4>) {
-        if vertex.get("red").is_none()
-            || vertex.get("green").is_none()
-            || vertex.get("blue").is_none()
-        {
-            return;
+    // payload of a base64 data uri
+    fn decode_data_uri(uri: &str) -> Result<Vec<u8>> {
+        let (_, payload) = uri
+            .split_once(";base64,")
+            .ok_or_else(|| invalid_data("unsupported data uri"))?;
+        let mut bytes = Vec::with_capacity(payload.len() * 3 / 4);
+        let (mut bits, mut count) = (0u32, 0);
+        for c in payload.bytes().filter(|&c| c != b'=') {
+            let value = match c {
+                b'A'..=b'Z' => c - b'A',
+                b'a'..=b'z' => c - b'a' + 26,
+                b'0'..=b'9' => c - b'0' + 52,
+                b'+' | b'-' => 62,
+                b'/' | b'_' => 63,
+                _ => return Err(invalid_data("invalid base64")),
+            };
+            bits = bits << 6 | value as u32;
+            count += 6;
+            if count >= 8 {
+                count -= 8;
+                bytes.push((bits >> count) as u8);
+            }
+        }
+        Ok(bytes)
+    }
+
+    impl Gltf {
+        fn view<'a>(&self, buffers: &'a [Vec<u8>], index: usize) -> Result<&'a [u8]> {
+            let view = self
+                .buffer_views
+                .get(index)
+                .ok_or_else(|| invalid_data("invalid buffer view"))?;
+            buffers
+                .get(view.buffer)
+                .and_then(|buffer| {
+                    buffer.get(view.byte_offset..view.byte_offset + view.byte_length)
+                })
+                .ok_or_else(|| invalid_data("buffer view out of bounds"))
+        }
+
+        // components of an accessor of one of the given types, with normalized
+        // integers mapped to [0, 1] or [-1, 1]; f64 keeps 32-bit indices exact
+        fn read_accessor(
+            &self,
+            buffers: &[Vec<u8>],
+            index: usize,
+            types: &[&str],
+        ) -> Result<(Vec<f64>, usize)> {
+            let accessor = self
+                .accessors
+                .get(index)
+                .ok_or_else(|| invalid_data("invalid accessor"))?;
+            if !types.contains(&accessor.a_type.as_str()) {
+                return Err(invalid_data(format!(
+                    "accessor of type {} instead of {}",
+                    accessor.a_type,
+                    types.join(" or ")
+                )));
+            }
+            let components = match accessor.a_type.as_str() {
+                "SCALAR" => 1,
+                "VEC2" => 2,
+                "VEC3" => 3,
+                "VEC4" => 4,
+                a_type => return Err(invalid_data(format!("unsupported accessor {}", a_type))),
+            };
+            if accessor.sparse.is_some() {
+                return Err(invalid_data("unsupported sparse accessor"));
+            }
+            let size = match accessor.component_type {
+                5120 | 5121 => 1,
+                5122 | 5123 => 2,
+                5125 | 5126 => 4,
+                component => return Err(invalid_data(format!("invalid component {}", component))),
+            };
+            let length = accessor
+                .count
+                .checked_mul(components)
+                .ok_or_else(|| invalid_data("accessor out of bounds"))?;
+            // accessors without a view are zero
+            let Some(view_index) = accessor.buffer_view else {
+                return Ok((vec![0.0; length], components));
+            };
+            let data = self.view(buffers, view_index)?;
+            let stride = self.buffer_views[view_index]
+                .byte_stride
+                .unwrap_or(size * components);
+            // the last element ends within the view, so the count is bounded
+            // by the data before anything is allocated
+            if accessor.count > 0 {
+                let end = (accessor.count - 1)
+                    .checked_mul(stride)
+                    .and_then(|end| end.checked_add(accessor.byte_offset))
+                    .and_then(|end| end.checked_add(components * size));
+                if !matches!(end, Some(end) if end <= data.len()) {
+                    return Err(invalid_data("accessor out of bounds"));
+                }
+            }
+            let mut values = Vec::with_capacity(length);
+            for element in 0..accessor.count {
+                for component in 0..components {
+                    let offset = accessor.byte_offset + element * stride + component * size;
+                    let bytes = &data[offset..offset + size];
+                    let (value, range) = match accessor.component_type {
+                        5120 => (bytes[0] as i8 as f64, 127.0),
+                        5121 => (bytes[0] as f64, 255.0),
+                        5122 => (i16::from_le_bytes([bytes[0], bytes[1]]) as f64, 32767.0),
+                        5123 => (u16::from_le_bytes([bytes[0], bytes[1]]) as f64, 65535.0),
+                        5125 => (u32::from_le_bytes(bytes.try_into().unwrap()) as f64, 1.0),
+                        _ => (f32::from_le_bytes(bytes.try_into().unwrap()) as f64, 1.0),
+                    };
+                    values.push(if accessor.normalized {
+                        f64::max(value / range, -1.0)
+                    } else {
+                        value
+                    });
+                }
+            }
+            Ok((values, components))
+        }
+
+        fn read_vec3s(&self, buffers: &[Vec<u8>], index: usize) -> Result<Vec<Vec3>> {
+            let (values, components) = self.read_accessor(buffers, index, &["VEC3"])?;
+            Ok(values
+                .chunks(components)
+                .map(|v| vec3(v[0] as f32, v[1] as f32, v[2] as f32))
+                .collect())
+        }
+
+        fn read_vec4s(
+            &self,
+            buffers: &[Vec<u8>],
+            index: usize,
+            types: &[&str],
+        ) -> Result<Vec<Vec4>> {
+            let (values, components) = self.read_accessor(buffers, index, types)?;
+            Ok(values
+                .chunks(components)
+                .map(|v| {
+                    // rgb colors are opaque
+                    let w = if components == 4 { v[3] as f32 } else { 1.0 };
+                    vec4(v[0] as f32, v[1] as f32, v[2] as f32, w)
+                })
+                .collect())
+        }
+
+        fn read_shape(&self, buffers: &[Vec<u8>], primitive: &GltfPrimitive) -> Result<Shape> {
+            let attribute = |name: &str| primitive.attributes.get(name).copied();
+            let mut shape = Shape::default();
+            let positions =
+                attribute("POSITION").ok_or_else(|| invalid_data("missing positions"))?;
+            shape.positions = self.read_vec3s(buffers, positions)?;
+            if let Some(normals) = attribute("NORMAL") {
+                shape.normals = self.read_vec3s(buffers, normals)?;
+            }
+            if let Some(texcoords) = attribute("TEXCOORD_0") {
+                // glTF texcoords already start from the top of the image
+                let (values, _) = self.read_accessor(buffers, texcoords, &["VEC2"])?;
+                shape.texcoords = values
+                    .chunks(2)
+                    .map(|v| Vec2::new(v[0] as f32, v[1] as f32))
+                    .collect();
+            }
+            if let Some(colors) = attribute("COLOR_0") {
+                shape.colors = self.read_vec4s(buffers, colors, &["VEC3", "VEC4"])?;
+            }
+            if let Some(tangents) = attribute("TANGENT") {
+                shape.tangents = self.read_vec4s(buffers, tangents, &["VEC4"])?;
+            }
+
+            let indices: Vec<i32> = match primitive.indices {
+                Some(indices) => {
+                    let (values, _) = self.read_accessor(buffers, indices, &["SCALAR"])?;
+                    values.iter().map(|&index| index as i32).collect()
+                }
+                None => (0..shape.positions.len() as i32).collect(),
+            };
+            if indices
+                .iter()
+                .any(|&index| index as usize >= shape.positions.len())
+            {
+                return Err(invalid_data("index out of bounds"));
+            }
+            let count = indices.len();
+            match primitive.mode {
+                0 => shape.points = indices,
+                1 => {
+                    shape.lines = (indices.chunks_exact(2))
+                        .map(|l| TVec2::new(l[0], l[1]))
+                        .collect()
+                }
+                // line loops close back on the first vertex
+                2 | 3 => {
+                    shape.lines = (1..count)
+                        .map(|i| TVec2::new(indices[i - 1], indices[i]))
+                        .collect();
+                    if primitive.mode == 2 && count > 2 {
+                        shape.lines.push(TVec2::new(indices[count - 1], indices[0]));
+                    }
+                }
+                4 => {
+                    shape.triangles = (indices.chunks_exact(3))
+                        .map(|t| TVec3::new(t[0], t[1], t[2]))
+                        .collect()
+                }
+                // strips alternate winding to keep a consistent orientation
+                5 => {
+                    shape.triangles = (2..count)
+                        .map(|i| {
+                            if i % 2 == 0 {
+                                TVec3::new(indices[i - 2], indices[i - 1], indices[i])
+                            } else {
+                                TVec3::new(indices[i - 1], indices[i - 2], indices[i])
+                            }
+                        })
+                        .collect()
+                }
+                6 => {
+                    shape.triangles = (2..count)
+                        .map(|i| TVec3::new(indices[0], indices[i - 1], indices[i]))
+                        .collect()
+                }
+                mode => return Err(invalid_data(format!("invalid primitive mode {}", mode))),
+            }
+            Ok(shape)
+        }
+
+        // textures keep the uri of external images, which are loaded with the
+        // rest of the scene, while embedded ones are decoded here
+        fn read_texture(&self, buffers: &[Vec<u8>], texture: &GltfTexture) -> Result<Texture> {
+            let image = texture
+                .source
+                .and_then(|source| self.images.get(source))
+                .ok_or_else(|| invalid_data("texture without image"))?;
+            let wrap = texture
+                .sampler
+                .and_then(|sampler| self.samplers.get(sampler))
+                .map_or(WrapMode::Repeat, |sampler| sampler.wrap_mode());
+            let mut result = Texture {
+                wrap,
+                ..Default::default()
+            };
+            if let Some(view) = image.buffer_view {
+                super::texture::read_image_from_memory(self.view(buffers, view)?, &mut result)?;
+                result.build_mips();
+            } else if image.uri.starts_with("data:") {
+                let data = decode_data_uri(&image.uri)?;
+                super::texture::read_image_from_memory(&data, &mut result)?;
+                result.build_mips();
+            } else {
+                result.uri = image.uri.replace("%20", " ");
+            }
+            Ok(result)
+        }
+    }
+
+    fn read_material(material: &GltfMaterial) -> Material {
+        let pbr = &material.pbr_metallic_roughness;
+        let extensions = &material.extensions;
+        let texture =
+            |info: &Option<GltfTextureInfo>| info.as_ref().map_or(INVALID, |info| info.index);
+        let [r, g, b, a] = pbr.base_color_factor;
+        let strength = extensions
+            .emissive_strength
+            .as_ref()
+            .map_or(1.0, |extension| extension.emissive_strength);
+        let mut result = Material {
+            m_type: MaterialType::Gltfpbr,
+            color: vec3(r, g, b),
+            opacity: if material.alpha_mode == "OPAQUE" {
+                1.0
+            } else {
+                a
+            },
+            metallic: pbr.metallic_factor,
+            roughness: pbr.roughness_factor,
+            emission: glm::make_vec3(&material.emissive_factor) * strength,
+            ior: extensions
+                .ior
+                .as_ref()
+                .map_or(1.5, |extension| extension.ior),
+            color_tex: texture(&pbr.base_color_texture),
+            roughness_tex: texture(&pbr.metallic_roughness_texture),
+            emission_tex: texture(&material.emissive_texture),
+            normal_tex: texture(&material.normal_texture),
+            ..Default::default()
+        };
+        // transmission and clearcoat need the layers of the principled model
+        if let Some(transmission) = &extensions.transmission {
+            result.m_type = MaterialType::Principled;
+            result.transmission = transmission.transmission_factor;
+            result.transmission_tex = texture(&transmission.transmission_texture);
+        }
+        if let Some(clearcoat) = &extensions.clearcoat {
+            result.m_type = MaterialType::Principled;
+            result.clearcoat = clearcoat.clearcoat_factor;
+            result.clearcoat_roughness = clearcoat.clearcoat_roughness_factor;
+            result.clearcoat_tex = texture(&clearcoat.clearcoat_texture);
+            result.clearcoat_roughness_tex = texture(&clearcoat.clearcoat_roughness_texture);
+        }
+        result
+    }
+
+    // film of the default camera, with the lens set to match the field of view
+    fn read_camera(camera: &GltfCamera, frame: Mat3x4) -> Camera {
+        let mut result = Camera {
+            frame,
+            ..Default::default()
+        };
+        if let Some(orthographic) = &camera.orthographic {
+            result.orthographic = true;
+            result.aspect = orthographic.xmag / orthographic.ymag;
+            let film = f32::min(result.film, result.film / result.aspect);
+            result.lens = film / (2.0 * orthographic.ymag);
+        } else if let Some(perspective) = &camera.perspective {
+            result.aspect = perspective.aspect_ratio.unwrap_or(result.aspect);
+            let film = f32::min(result.film, result.film / result.aspect);
+            result.lens = film / (2.0 * f32::tan(perspective.yfov / 2.0));
         }
-        let red = match vertex["red"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        result
+    }
+
+    pub fn read_gltf(path: &Path, scene: &mut Scene) -> Result<()> {
+        let data = std::fs::read(path)?;
+        let (json, bin) = if data.starts_with(b"glTF") {
+            split_glb(&data)?
+        } else {
+            (&data[..], None)
         };
-        let green = match vertex["green"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        let gltf: Gltf = serde_json::from_slice(json).map_err(invalid_data)?;
+
+        // buffers without uri are the binary chunk of a glb
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        let buffers = gltf
+            .buffers
+            .iter()
+            .map(|buffer| {
+                if buffer.uri.is_empty() {
+                    bin.map(|bin| bin.to_vec())
+                        .ok_or_else(|| invalid_data("missing glb buffer"))
+                } else if buffer.uri.starts_with("data:") {
+                    decode_data_uri(&buffer.uri)
+                } else {
+                    std::fs::read(directory.join(buffer.uri.replace("%20", " ")))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // textures and materials keep their glTF indices, primitives without
+        // a material use the glTF default one, added at the end when needed
+        for texture in &gltf.textures {
+            scene.textures.push(gltf.read_texture(&buffers, texture)?);
+        }
+        for material in &gltf.materials {
+            let material = read_material(material);
+            for texture in [
+                material.color_tex,
+                material.roughness_tex,
+                material.emission_tex,
+                material.normal_tex,
+                material.transmission_tex,
+                material.clearcoat_tex,
+                material.clearcoat_roughness_tex,
+            ] {
+                if texture != INVALID && texture >= scene.textures.len() {
+                    return Err(invalid_data("invalid texture"));
+                }
+            }
+            scene.materials.push(material);
+        }
+        let mut default_material = INVALID;
+        let mut meshes = Vec::new();
+        for mesh in &gltf.meshes {
+            let mut primitives = Vec::new();
+            for primitive in &mesh.primitives {
+                let material = match primitive.material {
+                    Some(material) if material < gltf.materials.len() => material,
+                    Some(_) => return Err(invalid_data("invalid material")),
+                    None => {
+                        if default_material == INVALID {
+                            scene
+                                .materials
+                                .push(read_material(&GltfMaterial::default()));
+                            default_material = scene.materials.len() - 1;
+                        }
+                        default_material
+                    }
+                };
+                scene.shapes.push(gltf.read_shape(&buffers, primitive)?);
+                primitives.push((scene.shapes.len() - 1, material));
+            }
+            meshes.push(primitives);
+        }
+
+        // walk the node hierarchy from the roots of the default scene, or
+        // from all the nodes that are nobody's children without scenes
+        let roots = match gltf.scenes.get(gltf.scene.unwrap_or(0)) {
+            Some(gltf_scene) => gltf_scene.nodes.clone(),
+            None => (0..gltf.nodes.len())
+                .filter(|&node| {
+                    gltf.nodes
+                        .iter()
+                        .all(|parent| !parent.children.contains(&node))
+                })
+                .collect(),
         };
-        let blue = match vertex["blue"] {
-            Property::Float(c) => c,
-            _ => unreachable!(),
+        let mut stack: Vec<(usize, Mat4)> = roots
+            .into_iter()
+            .rev()
+            .map(|node| (node, Mat4::identity()))
+            .collect();
+        let mut visited = vec![false; gltf.nodes.len()];
+        while let Some((index, parent)) = stack.pop() {
+            let node = gltf
+                .nodes
+                .get(index)
+                .ok_or_else(|| invalid_data("invalid node"))?;
+            if std::mem::replace(&mut visited[index], true) {
+                return Err(invalid_data("node hierarchy is not a tree"));
+            }
+            let transform = parent * node.transform();
+            let frame = Mat3x4::from_fn(|i, j| transform[(i, j)]);
+            if let Some(mesh) = node.mesh {
+                let primitives = meshes
+                    .get(mesh)
+                    .ok_or_else(|| invalid_data("invalid mesh"))?;
+                for &(shape, material) in primitives {
+                    scene.instances.push(Instance {
+                        frame,
+                        shape,
+                        material,
+                    });
+                }
+            }
+            if let Some(camera) = node.camera {
+                let camera = gltf
+                    .cameras
+                    .get(camera)
+                    .ok_or_else(|| invalid_data("invalid camera"))?;
+                scene.cameras.push(read_camera(camera, frame));
+            }
+            stack.extend(node.children.iter().rev().map(|&child| (child, transform)));
+        }
+        if scene.cameras.is_empty() {
+            scene.cameras.push(scene.framing_camera());
+        }
+        Ok(())
+    }
+}
+
+pub mod obj {
+    use crate::scene::Scene;
+    use crate::scene_components::*;
+    use crate::shading::eta_to_reflectivity;
+    use glm::{vec2, vec3, vec4, Vec2, Vec3};
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+    use std::path::Path;
+    const INVALID: usize = usize::MAX;
+
+    fn invalid_data<E: std::fmt::Display>(error: E) -> Error {
+        Error::new(ErrorKind::InvalidData, error.to_string())
+    }
+
+    // position, texcoord and normal indices of a face vertex, INVALID when
+    // the vertex does not reference one
+    type ObjVertex = (usize, usize, usize);
+
+    // elements between changes of object or material
+    #[derive(Default)]
+    struct ObjGroup {
+        material: String,
+        faces: Vec<Vec<ObjVertex>>,
+        lines: Vec<[ObjVertex; 2]>,
+        points: Vec<ObjVertex>,
+    }
+
+    #[derive(Default)]
+    struct Obj {
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        texcoords: Vec<Vec2>,
+        groups: Vec<ObjGroup>,
+        libraries: Vec<String>,
+    }
+
+    fn parse_floats<const N: usize>(tokens: &[&str]) -> Result<[f32; N]> {
+        let mut values = [0.0; N];
+        for (value, token) in values.iter_mut().zip(tokens) {
+            *value = token.parse().map_err(invalid_data)?;
+        }
+        Ok(values)
+    }
+
+    // indices are one based, negative ones count back from the last element
+    fn parse_index(token: &str, count: usize) -> Result<usize> {
+        if token.is_empty() {
+            return Ok(INVALID);
+        }
+        let index: i64 = token.parse().map_err(invalid_data)?;
+        let index = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
         };
-        if vertex.get("alpha").is_some() {
-            let alpha = match vertex["alpha"] {
-                Property::Float(c) => c,
-                _ => unreachable!(),
+        if index < 0 || index >= count as i64 {
+            return Err(invalid_data(format!("index {} out of bounds", token)));
+        }
+        Ok(index as usize)
+    }
+
+    fn parse_vertex(token: &str, obj: &Obj) -> Result<ObjVertex> {
+        let mut indices = token.split('/');
+        let position = parse_index(indices.next().unwrap_or(""), obj.positions.len())?;
+        let texcoord = parse_index(indices.next().unwrap_or(""), obj.texcoords.len())?;
+        let normal = parse_index(indices.next().unwrap_or(""), obj.normals.len())?;
+        if position == INVALID {
+            return Err(invalid_data(format!("vertex {} without position", token)));
+        }
+        Ok((position, texcoord, normal))
+    }
+
+    fn read_obj(path: &Path) -> Result<Obj> {
+        let mut obj = Obj::default();
+        let mut group = ObjGroup::default();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let Some((&command, arguments)) = tokens.split_first() else {
+                continue;
             };
-            colors.push(vec4(red, green, blue, alpha));
-        } else {
-            colors.push(vec4(red, green, blue, 1.0));
+            match command {
+                "v" => obj.positions.push(parse_floats::<3>(arguments)?.into()),
+                "vn" => obj.normals.push(parse_floats::<3>(arguments)?.into()),
+                // flipping
+                "vt" => {
+                    let [u, v] = parse_floats::<2>(arguments)?;
+                    obj.texcoords.push(vec2(u, 1.0 - v));
+                }
+                "f" | "l" | "p" => {
+                    let vertices = arguments
+                        .iter()
+                        .map(|token| parse_vertex(token, &obj))
+                        .collect::<Result<Vec<_>>>()?;
+                    match command {
+                        "f" if vertices.len() >= 3 => group.faces.push(vertices),
+                        "l" => {
+                            let segments = vertices.windows(2).map(|v| [v[0], v[1]]);
+                            group.lines.extend(segments);
+                        }
+                        "p" => group.points.extend(vertices),
+                        _ => return Err(invalid_data("face with less than three vertices")),
+                    }
+                }
+                "o" | "g" | "usemtl" => {
+                    let material = if command == "usemtl" {
+                        arguments.join(" ")
+                    } else {
+                        group.material.clone()
+                    };
+                    let next = ObjGroup {
+                        material,
+                        ..Default::default()
+                    };
+                    obj.groups.push(std::mem::replace(&mut group, next));
+                }
+                "mtllib" => obj.libraries.push(arguments.join(" ")),
+                _ => {}
+            }
         }
+        obj.groups.push(group);
+        obj.groups.retain(|group| {
+            !group.faces.is_empty() || !group.lines.is_empty() || !group.points.is_empty()
+        });
+        Ok(obj)
     }
 
-    #[inline(always)]
-    pub fn get_faces(face: &LinkedHashMap<String, Property>, shape: &mut Shape) {
-        if face.get("vertex_indices").is_none() {
-            return;
-        }
-        let vertex_indices = match &face["vertex_indices"] {
-            Property::ListInt(c) => c,
-            _ => unreachable!(),
-        };
-        if vertex_indices.len() == 3 {
-            shape.triangles.push(vec3(
-                vertex_indices[0],
-                vertex_indices[1],
-                vertex_indices[2],
-            ));
-        } else if vertex_indices.len() == 4 {
-            shape.quads.push(vec4(
-                vertex_indices[0],
-                vertex_indices[1],
-                vertex_indices[2],
-                vertex_indices[3],
-            ));
+    // shape with the vertices of the elements welded over their index
+    // triples, with the indices of the elements; attributes only some of the
+    // vertices have are dropped
+    fn weld_elements(obj: &Obj, elements: &[&[ObjVertex]]) -> (Shape, Vec<Vec<i32>>) {
+        let vertices = || elements.iter().flat_map(|element| element.iter());
+        let has_texcoords = vertices().all(|vertex| vertex.1 != INVALID);
+        let has_normals = vertices().all(|vertex| vertex.2 != INVALID);
+
+        let mut shape = Shape::default();
+        let mut welded = HashMap::new();
+        let mut weld = |vertex: &ObjVertex| -> i32 {
+            let texcoord = if has_texcoords { vertex.1 } else { INVALID };
+            let normal = if has_normals { vertex.2 } else { INVALID };
+            *welded
+                .entry((vertex.0, texcoord, normal))
+                .or_insert_with(|| {
+                    shape.positions.push(obj.positions[vertex.0]);
+                    if has_texcoords {
+                        shape.texcoords.push(obj.texcoords[texcoord]);
+                    }
+                    if has_normals {
+                        shape.normals.push(obj.normals[normal]);
+                    }
+                    shape.positions.len() as i32 - 1
+                })
+        };
+        let indices = elements
+            .iter()
+            .map(|element| element.iter().map(&mut weld).collect())
+            .collect();
+        (shape, indices)
+    }
+
+    // shapes hold a single kind of element, so the faces, lines and points
+    // of the groups each make a shape of their own, when there are any;
+    // faces keep quads when there are any, with triangles stored as quads
+    // repeating their last vertex, and larger polygons are split in fans
+    fn make_shapes(obj: &Obj, groups: &[&ObjGroup]) -> Vec<Shape> {
+        let faces: Vec<&[ObjVertex]> = groups
+            .iter()
+            .flat_map(|group| &group.faces)
+            .map(|face| face.as_slice())
+            .collect();
+        let lines: Vec<&[ObjVertex]> = groups
+            .iter()
+            .flat_map(|group| &group.lines)
+            .map(|line| line.as_slice())
+            .collect();
+        let points: Vec<&[ObjVertex]> = groups
+            .iter()
+            .flat_map(|group| &group.points)
+            .map(std::slice::from_ref)
+            .collect();
+
+        let mut shapes = vec![];
+        if !faces.is_empty() {
+            let (mut shape, faces) = weld_elements(obj, &faces);
+            let keep_quads = faces.iter().any(|face| face.len() == 4);
+            for face in faces {
+                if keep_quads && face.len() == 4 {
+                    shape.quads.push(vec4(face[0], face[1], face[2], face[3]));
+                    continue;
+                }
+                for i in 2..face.len() {
+                    if keep_quads {
+                        shape
+                            .quads
+                            .push(vec4(face[0], face[i - 1], face[i], face[i]));
+                    } else {
+                        shape.triangles.push(vec3(face[0], face[i - 1], face[i]));
+                    }
+                }
+            }
+            shapes.push(shape);
+        }
+        if !lines.is_empty() {
+            let (mut shape, lines) = weld_elements(obj, &lines);
+            shape.lines = lines.iter().map(|line| vec2(line[0], line[1])).collect();
+            shapes.push(shape);
+        }
+        if !points.is_empty() {
+            let (mut shape, points) = weld_elements(obj, &points);
+            shape.points = points.iter().map(|point| point[0]).collect();
+            shapes.push(shape);
         }
+        shapes
     }
 
-    #[inline(always)]
-    pub fn get_points(point: &LinkedHashMap<String, Property>, points: &mut Vec<i32>) {
-        if point.get("vertex_indices").is_none() {
-            return;
+    // all the elements of the file as a single shape, materials are ignored;
+    // files mixing faces, lines and points are only read as scenes
+    pub fn read_shape(path: &Path, shape: &mut Shape) -> Result<()> {
+        let obj = read_obj(path)?;
+        let groups: Vec<&ObjGroup> = obj.groups.iter().collect();
+        let mut shapes = make_shapes(&obj, &groups);
+        if shapes.len() > 1 {
+            return Err(invalid_data(
+                "obj shapes hold a single kind of element, faces, lines or points",
+            ));
         }
-        let vertex_indices = match point["vertex_indices"] {
-            Property::Int(c) => c,
-            _ => unreachable!(),
+        *shape = Shape {
+            uri: std::mem::take(&mut shape.uri),
+            ..shapes.pop().unwrap_or_default()
         };
-        points.push(vertex_indices);
+        Ok(())
     }
 
-    #[inline(always)]
-    pub fn get_lines(line: &LinkedHashMap<String, Property>, lines: &mut Vec<TVec2<i32>>) {
-        if line.get("vertex_indices").is_none() {
-            return;
+    struct MtlMaterial {
+        name: String,
+        diffuse: Vec3,
+        specular: Vec3,
+        emission: Vec3,
+        exponent: f32,
+        ior: f32,
+        opacity: f32,
+        diffuse_map: String,
+        specular_map: String,
+        emission_map: String,
+        normal_map: String,
+        bump_map: String,
+        bump_strength: f32,
+    }
+
+    impl Default for MtlMaterial {
+        fn default() -> Self {
+            MtlMaterial {
+                name: String::new(),
+                diffuse: vec3(0.8, 0.8, 0.8),
+                specular: vec3(0.0, 0.0, 0.0),
+                emission: vec3(0.0, 0.0, 0.0),
+                exponent: 10.0,
+                ior: 1.5,
+                opacity: 1.0,
+                diffuse_map: String::new(),
+                specular_map: String::new(),
+                emission_map: String::new(),
+                normal_map: String::new(),
+                bump_map: String::new(),
+                bump_strength: 1.0,
+            }
         }
-        let vertex_indices = match &line["vertex_indices"] {
-            Property::ListInt(c) => c,
-            _ => unreachable!(),
+    }
+
+    // texture file of a map statement, the last argument after the options,
+    // and the bump multiplier when given
+    fn parse_map(arguments: &[&str]) -> (String, Option<f32>) {
+        let strength = arguments
+            .iter()
+            .position(|&token| token == "-bm")
+            .and_then(|i| arguments.get(i + 1))
+            .and_then(|token| token.parse().ok());
+        let file = arguments.last().copied().unwrap_or_default();
+        (file.to_string(), strength)
+    }
+
+    fn read_mtl(path: &Path) -> Result<Vec<MtlMaterial>> {
+        let mut materials: Vec<MtlMaterial> = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let Some((&command, arguments)) = tokens.split_first() else {
+                continue;
+            };
+            if command == "newmtl" {
+                materials.push(MtlMaterial {
+                    name: arguments.join(" "),
+                    ..Default::default()
+                });
+                continue;
+            }
+            let material = materials
+                .last_mut()
+                .ok_or_else(|| invalid_data(format!("{} before newmtl", command)))?;
+            let color = || parse_floats::<3>(arguments).map(Vec3::from);
+            let value = || parse_floats::<1>(arguments).map(|[value]| value);
+            match command {
+                "Kd" => material.diffuse = color()?,
+                "Ks" => material.specular = color()?,
+                "Ke" => material.emission = color()?,
+                "Ns" => material.exponent = value()?,
+                "Ni" => material.ior = value()?,
+                "d" => material.opacity = value()?,
+                "Tr" => material.opacity = 1.0 - value()?,
+                "map_Kd" => material.diffuse_map = parse_map(arguments).0,
+                "map_Ks" => material.specular_map = parse_map(arguments).0,
+                "map_Ke" => material.emission_map = parse_map(arguments).0,
+                "norm" | "map_Kn" => material.normal_map = parse_map(arguments).0,
+                "bump" | "map_bump" | "map_Bump" => {
+                    let (file, strength) = parse_map(arguments);
+                    material.bump_map = file;
+                    material.bump_strength = strength.unwrap_or(1.0);
+                }
+                _ => {}
+            }
+        }
+        Ok(materials)
+    }
+
+    // phong exponent to the roughness of a lobe with a similar highlight
+    fn exponent_to_roughness(exponent: f32) -> f32 {
+        let roughness = f32::powf(2.0 / (exponent + 2.0), 0.25);
+        if roughness < 0.01 {
+            0.0
+        } else if roughness > 0.99 {
+            1.0
+        } else {
+            roughness
+        }
+    }
+
+    // specular over a diffuse color is taken as plastic and specular without
+    // a diffuse color as metal; for plastics ks is read as in blender, where
+    // 0.5 is the 4% reflectance of common dielectrics, so the reflectance at
+    // normal incidence is 0.08 ks, and the principled specular scales the
+    // reflectance of the index of refraction to match it
+    fn make_material(mtl: &MtlMaterial, mut texture: impl FnMut(&str) -> usize) -> Material {
+        let specular = mtl.specular.max();
+        let (m_type, color, color_map) = if specular > 0.0 && mtl.diffuse.max() == 0.0 {
+            (MaterialType::Reflective, mtl.specular, &mtl.specular_map)
+        } else if specular > 0.0 {
+            (MaterialType::Principled, mtl.diffuse, &mtl.diffuse_map)
+        } else {
+            (MaterialType::Matte, mtl.diffuse, &mtl.diffuse_map)
+        };
+        let plastic = m_type == MaterialType::Principled;
+        let reflectivity = eta_to_reflectivity(&vec3(mtl.ior, mtl.ior, mtl.ior)).x;
+        let specular = if reflectivity > 0.0 {
+            0.08 * specular / reflectivity
+        } else {
+            0.0
         };
-        lines.push(vec2(vertex_indices[0], vertex_indices[1]))
+        Material {
+            m_type,
+            color,
+            emission: mtl.emission,
+            roughness: exponent_to_roughness(mtl.exponent),
+            ior: mtl.ior,
+            opacity: mtl.opacity,
+            specular: if plastic { specular } else { 1.0 },
+            color_tex: texture(color_map),
+            specular_tex: if plastic {
+                texture(&mtl.specular_map)
+            } else {
+                INVALID
+            },
+            emission_tex: texture(&mtl.emission_map),
+            normal_tex: texture(&mtl.normal_map),
+            bump_tex: texture(&mtl.bump_map),
+            bump_strength: mtl.bump_strength,
+            ..Default::default()
+        }
+    }
+
+    // one shape and instance for each object and material, with textures
+    // named relative to the obj file
+    pub fn read_obj_scene(path: &Path, scene: &mut Scene) -> Result<()> {
+        let obj = read_obj(path)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut materials = HashMap::new();
+        let mut textures = HashMap::new();
+        for library in &obj.libraries {
+            let library = Path::new(library);
+            let library_directory = library.parent().unwrap_or_else(|| Path::new(""));
+            // materials of missing libraries fall back to the default
+            let mtls = match read_mtl(&directory.join(library)) {
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                mtls => mtls?,
+            };
+            for mtl in mtls {
+                let texture = |file: &str| {
+                    if file.is_empty() {
+                        return INVALID;
+                    }
+                    let uri = library_directory.join(file).to_string_lossy().into_owned();
+                    *textures.entry(uri.clone()).or_insert_with(|| {
+                        scene.textures.push(Texture {
+                            uri,
+                            ..Default::default()
+                        });
+                        scene.textures.len() - 1
+                    })
+                };
+                let material = make_material(&mtl, texture);
+                scene.materials.push(material);
+                materials.insert(mtl.name, scene.materials.len() - 1);
+            }
+        }
+
+        // unknown materials fall back to the mtl default
+        let mut default_material = INVALID;
+        for group in &obj.groups {
+            let material = match materials.get(&group.material) {
+                Some(&material) => material,
+                None => {
+                    if default_material == INVALID {
+                        let material = make_material(&MtlMaterial::default(), |_| INVALID);
+                        scene.materials.push(material);
+                        default_material = scene.materials.len() - 1;
+                    }
+                    default_material
+                }
+            };
+            for shape in make_shapes(&obj, &[group]) {
+                scene.shapes.push(shape);
+                scene.instances.push(Instance {
+                    shape: scene.shapes.len() - 1,
+                    material,
+                    ..Default::default()
+                });
+            }
+        }
+        if scene.cameras.is_empty() {
+            scene.cameras.push(scene.framing_camera());
+        }
+        Ok(())
     }
 }
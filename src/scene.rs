@@ -1,14 +1,17 @@
 use crate::bvh::{BvhData, BvhIntersection};
 use crate::scene_components::*;
 use crate::shading::*;
+use crate::texture_cache::{CacheOptions, LazyTexture, TextureCache};
 use crate::trace::Ray;
 use crate::utils::*;
 use crate::*;
 use glm::{
-    clamp, cross, dot, epsilon, is_null, log, make_mat3x4, mat3x4, normalize, vec2, vec3, vec4,
+    clamp, cross, dot, epsilon, is_null, lerp, log, make_mat3x4, mat3x4, normalize, vec2, vec3,
+    vec4,
 };
 use glm::{Vec2, Vec3, Vec4};
-use ply_rs as ply;
+use parking_lot::Mutex;
+use rand::prelude::SmallRng;
 use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
 use serde::Deserialize;
 use std::collections::VecDeque;
@@ -16,6 +19,7 @@ use std::f32::consts::PI;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::sync::Arc;
 
 const INVALID: usize = usize::MAX;
 const MIN_ROUGHNESS: f32 = 0.03 * 0.03;
@@ -29,19 +33,32 @@ pub struct Scene {
     pub shapes: Vec<Shape>,
     pub textures: Vec<Texture>,
     pub materials: Vec<Material>,
+    pub volumes: Vec<Volume>,
+    pub brdfs: Vec<Arc<MeasuredBrdf>>,
     pub subdivs: Vec<Subdiv>,
     #[serde(skip)]
     pub lights: Vec<Light>,
+    #[serde(skip)]
+    pub texture_cache: Option<Arc<TextureCache>>,
 }
 
 impl Scene {
     pub fn eval_shading_position(&self, intersection: &BvhIntersection) -> Vec3 {
         let instance = &self.instances[intersection.instance];
         let shape = &self.shapes[instance.shape];
-        if !shape.triangles.is_empty() || !shape.quads.is_empty() {
+        if shape.primitive.is_some() || !shape.triangles.is_empty() || !shape.quads.is_empty() {
             self.eval_position(instance, intersection.element, &intersection.uv)
         } else if !shape.lines.is_empty() {
-            self.eval_position(instance, intersection.element, &intersection.uv)
+            if shape.curve != CurveType::Flat && !is_null(&intersection.normal, epsilon()) {
+                // round curves are hit on their surface, away from the center
+                let (center, _, radius) = shape.eval_curve(intersection.element, intersection.uv.x);
+                transform_point(
+                    &instance.frame,
+                    &(center + normalize(&intersection.normal) * radius),
+                )
+            } else {
+                self.eval_position(instance, intersection.element, &intersection.uv)
+            }
         } else if !shape.points.is_empty() {
             shape.eval_position(intersection.element, &intersection.uv)
         } else {
@@ -51,7 +68,9 @@ impl Scene {
 
     fn eval_position(&self, instance: &Instance, element: usize, uv: &Vec2) -> Vec3 {
         let shape = &self.shapes[instance.shape];
-        if !shape.triangles.is_empty() {
+        if let Some(primitive) = &shape.primitive {
+            transform_point(&instance.frame, &primitive.eval_position(uv))
+        } else if !shape.triangles.is_empty() {
             let triangle = &shape.triangles[element];
             transform_point(
                 &instance.frame,
@@ -75,15 +94,7 @@ impl Scene {
                 ),
             )
         } else if !shape.lines.is_empty() {
-            let line = &shape.lines[element];
-            transform_point(
-                &instance.frame,
-                &interpolate_line(
-                    &shape.positions[line.x as usize],
-                    &shape.positions[line.y as usize],
-                    uv.x,
-                ),
-            )
+            transform_point(&instance.frame, &shape.eval_curve(element, uv.x).0)
         } else if !shape.points.is_empty() {
             let point = shape.points[element];
             transform_point(&instance.frame, &shape.positions[point as usize])
@@ -92,25 +103,36 @@ impl Scene {
         }
     }
 
-    pub fn eval_shading_normal(&self, intersection: &BvhIntersection, outgoing: &Vec3) -> Vec3 {
+    pub fn eval_shading_normal(
+        &self,
+        intersection: &BvhIntersection,
+        outgoing: &Vec3,
+        point: &MaterialPoint,
+    ) -> Vec3 {
         let instance = &self.instances[intersection.instance];
         let shape = &self.shapes[instance.shape];
         let material = &self.materials[instance.material];
         let uv = &intersection.uv;
-        if !shape.triangles.is_empty() || !shape.quads.is_empty() {
-            let normal = if material.normal_tex == INVALID {
+        if shape.primitive.is_some() || !shape.triangles.is_empty() || !shape.quads.is_empty() {
+            let normal = if material.normal_tex == INVALID && material.bump_tex == INVALID {
                 self.eval_normal(instance, intersection.element, uv)
             } else {
-                self.eval_normalmap(instance, intersection.element, uv)
+                self.eval_normalmap(intersection)
             };
-            if dot(&normal, outgoing) >= 0.0 || material.m_type == MaterialType::Refractive {
+            // transmissive surfaces keep their orientation to tell entering
+            // from leaving, with the same test the integrator uses for media
+            if dot(&normal, outgoing) >= 0.0 || is_volumetric(point) {
                 normal
             } else {
                 -normal
             }
         } else if !shape.lines.is_empty() {
-            let normal = self.eval_normal(instance, intersection.element, uv);
-            orthonormalize(outgoing, &normal)
+            if shape.curve != CurveType::Flat && !is_null(&intersection.normal, epsilon()) {
+                transform_normal_frame(&instance.frame, &normalize(&intersection.normal), false)
+            } else {
+                let normal = self.eval_normal(instance, intersection.element, uv);
+                orthonormalize(outgoing, &normal)
+            }
         } else if !shape.points.is_empty() {
             // HACK: sphere
             if true {
@@ -132,6 +154,9 @@ impl Scene {
 
     fn eval_normal(&self, instance: &Instance, element: usize, uv: &Vec2) -> Vec3 {
         let shape = &self.shapes[instance.shape];
+        if let Some(primitive) = &shape.primitive {
+            return transform_normal_frame(&instance.frame, &primitive.eval_normal(uv), false);
+        }
         if shape.normals.is_empty() {
             return shape.eval_normal(instance, element);
         }
@@ -265,39 +290,246 @@ impl Scene {
         }
     }
 
-    fn eval_normalmap(&self, instance: &Instance, element: usize, uv: &Vec2) -> Vec3 {
+    // vertex tangents interpolated over the element, in world space with the
+    // handedness of the bitangent in w
+    fn eval_vertex_tangent(&self, instance: &Instance, element: usize, uv: &Vec2) -> Option<Vec4> {
+        let shape = &self.shapes[instance.shape];
+        if shape.tangents.is_empty() {
+            return None;
+        }
+        let tangent = if !shape.triangles.is_empty() {
+            let t = shape.triangles[element];
+            interpolate_triangle(
+                &shape.tangents[t.x as usize],
+                &shape.tangents[t.y as usize],
+                &shape.tangents[t.z as usize],
+                uv,
+            )
+        } else if !shape.quads.is_empty() {
+            let q = shape.quads[element];
+            interpolate_quad(
+                &shape.tangents[q.x as usize],
+                &shape.tangents[q.y as usize],
+                &shape.tangents[q.z as usize],
+                &shape.tangents[q.w as usize],
+                uv,
+            )
+        } else {
+            return None;
+        };
+        let direction = transform_direction_frame(&instance.frame, &tangent.xyz());
+        Some(vec4(direction.x, direction.y, direction.z, tangent.w))
+    }
+
+    // tangent used to orient anisotropic lobes, taken from the vertex tangents
+    // when present and from the uv parametrization otherwise, then rotated
+    // around the normal by a fraction of a full turn
+    fn eval_shading_tangent(
+        &self,
+        instance: &Instance,
+        element: usize,
+        uv: &Vec2,
+        rotation: f32,
+    ) -> Vec3 {
+        let shape = &self.shapes[instance.shape];
+        let tangent = if let Some(primitive) = &shape.primitive {
+            transform_direction_frame(&instance.frame, &primitive.eval_tangent(uv))
+        } else if let Some(tangent) = self.eval_vertex_tangent(instance, element, uv) {
+            tangent.xyz()
+        } else if !shape.lines.is_empty() {
+            transform_direction_frame(&instance.frame, &shape.eval_curve(element, uv.x).1)
+        } else {
+            self.eval_element_tangents(instance, element).0
+        };
+        if is_null(&tangent, epsilon()) || rotation == 0.0 {
+            return tangent;
+        }
+        let normal = self.eval_normal(instance, element, uv);
+        let tangent = orthonormalize(&tangent, &normal);
+        let (sin, cos) = f32::sin_cos(2.0 * PI * rotation);
+        normalize(&(tangent * cos + cross(&normal, &tangent) * sin))
+    }
+
+    // shading normal perturbed by the normal map, then by the bump map
+    fn eval_normalmap(&self, intersection: &BvhIntersection) -> Vec3 {
+        let instance = &self.instances[intersection.instance];
+        let (element, uv) = (intersection.element, &intersection.uv);
         let shape = &self.shapes[instance.shape];
         let material = &self.materials[instance.material];
-        // apply normal mapping
+        // apply normal mapping, with unfiltered lookups at the projected points
         let normal = self.eval_normal(instance, element, uv);
-        let texcoord = self.eval_texcoord(instance, element, uv);
-        if material.normal_tex != INVALID
+        let (points, weights) = self.eval_texture_points(material, intersection, &Ray::default());
+        let normal = if material.normal_tex != INVALID
             && (!shape.triangles.is_empty() || !shape.quads.is_empty())
         {
-            let texture = self
-                .eval_texture(material.normal_tex, &texcoord, false, false, false)
-                .xyz();
-            let mut normalmap = vec3(-1.0, -1.0, -1.0) + 2.0 * texture;
-            let (tu, tv) = self.eval_element_tangents(instance, element);
-            let frame_x = orthonormalize(&tu, &normal);
-            let frame_y = normalize(&cross(&normal, &frame_x));
-            let flip_v = dot(&frame_y, &tv) < 0.0;
-            if !flip_v {
-                normalmap.y *= -1.0; // flip vertical axis
+            // each projection axis is rotated into its own frame before blending
+            let mut blended = zero3!();
+            for (axis, point) in points.iter().enumerate() {
+                if weights[axis] <= 0.0 {
+                    continue;
+                }
+                let texture = self
+                    .eval_texture_filtered(material.normal_tex, point, false)
+                    .xyz();
+                let mut normalmap = vec3(-1.0, -1.0, -1.0) + 2.0 * texture;
+                // vertex tangents carry the handedness, uv derivatives are per
+                // face and projections have a frame per axis
+                let vertex_tangent = if material.projection.p_type == ProjectionType::Uv {
+                    self.eval_vertex_tangent(instance, element, uv)
+                } else {
+                    None
+                };
+                let (frame_x, frame_y, flip_v) = if let Some(tangent) = vertex_tangent {
+                    let frame_x = orthonormalize(&tangent.xyz(), &normal);
+                    let frame_y = normalize(&cross(&normal, &frame_x));
+                    (frame_x, frame_y, tangent.w < 0.0)
+                } else if let Some((tu, tv)) =
+                    self.eval_texcoord_tangents(intersection, point, axis, &normal)
+                {
+                    let frame_x = orthonormalize(&tu, &normal);
+                    let frame_y = normalize(&cross(&normal, &frame_x));
+                    (frame_x, frame_y, dot(&frame_y, &tv) < 0.0)
+                } else {
+                    blended += normal * weights[axis];
+                    continue;
+                };
+                if !flip_v {
+                    normalmap.y *= -1.0; // flip vertical axis
+                }
+                let frame = make_mat3x4(
+                    &[
+                        frame_x.as_slice(),
+                        frame_y.as_slice(),
+                        normal.as_slice(),
+                        zero3!().as_slice(),
+                    ]
+                    .concat(),
+                );
+                blended += transform_normal_frame(&frame, &normalmap, false) * weights[axis];
             }
-            let frame = make_mat3x4(
-                &[
-                    frame_x.as_slice(),
-                    frame_y.as_slice(),
-                    normal.as_slice(),
-                    zero3!().as_slice(),
-                ]
-                .concat(),
-            );
-            transform_normal_frame(&frame, &normalmap, false)
+            normalize(&blended)
         } else {
             normal
+        };
+        if material.bump_tex != INVALID {
+            self.eval_bump(intersection, &points, &weights, &normal)
+        } else {
+            normal
+        }
+    }
+
+    // tangents along the texcoords of a lookup point, in the plane of the
+    // normal: from the parametrization of the shape for uv maps, and from the
+    // texcoord gradients of the axis for projections, so shapes without
+    // texcoords get a frame too; none where they degenerate
+    fn eval_texcoord_tangents(
+        &self,
+        intersection: &BvhIntersection,
+        point: &TexturePoint,
+        axis: usize,
+        normal: &Vec3,
+    ) -> Option<(Vec3, Vec3)> {
+        let instance = &self.instances[intersection.instance];
+        let (element, uv) = (intersection.element, &intersection.uv);
+        let shape = &self.shapes[instance.shape];
+        let projection = &self.materials[instance.material].projection;
+        if projection.p_type != ProjectionType::Uv {
+            let (mut du, mut dv) = if projection.space == TextureSpace::World {
+                projection.eval_gradients(&point.position, axis)
+            } else {
+                projection.eval_gradients(&point.local_position, axis)
+            };
+            if projection.space != TextureSpace::World {
+                // gradients go to world space with the transposed inverse
+                let inverse = inverse_frame(&instance.frame, true);
+                let covector = |gradient: &Vec3| {
+                    vec3(
+                        inverse.column(0).dot(gradient),
+                        inverse.column(1).dot(gradient),
+                        inverse.column(2).dot(gradient),
+                    )
+                };
+                (du, dv) = (covector(&du), covector(&dv));
+            }
+            // the tangents are the basis dual to the gradients in the plane
+            let det = dot(&du, &cross(&dv, normal));
+            if f32::abs(det) < 1e-12 {
+                return None;
+            }
+            return Some((cross(&dv, normal) / det, cross(normal, &du) / det));
         }
+        let (dpdu, dpdv) = if shape.primitive.is_some() {
+            let delta = 1e-3;
+            let position =
+                |du: f32, dv: f32| self.eval_position(instance, element, &(uv + vec2(du, dv)));
+            (
+                (position(delta, 0.0) - position(-delta, 0.0)) / (2.0 * delta),
+                (position(0.0, delta) - position(0.0, -delta)) / (2.0 * delta),
+            )
+        } else {
+            self.eval_element_tangents(instance, element)
+        };
+        if f32::abs(dot(&dpdu, &cross(&dpdv, normal))) < 1e-12 {
+            return None;
+        }
+        Some((dpdu, dpdv))
+    }
+
+    // normal of the surface displaced along the normal by the height map,
+    // from the surface gradient of the height [Mikkelsen 2020] with height
+    // derivatives taken by central differences along the texcoords of each
+    // projection axis; heights are read with the positions moved along as
+    // well, so spatial procedurals and projected texcoords bump too
+    fn eval_bump(
+        &self,
+        intersection: &BvhIntersection,
+        points: &[TexturePoint; 3],
+        weights: &Vec3,
+        normal: &Vec3,
+    ) -> Vec3 {
+        let instance = &self.instances[intersection.instance];
+        let material = &self.materials[instance.material];
+        let projection = &material.projection;
+        let inverse = inverse_frame(&instance.frame, true);
+        // half a texel on images, procedurals have no natural step
+        let texture = &self.textures[material.bump_tex];
+        let delta = if texture.width > 0 && texture.height > 0 {
+            0.5 / u32::max(texture.width, texture.height) as f32
+        } else {
+            1e-3
+        };
+        let mut gradient = zero3!();
+        for (axis, point) in points.iter().enumerate() {
+            if weights[axis] <= 0.0 {
+                continue;
+            }
+            let (dpdu, dpdv) = match self.eval_texcoord_tangents(intersection, point, axis, normal)
+            {
+                Some(tangents) => tangents,
+                None => continue,
+            };
+            let r_u = cross(&dpdv, normal);
+            let r_v = cross(normal, &dpdu);
+            let det = dot(&dpdu, &r_u);
+            let height = |du: f32, dv: f32| {
+                let mut moved = *point;
+                moved.position += dpdu * du + dpdv * dv;
+                moved.local_position = transform_point(&inverse, &moved.position);
+                moved.texcoord = if projection.p_type == ProjectionType::Uv {
+                    point.texcoord + vec2(du, dv)
+                } else if projection.space == TextureSpace::World {
+                    projection.eval_texcoord(&moved.position, axis)
+                } else {
+                    projection.eval_texcoord(&moved.local_position, axis)
+                };
+                self.eval_texture_filtered(material.bump_tex, &moved, false)
+                    .x
+            };
+            let dhdu = (height(delta, 0.0) - height(-delta, 0.0)) / (2.0 * delta);
+            let dhdv = (height(0.0, delta) - height(0.0, -delta)) / (2.0 * delta);
+            gradient += (r_u * dhdu + r_v * dhdv) * (weights[axis] / det);
+        }
+        normalize(&(normal - gradient * material.bump_strength))
     }
 
     fn eval_color(&self, instance: &Instance, intersection: &BvhIntersection) -> Vec4 {
@@ -351,104 +583,486 @@ impl Scene {
             }
 
             let texture = self
-                .eval_texture(environment.emission_tex, &texcoord, false, false, false)
+                .eval_texture(environment.emission_tex, &texcoord, false, false)
                 .xyz();
             emission += vec_comp_mul!(environment.emission, &texture);
         }
         emission
     }
 
-    pub fn eval_texture(
+    // texture lookup filtered over the footprint given by the texcoord
+    // derivatives, falling back to bilinear lookups without one; procedural
+    // textures are evaluated in their own space and not filtered
+    pub fn eval_texture_filtered(
         &self,
         texture_idx: usize,
-        uv: &Vec2,
+        point: &TexturePoint,
         as_linear: bool,
-        no_interpolation: bool,
-        clamp_to_edge: bool,
     ) -> Vec4 {
         if texture_idx == INVALID {
             return one4!();
         }
         let texture = &self.textures[texture_idx];
+        if let Some(procedural) = &texture.procedural {
+            return match procedural.space {
+                TextureSpace::Uv => {
+                    procedural.eval(&vec3(point.texcoord.x, point.texcoord.y, 0.0), true)
+                }
+                TextureSpace::Object => procedural.eval(&point.local_position, false),
+                TextureSpace::World => procedural.eval(&point.position, false),
+            };
+        }
+        let (texture, uv) = if texture.tiles.is_empty() {
+            (texture, point.texcoord)
+        } else {
+            match texture.eval_tile(&point.texcoord) {
+                Some(tile) => tile,
+                None => return zero4!(),
+            }
+        };
         if texture.width == 0 || texture.height == 0 {
             return zero4!();
         }
-        // get coordinates normalized for tiling
-        let (s, t) = if clamp_to_edge {
-            (
-                uv.x.clamp(0.0, 1.0) * texture.width as f32,
-                uv.y.clamp(0.0, 1.0) * texture.height as f32,
-            )
+        let (duvdx, duvdy) = (&point.duvdx, &point.duvdy);
+        if texture.mip_count() == 0 || (is_null(duvdx, 0.0) && is_null(duvdy, 0.0)) {
+            return texture.eval(&uv, as_linear, false);
+        }
+        texture.eval_filtered(&uv, duvdx, duvdy, as_linear)
+    }
+
+    // lookups at the points of all projections blended by their weights
+    fn eval_texture_blended(
+        &self,
+        texture_idx: usize,
+        points: &[TexturePoint; 3],
+        weights: &Vec3,
+        as_linear: bool,
+    ) -> Vec4 {
+        let mut value = zero4!();
+        for (point, weight) in points.iter().zip(weights.iter()) {
+            if *weight > 0.0 {
+                value += self.eval_texture_filtered(texture_idx, point, as_linear) * *weight;
+            }
+        }
+        value
+    }
+
+    // points where the differential rays cross the tangent plane at the hit,
+    // none without differentials, on lines and points, or for rays parallel
+    // to the plane
+    fn eval_differential_positions(
+        &self,
+        intersection: &BvhIntersection,
+        ray: &Ray,
+    ) -> [Option<Vec3>; 2] {
+        let differential = match &ray.differential {
+            Some(differential) => differential,
+            None => return [None, None],
+        };
+        let instance = &self.instances[intersection.instance];
+        let shape = &self.shapes[instance.shape];
+        let (element, uv) = (intersection.element, &intersection.uv);
+        let normal = if shape.primitive.is_some() {
+            self.eval_normal(instance, element, uv)
+        } else if !shape.triangles.is_empty() || !shape.quads.is_empty() {
+            self.eval_element_normal(instance, element)
         } else {
-            (
-                if ((uv.x % 1.0) * texture.width as f32) < 0.0 {
-                    (uv.x % 1.0) * texture.width as f32 + texture.width as f32
-                } else {
-                    (uv.x % 1.0) * texture.width as f32
-                },
-                if ((uv.y % 1.0) * texture.height as f32) < 0.0 {
-                    (uv.y % 1.0) * texture.height as f32 + texture.height as f32
-                } else {
-                    (uv.y % 1.0) * texture.height as f32
-                },
-            )
+            return [None, None];
+        };
+        let position = self.eval_position(instance, element, uv);
+        let offset = |origin: &Vec3, direction: &Vec3| {
+            let denominator = dot(&normal, direction);
+            if f32::abs(denominator) < 1e-8 {
+                return None;
+            }
+            Some(origin + direction * (dot(&normal, &(position - origin)) / denominator))
         };
+        [
+            offset(&differential.rx_origin, &differential.rx_direction),
+            offset(&differential.ry_origin, &differential.ry_direction),
+        ]
+    }
 
-        // get image coordinates and residuals
-        let i = (s as u32).clamp(0, texture.width - 1);
-        let j = (t as u32).clamp(0, texture.height - 1);
-        let ii = (i + 1) % texture.width;
-        let jj = (j + 1) % texture.height;
-        let u = s - i as f32;
-        let v = t - j as f32;
+    // texcoord derivatives across the image, from the texcoords at the
+    // differential positions; zero where those are missing
+    fn eval_texcoord_differentials(
+        &self,
+        intersection: &BvhIntersection,
+        offsets: &[Option<Vec3>; 2],
+    ) -> (Vec2, Vec2) {
+        let instance = &self.instances[intersection.instance];
+        let shape = &self.shapes[instance.shape];
+        let (element, uv) = (intersection.element, &intersection.uv);
+        let texcoord = self.eval_texcoord(instance, element, uv);
+        // angles wrap around on spheres, disks and cylinders
+        let periodic = matches!(
+            shape.primitive,
+            Some(p) if p.p_type != PrimitiveType::Rectangle && p.p_type != PrimitiveType::Plane
+        );
+        texcoord_differentials(offsets, &texcoord, periodic, |offset| {
+            self.eval_plane_texcoord(instance, element, uv, offset)
+        })
+    }
 
-        // handle interpolation
-        if no_interpolation {
-            texture.lookup(i, j, as_linear)
+    // points of a hit for texture lookups, one for each axis of box and
+    // triplanar projections with their weights, only the first otherwise
+    fn eval_texture_points(
+        &self,
+        material: &Material,
+        intersection: &BvhIntersection,
+        ray: &Ray,
+    ) -> ([TexturePoint; 3], Vec3) {
+        let instance = &self.instances[intersection.instance];
+        let (element, uv) = (intersection.element, &intersection.uv);
+        let projection = &material.projection;
+        let position = self.eval_shading_position(intersection);
+        let inverse = inverse_frame(&instance.frame, true);
+        // normals are only needed by projections
+        let (normal, local_normal) = if material.nodes.is_empty()
+            && projection.p_type != ProjectionType::Box
+            && projection.p_type != ProjectionType::Triplanar
+        {
+            (zero3!(), zero3!())
         } else {
-            texture.lookup(i, j, as_linear) * (1.0 - u) * (1.0 - v)
-                + texture.lookup(i, jj, as_linear) * (1.0 - u) * v
-                + texture.lookup(ii, j, as_linear) * u * (1.0 - v)
-                + texture.lookup(ii, jj, as_linear) * u * v
+            let normal = self.eval_normal(instance, element, uv);
+            (normal, transform_normal_frame(&inverse, &normal, true))
+        };
+        let mut offsets = self.eval_differential_positions(intersection, ray);
+        let [dpdx, dpdy] =
+            offsets.map(|offset| offset.map_or(zero3!(), |offset| offset - position));
+        let mut point = TexturePoint {
+            texcoord: zero2!(),
+            duvdx: zero2!(),
+            duvdy: zero2!(),
+            position,
+            local_position: transform_point(&inverse, &position),
+            normal,
+            local_normal,
+            dpdx,
+            dpdy,
+            local_dpdx: transform_vector_frame(&inverse, &dpdx),
+            local_dpdy: transform_vector_frame(&inverse, &dpdy),
+        };
+        if projection.p_type == ProjectionType::Uv {
+            point.texcoord = self.eval_texcoord(instance, element, uv);
+            (point.duvdx, point.duvdy) = self.eval_texcoord_differentials(intersection, &offsets);
+            return ([point, point, point], vec3(1.0, 0.0, 0.0));
+        }
+        let (projected, projected_normal) = if projection.space == TextureSpace::World {
+            (point.position, point.normal)
+        } else {
+            (point.local_position, point.local_normal)
+        };
+        if projection.space != TextureSpace::World {
+            for offset in offsets.iter_mut().flatten() {
+                *offset = transform_point(&inverse, offset);
+            }
         }
+        let weights = projection.eval_weights(&projected_normal);
+        let mut points = [point, point, point];
+        for (axis, point) in points.iter_mut().enumerate() {
+            if weights[axis] > 0.0 {
+                point.texcoord = projection.eval_texcoord(&projected, axis);
+                (point.duvdx, point.duvdy) = texcoord_differentials(
+                    &offsets,
+                    &point.texcoord,
+                    projection.is_periodic(),
+                    |offset| projection.eval_texcoord(offset, axis),
+                );
+            }
+        }
+        (points, weights)
     }
 
-    pub fn eval_material(&self, intersection: &BvhIntersection) -> MaterialPoint {
+    // texcoord of a point on the plane of an element, extrapolating its
+    // parametrization linearly
+    fn eval_plane_texcoord(
+        &self,
+        instance: &Instance,
+        element: usize,
+        uv: &Vec2,
+        position: &Vec3,
+    ) -> Vec2 {
+        let shape = &self.shapes[instance.shape];
+        let local = transform_point(&inverse_frame(&instance.frame, true), position);
+        if let Some(primitive) = &shape.primitive {
+            return primitive.eval_uv(&local);
+        }
+        // quads are interpolated as two triangles, keep the one that was hit
+        let (corners, flipped) = if !shape.triangles.is_empty() {
+            let t = shape.triangles[element];
+            ([t.x, t.y, t.z], false)
+        } else {
+            let q = shape.quads[element];
+            if uv.x + uv.y <= 1.0 {
+                ([q.x, q.y, q.w], false)
+            } else {
+                ([q.z, q.w, q.y], true)
+            }
+        };
+        let p0 = shape.positions[corners[0] as usize];
+        let (e1, e2) = (
+            shape.positions[corners[1] as usize] - p0,
+            shape.positions[corners[2] as usize] - p0,
+        );
+        let v = local - p0;
+        let (d00, d01, d11) = (dot(&e1, &e1), dot(&e1, &e2), dot(&e2, &e2));
+        let (d20, d21) = (dot(&v, &e1), dot(&v, &e2));
+        let denominator = d00 * d11 - d01 * d01;
+        let barycentric = vec2(
+            (d11 * d20 - d01 * d21) / denominator,
+            (d00 * d21 - d01 * d20) / denominator,
+        );
+        let element_uv = if flipped {
+            vec2(1.0 - barycentric.x, 1.0 - barycentric.y)
+        } else {
+            barycentric
+        };
+        self.eval_texcoord(instance, element, &element_uv)
+    }
+
+    pub fn eval_texture(
+        &self,
+        texture_idx: usize,
+        uv: &Vec2,
+        as_linear: bool,
+        no_interpolation: bool,
+    ) -> Vec4 {
+        if texture_idx == INVALID {
+            return one4!();
+        }
+        let texture = &self.textures[texture_idx];
+        if let Some(procedural) = &texture.procedural {
+            return procedural.eval(&vec3(uv.x, uv.y, 0.0), true);
+        }
+        let (texture, uv) = if texture.tiles.is_empty() {
+            (texture, *uv)
+        } else {
+            match texture.eval_tile(uv) {
+                Some(tile) => tile,
+                None => return zero4!(),
+            }
+        };
+        if texture.width == 0 || texture.height == 0 {
+            return zero4!();
+        }
+        texture.eval(&uv, as_linear, no_interpolation)
+    }
+
+    // outputs of all the nodes of a material graph, in order
+    pub fn eval_nodes(&self, material: &Material, point: &TexturePoint) -> Vec<Vec4> {
+        let mut values: Vec<Vec4> = Vec::with_capacity(material.nodes.len());
+        for node in &material.nodes {
+            let value = match node.n_type {
+                NodeType::Value => node.value,
+                NodeType::Coordinates => match node.space {
+                    TextureSpace::Uv => vec4(point.texcoord.x, point.texcoord.y, 0.0, 1.0),
+                    TextureSpace::Object => point.local_position.push(1.0),
+                    TextureSpace::World => point.position.push(1.0),
+                },
+                NodeType::Texture => match node.inputs.first() {
+                    Some(&idx) => {
+                        let texcoord = values[idx].xy();
+                        let lookup = TexturePoint {
+                            texcoord,
+                            duvdx: zero2!(),
+                            duvdy: zero2!(),
+                            ..*point
+                        };
+                        self.eval_texture_filtered(node.texture, &lookup, node.as_linear)
+                    }
+                    None => self.eval_texture_filtered(node.texture, point, node.as_linear),
+                },
+                NodeType::Math => node.eval_math(&node.input(&values, 0), &node.input(&values, 1)),
+                NodeType::Mix => {
+                    let t = match node.inputs.get(2) {
+                        Some(&idx) => values[idx].x,
+                        None => node.value.x,
+                    };
+                    lerp(&node.input(&values, 0), &node.input(&values, 1), t)
+                }
+                NodeType::Ramp => eval_ramp(
+                    &node.ramp_positions,
+                    &node.ramp_colors,
+                    node.input(&values, 0).x,
+                ),
+                NodeType::Split => {
+                    let c = node.input(&values, 0)[usize::min(node.channel, 3)];
+                    vec4(c, c, c, c)
+                }
+                NodeType::Transform => {
+                    let uv = match node.inputs.first() {
+                        Some(&idx) => values[idx].xy(),
+                        None => point.texcoord,
+                    };
+                    let uv = node.transform_uv(&uv);
+                    vec4(uv.x, uv.y, 0.0, 1.0)
+                }
+                NodeType::Triplanar => {
+                    let (position, normal, dpdx, dpdy) = if node.space == TextureSpace::World {
+                        (&point.position, &point.normal, &point.dpdx, &point.dpdy)
+                    } else {
+                        (
+                            &point.local_position,
+                            &point.local_normal,
+                            &point.local_dpdx,
+                            &point.local_dpdy,
+                        )
+                    };
+                    // each axis is filtered by the footprint projected on it
+                    let weights = triplanar_weights(normal, node.sharpness);
+                    let mut color = zero4!();
+                    for axis in 0..3 {
+                        if weights[axis] > 0.0 {
+                            let texcoord = |p: &Vec3| node.transform_uv(&axis_texcoord(p, axis));
+                            let uv = texcoord(position);
+                            let lookup = TexturePoint {
+                                texcoord: uv,
+                                duvdx: texcoord(&(position + dpdx)) - uv,
+                                duvdy: texcoord(&(position + dpdy)) - uv,
+                                ..*point
+                            };
+                            color +=
+                                self.eval_texture_filtered(node.texture, &lookup, node.as_linear)
+                                    * weights[axis];
+                        }
+                    }
+                    color
+                }
+            };
+            values.push(value);
+        }
+        values
+    }
+
+    pub fn eval_material(&self, intersection: &BvhIntersection, ray: &Ray) -> MaterialPoint {
         let instance = &self.instances[intersection.instance];
         let material = &self.materials[instance.material];
-        let texcoord = &self.eval_texcoord(instance, intersection.element, &intersection.uv);
+        let (points, weights) = self.eval_texture_points(material, intersection, ray);
+        // node graphs are evaluated at the projection facing the normal most
+        let point = &points[weights.imax()];
+        let nodes = self.eval_nodes(material, point);
+        // channels read the x or xyz of their output node when they have one
+        let node_scalar = |node: usize, value: f32| {
+            if node == INVALID {
+                value
+            } else {
+                nodes[node].x
+            }
+        };
+        let node_color = |node: usize, value: Vec3| {
+            if node == INVALID {
+                value
+            } else {
+                nodes[node].xyz()
+            }
+        };
 
-        // evaluate textures
-        let emission_tex = self.eval_texture(material.emission_tex, texcoord, true, false, false);
-        let color_tex = self.eval_texture(material.color_tex, texcoord, true, false, false);
-        let roughness_tex =
-            self.eval_texture(material.roughness_tex, texcoord, false, false, false);
-        let scattering_tex =
-            self.eval_texture(material.scattering_tex, texcoord, true, false, false);
+        // evaluate textures, blending the lookups of all projections
+        let eval_texture = |texture_idx: usize, as_linear: bool| {
+            self.eval_texture_blended(texture_idx, &points, &weights, as_linear)
+        };
+        let emission_tex = eval_texture(material.emission_tex, true);
+        let color_tex = eval_texture(material.color_tex, true);
+        let roughness_tex = eval_texture(material.roughness_tex, false);
+        let scattering_tex = eval_texture(material.scattering_tex, true);
+        let specular_tex = eval_texture(material.specular_tex, false);
+        let sheen_tex = eval_texture(material.sheen_tex, true);
+        let clearcoat_tex = eval_texture(material.clearcoat_tex, false);
+        let clearcoat_roughness_tex = eval_texture(material.clearcoat_roughness_tex, false);
+        let transmission_tex = eval_texture(material.transmission_tex, false);
+        let film_tex = eval_texture(material.film_tex, false);
         let color_shp = self.eval_color(instance, intersection);
 
         // material point
         let m_type = material.m_type;
-        let emission = vec_comp_mul!(material.emission, &emission_tex.xyz());
+        let emission = node_color(
+            material.emission_node,
+            vec_comp_mul!(material.emission, &emission_tex.xyz()),
+        );
         let color = vec_comp_mul!(
-            vec_comp_mul!(material.color, &color_shp.xyz()),
-            &color_tex.xyz()
+            node_color(
+                material.color_node,
+                vec_comp_mul!(material.color, &color_tex.xyz())
+            ),
+            &color_shp.xyz()
+        );
+        let opacity =
+            node_scalar(material.opacity_node, material.opacity * color_tex.w) * color_shp.w;
+        let metallic = node_scalar(material.metallic_node, material.metallic * roughness_tex.z);
+        let mut roughness = node_scalar(
+            material.roughness_node,
+            material.roughness * roughness_tex.y,
         );
-        let opacity = material.opacity * color_tex.w * color_shp.w;
-        let metallic = material.metallic * roughness_tex.z;
-        let mut roughness = material.roughness * roughness_tex.y;
         roughness *= roughness;
         let ior = material.ior;
-        let scattering = vec_comp_mul!(material.scattering, &(scattering_tex).xyz());
+        let mut scattering = node_color(
+            material.scattering_node,
+            vec_comp_mul!(material.scattering, &(scattering_tex).xyz()),
+        );
         let scanisotropy = material.scanisotropy;
         let trdepth = material.trdepth;
+        let anisotropy = material.anisotropy;
+        let tangent = if anisotropy != 0.0 {
+            self.eval_shading_tangent(
+                instance,
+                intersection.element,
+                &intersection.uv,
+                material.rotation,
+            )
+        } else if m_type == MaterialType::Hair {
+            self.eval_shading_tangent(instance, intersection.element, &intersection.uv, 0.0)
+        } else {
+            zero3!()
+        };
+        // offset across the width of the fiber, embree reports v in [0, 1]
+        // across flat curves only and zero on round ones
+        let shape = &self.shapes[instance.shape];
+        let hair_offset = if m_type == MaterialType::Hair
+            && !shape.lines.is_empty()
+            && shape.curve == CurveType::Flat
+        {
+            f32::clamp(2.0 * intersection.uv.y - 1.0, -1.0, 1.0)
+        } else {
+            0.0
+        };
+
+        // principled lobes, tints are the base color normalized by its luminance
+        let tint = if luminance(&color) > 0.0 {
+            color / luminance(&color)
+        } else {
+            one3!()
+        };
+        let specular = lerp(&one3!(), &tint, material.specular_tint)
+            * node_scalar(material.specular_node, material.specular * specular_tex.x);
+        let sheen = vec_comp_mul!(
+            lerp(&one3!(), &tint, material.sheen_tint),
+            &node_color(material.sheen_node, material.sheen * sheen_tex.xyz())
+        );
+        // the alpha of the sheen texture scales its roughness
+        let sheen_roughness = f32::max(material.sheen_roughness * sheen_tex.w, MIN_ROUGHNESS);
+        let clearcoat = node_scalar(
+            material.clearcoat_node,
+            material.clearcoat * clearcoat_tex.x,
+        );
+        let mut clearcoat_roughness = material.clearcoat_roughness * clearcoat_roughness_tex.y;
+        clearcoat_roughness = f32::max(clearcoat_roughness * clearcoat_roughness, MIN_ROUGHNESS);
+        let transmission = node_scalar(
+            material.transmission_node,
+            material.transmission * transmission_tex.x,
+        );
+        let film_thickness = material.film_thickness * film_tex.x;
 
         // volume density
-        let density = if m_type == MaterialType::Refractive
-            || m_type == MaterialType::Volumetric
-            || m_type == MaterialType::Subsurface
-        {
+        let density = if m_type == MaterialType::Refractive || m_type == MaterialType::Volumetric {
             -log(&clamp(&color, 0.0001, 1.0)) / trdepth
+        } else if m_type == MaterialType::Subsurface {
+            // color is the multiple-scattering albedo seen on the surface and
+            // the radius the distance light travels below it, both inverted to
+            // the random walk coefficients
+            let radius = material.scatter_radius * trdepth;
+            scattering = subsurface_albedo(&color);
+            subsurface_density(&color, &radius)
         } else {
             zero3!()
         };
@@ -457,6 +1071,9 @@ impl Scene {
         if m_type == MaterialType::Matte
             || m_type == MaterialType::Gltfpbr
             || m_type == MaterialType::Glossy
+            || m_type == MaterialType::Principled
+            || m_type == MaterialType::Measured
+            || m_type == MaterialType::Hair
         {
             roughness = roughness.clamp(MIN_ROUGHNESS, 1.0);
         } else if m_type == MaterialType::Volumetric {
@@ -479,6 +1096,157 @@ impl Scene {
             scanisotropy,
             trdepth,
             opacity,
+            priority: material.priority,
+            outer_ior: 1.0,
+            anisotropy,
+            eta: material.eta,
+            etak: material.etak,
+            tangent,
+            volume: material.volume,
+            frame: instance.frame,
+            specular,
+            sheen,
+            sheen_roughness,
+            clearcoat,
+            clearcoat_roughness,
+            transmission,
+            film_thickness,
+            film_ior: material.film_ior,
+            film: material.film.clone(),
+            melanin: material.melanin,
+            melanin_redness: material.melanin_redness,
+            azimuthal_roughness: material.azimuthal_roughness,
+            tilt: material.tilt,
+            hair_offset,
+            brdf: if material.brdf == INVALID {
+                None
+            } else {
+                Some(self.brdfs[material.brdf].clone())
+            },
+        }
+    }
+
+    pub fn eval_volume(&self, volume_idx: usize, position: &Vec3) -> f32 {
+        if volume_idx == INVALID {
+            return 1.0;
+        }
+        let volume = &self.volumes[volume_idx];
+        if volume.width == 0 || volume.height == 0 || volume.depth == 0 {
+            return 0.0;
+        }
+        // get grid coordinates, the medium is empty outside the bounds
+        let uvw = vec_comp_div!(
+            position - volume.bbox_min,
+            &(volume.bbox_max - volume.bbox_min)
+        );
+        if uvw.min() < 0.0 || uvw.max() > 1.0 {
+            return 0.0;
+        }
+        let s = f32::max(uvw.x * volume.width as f32 - 0.5, 0.0);
+        let t = f32::max(uvw.y * volume.height as f32 - 0.5, 0.0);
+        let r = f32::max(uvw.z * volume.depth as f32 - 0.5, 0.0);
+
+        // get voxel coordinates and residuals
+        let i = (s as u32).clamp(0, volume.width - 1);
+        let j = (t as u32).clamp(0, volume.height - 1);
+        let k = (r as u32).clamp(0, volume.depth - 1);
+        let ii = u32::min(i + 1, volume.width - 1);
+        let jj = u32::min(j + 1, volume.height - 1);
+        let kk = u32::min(k + 1, volume.depth - 1);
+        let u = s - i as f32;
+        let v = t - j as f32;
+        let w = r - k as f32;
+
+        // trilinear interpolation
+        (volume.lookup(i, j, k) * (1.0 - u) * (1.0 - v)
+            + volume.lookup(i, jj, k) * (1.0 - u) * v
+            + volume.lookup(ii, j, k) * u * (1.0 - v)
+            + volume.lookup(ii, jj, k) * u * v)
+            * (1.0 - w)
+            + (volume.lookup(i, j, kk) * (1.0 - u) * (1.0 - v)
+                + volume.lookup(i, jj, kk) * (1.0 - u) * v
+                + volume.lookup(ii, j, kk) * u * (1.0 - v)
+                + volume.lookup(ii, jj, kk) * u * v)
+                * w
+    }
+
+    pub fn eval_volume_material(&self, vol: &MaterialPoint, position: &Vec3) -> MaterialPoint {
+        if vol.volume == INVALID {
+            return vol.clone();
+        }
+        let local_position = transform_point(&inverse_frame(&vol.frame, true), position);
+        MaterialPoint {
+            density: vol.density * self.eval_volume(vol.volume, &local_position),
+            ..vol.clone()
+        }
+    }
+
+    // delta tracking against the grid majorant, returns the sampled distance and
+    // the path weight; spectral densities are handled with mean-probability
+    // collisions so the estimator stays unbiased per channel
+    pub fn sample_volume_distance(
+        &self,
+        vol: &MaterialPoint,
+        ray: &Ray,
+        max_distance: f32,
+        rng: &Mutex<SmallRng>,
+    ) -> (f32, Vec3) {
+        if vol.volume == INVALID {
+            let distance = vol.sample_transmittance(max_distance, rand1(rng), rand1(rng));
+            let transmittance = vol.eval_transmittance(distance)
+                / vol.sample_transmittance_pdf(distance, max_distance);
+            return (distance, transmittance);
+        }
+        let majorant = self.volumes[vol.volume].max_density * vol.density.max();
+        if majorant <= 0.0 {
+            return (max_distance, one3!());
+        }
+        let mut weight = one3!();
+        let mut distance = 0.0;
+        loop {
+            distance -= f32::ln(1.0 - rand1(rng)) / majorant;
+            if distance >= max_distance {
+                return (max_distance, weight);
+            }
+            let position = ray.origin + ray.direction * distance;
+            let extinction = self.eval_volume_material(vol, &position).density;
+            let real_prob = mean3(&extinction) / majorant;
+            if rand1(rng) < real_prob {
+                return (distance, weight / (majorant * real_prob));
+            }
+            weight = vec_comp_mul!(
+                weight,
+                &((vec3(majorant, majorant, majorant) - extinction)
+                    / (majorant * (1.0 - real_prob)))
+            );
+        }
+    }
+
+    // ratio tracking estimate of the transmittance along a ray segment
+    pub fn eval_volume_transmittance(
+        &self,
+        vol: &MaterialPoint,
+        ray: &Ray,
+        max_distance: f32,
+        rng: &Mutex<SmallRng>,
+    ) -> Vec3 {
+        if vol.volume == INVALID {
+            return vol.eval_transmittance(max_distance);
+        }
+        let majorant = self.volumes[vol.volume].max_density * vol.density.max();
+        if majorant <= 0.0 {
+            return one3!();
+        }
+        let mut transmittance = one3!();
+        let mut distance = 0.0;
+        loop {
+            distance -= f32::ln(1.0 - rand1(rng)) / majorant;
+            if distance >= max_distance {
+                return transmittance;
+            }
+            let position = ray.origin + ray.direction * distance;
+            let extinction = self.eval_volume_material(vol, &position).density;
+            transmittance = vec_comp_mul!(transmittance, &(one3!() - extinction / majorant));
         }
     }
 
@@ -518,6 +1286,18 @@ impl Scene {
         }
     }
 
+    // world center and radius of spherical lights, which are sampled by solid
+    // angle; assumes instances scale spheres uniformly
+    fn sphere_light(&self, instance: &Instance) -> Option<(Vec3, f32)> {
+        match self.shapes[instance.shape].primitive {
+            Some(primitive) if primitive.p_type == PrimitiveType::Sphere => Some((
+                transform_point(&instance.frame, &zero3!()),
+                primitive.radius * instance.frame.column(0).norm(),
+            )),
+            _ => None,
+        }
+    }
+
     fn init_lights(&mut self) {
         for (handle, instance) in self.instances.iter().enumerate() {
             let material = &self.materials[instance.material];
@@ -525,7 +1305,8 @@ impl Scene {
                 continue;
             }
             let shape = &self.shapes[instance.shape];
-            if shape.triangles.is_empty() && shape.quads.is_empty() {
+            let area_primitive = matches!(shape.primitive, Some(p) if p.area().is_finite());
+            if !area_primitive && shape.triangles.is_empty() && shape.quads.is_empty() {
                 continue;
             }
             let mut light = Light {
@@ -533,7 +1314,11 @@ impl Scene {
                 environment: INVALID,
                 elements_cdf: VecDeque::new(),
             };
-            if !shape.triangles.is_empty() {
+            if let Some(primitive) = &shape.primitive {
+                light
+                    .elements_cdf
+                    .push_back(primitive.area_in(&instance.frame));
+            } else if !shape.triangles.is_empty() {
                 let elems_num = shape.triangles.len();
                 light.elements_cdf = VecDeque::with_capacity(elems_num);
                 for idx in 0..elems_num {
@@ -605,8 +1390,30 @@ impl Scene {
         if light.instance != INVALID {
             let instance = &self.instances[light.instance];
             let shape = &self.shapes[instance.shape];
+            if let Some((center, radius)) = self.sphere_light(instance) {
+                // sample the cone of directions subtended by the sphere
+                let distance = glm::length(&(center - position));
+                if distance > radius {
+                    let sin_max = radius / distance;
+                    let cos_max = f32::sqrt(f32::max(1.0 - sin_max * sin_max, 0.0));
+                    let cos_theta = 1.0 - ruv.y * (1.0 - cos_max);
+                    let sin_theta = f32::sqrt(f32::max(1.0 - cos_theta * cos_theta, 0.0));
+                    let phi = 2.0 * PI * ruv.x;
+                    let local = vec3(
+                        f32::cos(phi) * sin_theta,
+                        f32::sin(phi) * sin_theta,
+                        cos_theta,
+                    );
+                    return transform_direction_mat(
+                        &basis_fromz(&((center - position) / distance)),
+                        &local,
+                    );
+                }
+            }
             let element = sample_discrete(&light.elements_cdf, rel);
-            let uv = if !shape.triangles.is_empty() {
+            let uv = if let Some(primitive) = &shape.primitive {
+                primitive.sample_uv(ruv)
+            } else if !shape.triangles.is_empty() {
                 sample_triangle(ruv)
             } else {
                 *ruv
@@ -643,6 +1450,23 @@ impl Scene {
         for light in &self.lights {
             if light.instance != INVALID {
                 let instance = &self.instances[light.instance];
+                if let Some((center, radius)) = self.sphere_light(instance) {
+                    let distance = glm::length(&(center - position));
+                    if distance > radius {
+                        let intersection = bvh.intersect_instance(
+                            self,
+                            light.instance,
+                            Ray::new(position, direction),
+                        );
+                        if intersection.hit {
+                            let sin_max = radius / distance;
+                            let cos_max = f32::sqrt(f32::max(1.0 - sin_max * sin_max, 0.0));
+                            pdf += 1.0 / (2.0 * PI * (1.0 - cos_max));
+                        }
+                        continue;
+                    }
+                }
+                let shape = &self.shapes[instance.shape];
                 // check all intersection
                 let mut lpdf = 0.0;
                 let mut next_position = position;
@@ -658,7 +1482,11 @@ impl Scene {
                     // accumulate pdf
                     let lposition =
                         self.eval_position(&instance, intersection.element, &intersection.uv);
-                    let lnormal = self.eval_element_normal(instance, intersection.element);
+                    let lnormal = if shape.primitive.is_some() {
+                        self.eval_normal(instance, intersection.element, &intersection.uv)
+                    } else {
+                        self.eval_element_normal(instance, intersection.element)
+                    };
                     // prob triangle * area triangle = area triangle mesh
                     let area = light.elements_cdf.back().unwrap();
                     // try distance2
@@ -711,76 +1539,174 @@ impl Scene {
     }
 
     pub fn from_json<P: AsRef<Path> + Copy + Sync>(path: P) -> Scene {
-        let file = File::open(path).unwrap();
-        let reader = BufReader::new(file);
-        let mut scene: Scene = serde_json::from_reader(reader).expect("unable to parse JSON");
-        scene.shapes.par_iter_mut().for_each(|shape| {
+        Scene::load(path, None, read_json)
+    }
+
+    // scene format chosen by extension, json otherwise: gltf and glb files
+    // have their external images loaded like those of json scenes, obj files
+    // come with their mtl libraries, and both are viewed from a camera
+    // framing the whole scene when they have none
+    pub fn from_file<P: AsRef<Path> + Copy + Sync>(
+        path: P,
+        cache_options: Option<CacheOptions>,
+    ) -> Scene {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|os_str| os_str.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let read = match extension.as_deref() {
+            Some("gltf") | Some("glb") => model_io::gltf::read_gltf,
+            Some("obj") => model_io::obj::read_obj_scene,
+            _ => read_json,
+        };
+        Scene::load(path, cache_options, read)
+    }
+
+    // with cache options, textures are loaded on demand through a texture
+    // cache instead of being decoded with the scene
+    fn load<P: AsRef<Path> + Copy + Sync>(
+        path: P,
+        cache_options: Option<CacheOptions>,
+        read: fn(&Path, &mut Scene) -> std::io::Result<()>,
+    ) -> Scene {
+        let mut scene = Scene::default();
+        read(path.as_ref(), &mut scene).unwrap_or_else(|error| {
+            panic!("unable to load {}: {}", path.as_ref().display(), error)
+        });
+        scene.texture_cache = cache_options.map(|options| Arc::new(TextureCache::new(options)));
+        scene.load_resources(path);
+        scene
+    }
+
+    // camera on +z that frames the bounds of the instanced shapes, the
+    // default camera for a scene without any
+    pub fn framing_camera(&self) -> Camera {
+        let positions = || {
+            self.instances.iter().flat_map(|instance| {
+                let positions = &self.shapes[instance.shape].positions;
+                positions
+                    .iter()
+                    .map(|position| transform_point(&instance.frame, position))
+            })
+        };
+        let mut camera = Camera::default();
+        if positions().next().is_none() {
+            return camera;
+        }
+        let min = positions().fold(vec3(f32::MAX, f32::MAX, f32::MAX), |a, b| a.inf(&b));
+        let max = positions().fold(vec3(f32::MIN, f32::MIN, f32::MIN), |a, b| a.sup(&b));
+        let (center, radius) = ((max + min) / 2.0, (max - min).norm() / 2.0);
+        // a single point is framed as a unit sphere
+        let radius = if radius > 0.0 { radius } else { 1.0 };
+        let distance = 2.0 * radius * camera.lens / (camera.film / camera.aspect);
+        camera
+            .frame
+            .set_column(3, &(center + vec3(0.0, 0.0, distance)));
+        camera.focus = distance;
+        camera
+    }
+
+    // files referenced by the scene components, with paths relative to the
+    // scene file
+    fn load_resources<P: AsRef<Path> + Copy + Sync>(&mut self, path: P) {
+        for material in &mut self.materials {
+            if material.m_type == MaterialType::Conductor {
+                material.init_conductor().unwrap_or_else(|error| {
+                    panic!("unable to load {}: {}", path.as_ref().display(), error)
+                });
+            }
+            material.init_film();
+            material.check_nodes().unwrap_or_else(|error| {
+                panic!("unable to load {}: {}", path.as_ref().display(), error)
+            });
+        }
+        self.shapes.par_iter_mut().for_each(|shape| {
             if !shape.uri.is_empty() {
-                let mut ply_file =
-                    File::open(path.as_ref().parent().unwrap().join(&shape.uri)).unwrap();
-                let parser = ply::parser::Parser::<ply::ply::DefaultElement>::new();
-                let ply = parser.read_ply(&mut ply_file).unwrap();
-                if ply.payload.get("vertex").is_some() {
-                    for vertex in &ply.payload["vertex"] {
-                        model_io::ply::get_positions(vertex, &mut shape.positions);
-                        model_io::ply::get_normals(vertex, &mut shape.normals);
-                        model_io::ply::get_texcoords(vertex, &mut shape.texcoords);
-                        model_io::ply::get_colors(vertex, &mut shape.colors);
-                        model_io::ply::get_radius(vertex, &mut shape.radius);
-                    }
-                }
-                if ply.payload.get("face").is_some() {
-                    for face in &ply.payload["face"] {
-                        model_io::ply::get_faces(face, shape);
-                    }
-                }
-                if ply.payload.get("line").is_some() {
-                    for line in &ply.payload["line"] {
-                        model_io::ply::get_lines(line, &mut shape.lines);
-                    }
-                }
-                if ply.payload.get("point").is_some() {
-                    for point in &ply.payload["point"] {
-                        model_io::ply::get_points(point, &mut shape.points);
-                    }
-                }
+                let path = path.as_ref().parent().unwrap().join(&shape.uri);
+                let extension = path
+                    .extension()
+                    .and_then(|os_str| os_str.to_str())
+                    .map(|extension| extension.to_ascii_lowercase());
+                let result = if extension.as_deref() == Some("obj") {
+                    model_io::obj::read_shape(&path, shape)
+                } else {
+                    model_io::ply::read_shape(&path, shape)
+                };
+                result
+                    .unwrap_or_else(|error| panic!("unable to load {}: {}", path.display(), error));
             }
+            shape.check_curves().unwrap_or_else(|error| {
+                panic!("unable to load {}: {}", path.as_ref().display(), error)
+            });
         });
+        // tangents are only generated for the shapes that are normal mapped
+        let mut normal_mapped = vec![false; self.shapes.len()];
+        for instance in &self.instances {
+            let material = self.materials.get(instance.material);
+            if material.is_some_and(|material| material.normal_tex != INVALID) {
+                if let Some(normal_mapped) = normal_mapped.get_mut(instance.shape) {
+                    *normal_mapped = true;
+                }
+            }
+        }
+        self.shapes
+            .par_iter_mut()
+            .zip(normal_mapped)
+            .filter(|(_, normal_mapped)| *normal_mapped)
+            .for_each(|(shape, _)| shape.compute_tangents());
 
-        scene.textures.par_iter_mut().for_each(|texture| {
+        let texture_cache = &self.texture_cache;
+        self.textures.par_iter_mut().for_each(|texture| {
             if !texture.uri.is_empty() {
                 let path = path.as_ref().parent().unwrap().join(&texture.uri);
-                let extension = match path.extension() {
-                    None => "",
-                    Some(os_str) => {
-                        if os_str == "png" {
-                            "png"
-                        } else if os_str == "hdr" {
-                            "hdr"
-                        } else {
-                            ""
-                        }
+                if texture.uri.contains("<UDIM>") {
+                    // tiles are only decoded when first looked up
+                    texture.tiles =
+                        model_io::texture::find_udim_tiles(&path).unwrap_or_else(|error| {
+                            panic!("unable to load {}: {}", path.display(), error)
+                        });
+                    // tiles are decoded during the render, so their headers
+                    // are checked here
+                    for tile in texture.tiles.values_mut() {
+                        model_io::texture::read_texture_size(&tile.path).unwrap_or_else(|error| {
+                            panic!("unable to load {}: {}", tile.path.display(), error)
+                        });
+                        tile.cache = texture_cache.clone();
                     }
-                };
-                if extension == "png" {
-                    let rgba = image::open(path).unwrap().into_rgba8();
-                    texture.height = rgba.height();
-                    texture.width = rgba.width();
-                    texture.linear = false;
-                    texture.bytes = rgba;
-                } else if extension == "hdr" {
-                    let file = std::io::BufReader::new(std::fs::File::open(&path).unwrap());
-                    let decoder = image::codecs::hdr::HdrDecoder::new(file).unwrap();
-                    let metadata = decoder.metadata();
-                    texture.height = metadata.height;
-                    texture.width = metadata.width;
-                    texture.linear = true;
-                    texture.hdr = decoder.read_image_hdr().unwrap();
+                    return;
                 }
+                if let Some(cache) = texture_cache {
+                    let lazy = LazyTexture::open(&path, cache.clone()).unwrap_or_else(|error| {
+                        panic!("unable to load {}: {}", path.display(), error)
+                    });
+                    texture.set_lazy(lazy);
+                    return;
+                }
+                model_io::texture::read_texture(&path, texture)
+                    .unwrap_or_else(|error| panic!("unable to load {}: {}", path.display(), error));
+                texture.build_mips();
             }
         });
-        scene.init_lights();
-        scene
+        self.volumes.par_iter_mut().for_each(|volume| {
+            if !volume.uri.is_empty() {
+                let path = path.as_ref().parent().unwrap().join(&volume.uri);
+                model_io::vol::read_volume(&path, volume)
+                    .unwrap_or_else(|error| panic!("unable to load {}: {}", path.display(), error));
+                volume.max_density =
+                    volume.density.iter().fold(0.0, |a, &b| f32::max(a, b)) * volume.scale;
+            }
+        });
+        self.brdfs.par_iter_mut().for_each(|brdf| {
+            let brdf = Arc::get_mut(brdf).unwrap();
+            if !brdf.uri.is_empty() {
+                let path = path.as_ref().parent().unwrap().join(&brdf.uri);
+                model_io::brdf::read_brdf(&path, brdf)
+                    .unwrap_or_else(|error| panic!("unable to load {}: {}", path.display(), error));
+                brdf.init_sampling();
+            }
+        });
+        self.init_lights();
     }
 
     pub fn make_cornellbox() -> Scene {
@@ -1058,3 +1984,35 @@ impl Scene {
         }
     }
 }
+
+// texcoord derivatives from the texcoords at the differential positions, u
+// is taken modulo one when it wraps around
+fn texcoord_differentials(
+    offsets: &[Option<Vec3>; 2],
+    texcoord: &Vec2,
+    periodic: bool,
+    eval_texcoord: impl Fn(&Vec3) -> Vec2,
+) -> (Vec2, Vec2) {
+    let derivative = |offset: &Option<Vec3>| {
+        let offset = match offset {
+            Some(offset) => offset,
+            None => return zero2!(),
+        };
+        let mut derivative = eval_texcoord(offset) - texcoord;
+        if periodic {
+            derivative.x -= derivative.x.round();
+        }
+        if is_finite(&vec3(derivative.x, derivative.y, 0.0)) {
+            derivative
+        } else {
+            zero2!()
+        }
+    };
+    (derivative(&offsets[0]), derivative(&offsets[1]))
+}
+
+// json scenes replace the empty scene they are read into
+fn read_json(path: &Path, scene: &mut Scene) -> std::io::Result<()> {
+    *scene = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    Ok(())
+}
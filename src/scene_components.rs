@@ -1,10 +1,15 @@
-use crate::trace::Ray;
+use crate::shading::FilmResponse;
+use crate::texture_cache::{Encoding, LazyTexture, TextureCache};
+use crate::trace::{Ray, RayDifferential};
 use crate::utils::*;
 use crate::*;
 use glm::{mat3x4, normalize, triangle_normal, vec2, vec3, vec4};
 use glm::{Mat3x4, TVec2, TVec3, TVec4, Vec2, Vec3, Vec4};
 use serde::Deserialize;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 const INVALID: usize = usize::MAX;
 
 #[derive(Debug, Deserialize)]
@@ -34,6 +39,21 @@ impl Default for Camera {
 }
 
 impl Camera {
+    // camera ray with differentials toward the next pixel in x and y
+    pub fn eval_differential(&self, image_uv: Vec2, lens_uv: Vec2, pixel: Vec2) -> Ray {
+        let rx = self.eval(image_uv + vec2(pixel.x, 0.0), lens_uv);
+        let ry = self.eval(image_uv + vec2(0.0, pixel.y), lens_uv);
+        Ray {
+            differential: Some(RayDifferential {
+                rx_origin: rx.origin,
+                rx_direction: rx.direction,
+                ry_origin: ry.origin,
+                ry_direction: ry.direction,
+            }),
+            ..self.eval(image_uv, lens_uv)
+        }
+    }
+
     pub fn eval(&self, image_uv: Vec2, lens_uv: Vec2) -> Ray {
         let film = if self.aspect >= 1.0 {
             vec2(self.film, self.film / self.aspect)
@@ -100,6 +120,10 @@ pub enum MaterialType {
     Volumetric,
     Subsurface,
     Gltfpbr,
+    Conductor,
+    Principled,
+    Measured,
+    Hair,
 }
 
 #[derive(Deserialize, Debug)]
@@ -115,13 +139,73 @@ pub struct Material {
     pub scattering: Vec3,
     pub scanisotropy: f32,
     pub trdepth: f32,
+    // subsurface, per channel scale of trdepth as the scattering distance
+    pub scatter_radius: Vec3,
     pub opacity: f32,
+    pub priority: i32,
+    pub anisotropy: f32,
+    pub rotation: f32,
+    // conductors
+    pub eta: Vec3,
+    pub etak: Vec3,
+    pub conductor: String,
+    pub eta_spectrum: Vec<Vec3>,
+    // thin film, thickness in nm
+    pub film_thickness: f32,
+    pub film_ior: f32,
+    #[serde(skip)]
+    pub film: Option<Arc<FilmResponse>>,
+    // principled
+    pub specular: f32,
+    pub specular_tint: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub sheen_roughness: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    // fraction of the dielectric base refracted through ior
+    pub transmission: f32,
+    // hair, melanin concentrations and cuticle tilt in degrees
+    pub melanin: f32,
+    pub melanin_redness: f32,
+    pub azimuthal_roughness: f32,
+    pub tilt: f32,
     // textures
     pub emission_tex: usize,
     pub color_tex: usize,
     pub roughness_tex: usize,
     pub scattering_tex: usize,
     pub normal_tex: usize,
+    pub specular_tex: usize,
+    // sheen color, with alpha scaling the sheen roughness
+    pub sheen_tex: usize,
+    // clearcoat in red, its roughness in the green of a texture of its own
+    pub clearcoat_tex: usize,
+    pub clearcoat_roughness_tex: usize,
+    pub transmission_tex: usize,
+    pub film_tex: usize,
+    // height map and the height of its unit value in world units
+    pub bump_tex: usize,
+    pub bump_strength: f32,
+    // texcoords from positions for shapes without usable ones
+    pub projection: Projection,
+    // node graph, channels with an output node take its value instead of
+    // the constant times the texture
+    pub nodes: Vec<Node>,
+    pub emission_node: usize,
+    pub color_node: usize,
+    pub opacity_node: usize,
+    pub roughness_node: usize,
+    pub metallic_node: usize,
+    pub scattering_node: usize,
+    pub specular_node: usize,
+    pub sheen_node: usize,
+    pub clearcoat_node: usize,
+    pub transmission_node: usize,
+    // volumes
+    pub volume: usize,
+    // measured brdfs
+    pub brdf: usize,
 }
 
 impl Default for Material {
@@ -136,15 +220,183 @@ impl Default for Material {
             scattering: zero3!(),
             scanisotropy: 0.0,
             trdepth: 0.01,
+            scatter_radius: one3!(),
             opacity: 1.0,
+            priority: 0,
+            anisotropy: 0.0,
+            rotation: 0.0,
+            // conductors
+            eta: zero3!(),
+            etak: zero3!(),
+            conductor: String::new(),
+            eta_spectrum: Vec::new(),
+            // thin film, thickness in nm
+            film_thickness: 0.0,
+            film_ior: 1.33,
+            film: None,
+            // principled
+            specular: 1.0,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            sheen_roughness: 0.3,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            transmission: 0.0,
+            // hair, melanin concentrations and cuticle tilt in degrees
+            melanin: 0.0,
+            melanin_redness: 0.0,
+            azimuthal_roughness: 0.3,
+            tilt: 2.0,
             // textures
             emission_tex: INVALID,
             color_tex: INVALID,
             roughness_tex: INVALID,
             scattering_tex: INVALID,
             normal_tex: INVALID,
+            specular_tex: INVALID,
+            sheen_tex: INVALID,
+            clearcoat_tex: INVALID,
+            clearcoat_roughness_tex: INVALID,
+            transmission_tex: INVALID,
+            film_tex: INVALID,
+            bump_tex: INVALID,
+            bump_strength: 1.0,
+            projection: Projection::default(),
+            // node graph
+            nodes: Vec::new(),
+            emission_node: INVALID,
+            color_node: INVALID,
+            opacity_node: INVALID,
+            roughness_node: INVALID,
+            metallic_node: INVALID,
+            scattering_node: INVALID,
+            specular_node: INVALID,
+            sheen_node: INVALID,
+            clearcoat_node: INVALID,
+            transmission_node: INVALID,
+            // volumes
+            volume: INVALID,
+            // measured brdfs
+            brdf: INVALID,
+        }
+    }
+}
+
+impl Material {
+    // graphs are evaluated in order, so inputs may only refer to earlier
+    // nodes, which also rules out cycles
+    pub fn check_nodes(&self) -> std::io::Result<()> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if let Some(input) = node.inputs.iter().find(|&&input| input >= idx) {
+                return Err(invalid(format!(
+                    "node {} reads node {} that is not before it",
+                    idx, input
+                )));
+            }
         }
+        for output in [
+            self.emission_node,
+            self.color_node,
+            self.opacity_node,
+            self.roughness_node,
+            self.metallic_node,
+            self.scattering_node,
+            self.specular_node,
+            self.sheen_node,
+            self.clearcoat_node,
+            self.transmission_node,
+        ] {
+            if output != INVALID && output >= self.nodes.len() {
+                return Err(invalid(format!(
+                    "material output reads missing node {}",
+                    output
+                )));
+            }
+        }
+        Ok(())
     }
+
+    // tabulated film reflectance, shared by the points of the material
+    pub fn init_film(&mut self) {
+        if self.film_thickness > 0.0 {
+            self.film = Some(Arc::new(FilmResponse::new(
+                self.film_ior,
+                self.film_thickness,
+            )));
+        }
+    }
+
+    // resolves the complex IOR of conductors from a named preset or from
+    // measured (wavelength in nm, eta, k) samples
+    pub fn init_conductor(&mut self) -> std::io::Result<()> {
+        if !self.conductor.is_empty() {
+            let (eta, etak) = conductor_preset(&self.conductor).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unknown conductor {}", self.conductor),
+                )
+            })?;
+            self.eta = eta;
+            self.etak = etak;
+        } else if !self.eta_spectrum.is_empty() {
+            let sample = |wavelength: f32| -> Vec3 {
+                let spectrum = &self.eta_spectrum;
+                let idx = spectrum.partition_point(|s| s.x < wavelength);
+                if idx == 0 {
+                    spectrum[0]
+                } else if idx == spectrum.len() {
+                    spectrum[spectrum.len() - 1]
+                } else {
+                    let (s0, s1) = (&spectrum[idx - 1], &spectrum[idx]);
+                    interpolate_line(s0, s1, (wavelength - s0.x) / (s1.x - s0.x))
+                }
+            };
+            let (red, green, blue) = (sample(630.0), sample(532.0), sample(465.0));
+            self.eta = vec3(red.y, green.y, blue.y);
+            self.etak = vec3(red.z, green.z, blue.z);
+        }
+        Ok(())
+    }
+}
+
+// rgb complex IOR for common metals, fitted from measured spectra
+pub fn conductor_preset(name: &str) -> Option<(Vec3, Vec3)> {
+    match name {
+        "gold" => Some((
+            vec3(0.143119, 0.374957, 1.44248),
+            vec3(3.98316, 2.38572, 1.60322),
+        )),
+        "copper" => Some((
+            vec3(0.200438, 0.924033, 1.10221),
+            vec3(3.91295, 2.45285, 2.14219),
+        )),
+        "aluminium" | "aluminum" => Some((
+            vec3(1.65746, 0.880369, 0.521229),
+            vec3(9.22387, 6.26952, 4.837),
+        )),
+        "silver" => Some((
+            vec3(0.155265, 0.116723, 0.138342),
+            vec3(4.82835, 3.12225, 2.14696),
+        )),
+        "chrome" | "chromium" => Some((
+            vec3(4.36968, 2.9167, 1.6547),
+            vec3(5.20643, 4.23136, 3.75495),
+        )),
+        _ => None,
+    }
+}
+
+// how texcoords outside [0, 1] are mapped back onto the image
+#[derive(PartialEq, Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
 }
 
 #[derive(Default, Deserialize, Debug)]
@@ -153,19 +405,325 @@ pub struct Texture {
     pub width: u32,
     pub height: u32,
     pub linear: bool,
+    pub wrap: WrapMode,
     #[serde(skip)]
-    pub hdr: Vec<image::Rgb<f32>>,
+    pub hdr: Vec<image::Rgba<f32>>,
     #[serde(skip)]
     pub bytes: image::RgbaImage,
+    #[serde(skip)]
+    pub shorts: image::ImageBuffer<image::Rgba<u16>, Vec<u16>>,
+    // downsampled levels below the full resolution image
+    #[serde(skip)]
+    pub mips: Vec<MipLevel>,
     pub uri: String,
+    // textures without an image are evaluated from a pattern
+    pub procedural: Option<Procedural>,
+    // udim tile sets, by tile number, for uris with a <UDIM> token
+    #[serde(skip)]
+    pub tiles: HashMap<u32, TextureTile>,
+    // texels loaded on demand through a texture cache
+    #[serde(skip)]
+    pub lazy: Option<LazyTexture>,
+}
+
+// tile of a udim set, decoded on first lookup or loaded through the texture
+// cache when there is one
+#[derive(Debug, Default)]
+pub struct TextureTile {
+    pub path: PathBuf,
+    pub cache: Option<Arc<TextureCache>>,
+    // none when the tile fails to decode, which shows as no tile
+    pub texture: OnceLock<Option<Texture>>,
 }
 
+#[derive(Debug)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub encoding: Encoding,
+    pub data: Vec<u8>,
+}
+
+impl MipLevel {
+    // level halved from the texels of a width by height level, box filtered
+    // in linear space and stored with the given encoding
+    pub fn downsample(
+        width: u32,
+        height: u32,
+        linear: bool,
+        encoding: Encoding,
+        texel: impl Fn(u32, u32) -> Vec4,
+    ) -> MipLevel {
+        let (next_width, next_height) = (u32::max(width / 2, 1), u32::max(height / 2, 1));
+        let mut data =
+            Vec::with_capacity((next_width * next_height) as usize * encoding.texel_size());
+        for j in 0..next_height {
+            for i in 0..next_width {
+                let mut sum = zero4!();
+                for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let color = texel(
+                        u32::min(2 * i + di, width - 1),
+                        u32::min(2 * j + dj, height - 1),
+                    );
+                    sum += if linear { color } else { srgb_to_rgb(color) };
+                }
+                let average = if linear {
+                    sum / 4.0
+                } else {
+                    rgb_to_srgb(sum / 4.0)
+                };
+                encoding.encode(&average, &mut data);
+            }
+        }
+        MipLevel {
+            width: next_width,
+            height: next_height,
+            encoding,
+            data,
+        }
+    }
+
+    pub fn halve(&self, linear: bool) -> MipLevel {
+        MipLevel::downsample(self.width, self.height, linear, self.encoding, |i, j| {
+            self.texel(i, j)
+        })
+    }
+
+    pub fn texel(&self, i: u32, j: u32) -> Vec4 {
+        let size = self.encoding.texel_size();
+        let offset = (j * self.width + i) as usize * size;
+        self.encoding.decode(&self.data[offset..offset + size])
+    }
+}
+
+// footprints more elongated than this are filtered with ewa instead of
+// trilinear lookups, and clamped to the maximum anisotropy
+const EWA_ANISOTROPY: f32 = 2.0;
+const MAX_ANISOTROPY: f32 = 8.0;
+
 impl Texture {
+    // box filtered pyramid down to a single texel, stored with the same
+    // encoding as the image
+    pub fn build_mips(&mut self) {
+        self.mips.clear();
+        let encoding = Encoding::of(self);
+        let (mut width, mut height) = (self.width, self.height);
+        while width > 1 || height > 1 {
+            let level = self.mips.len();
+            let mip = MipLevel::downsample(width, height, self.linear, encoding, |i, j| {
+                self.texel(level, i, j)
+            });
+            (width, height) = (mip.width, mip.height);
+            self.mips.push(mip);
+        }
+    }
+
+    // texels come from the cache from now on
+    pub fn set_lazy(&mut self, lazy: LazyTexture) {
+        (self.width, self.height) = lazy.levels[0];
+        self.linear = lazy.linear;
+        self.lazy = Some(lazy);
+    }
+
+    // number of levels below the full resolution image
+    pub fn mip_count(&self) -> usize {
+        match &self.lazy {
+            Some(lazy) => lazy.levels.len() - 1,
+            None => self.mips.len(),
+        }
+    }
+
+    pub(crate) fn level_size(&self, level: usize) -> (u32, u32) {
+        if let Some(lazy) = &self.lazy {
+            lazy.levels[level]
+        } else if level == 0 {
+            (self.width, self.height)
+        } else {
+            (self.mips[level - 1].width, self.mips[level - 1].height)
+        }
+    }
+
+    pub(crate) fn texel(&self, level: usize, i: u32, j: u32) -> Vec4 {
+        if let Some(lazy) = &self.lazy {
+            lazy.texel(level, i, j)
+        } else if level == 0 {
+            self.lookup(i, j, false)
+        } else {
+            self.mips[level - 1].texel(i, j)
+        }
+    }
+
+    // texel with integer coordinates mapped to the image by the wrap mode
+    fn lookup_level(&self, level: usize, i: i32, j: i32, as_linear: bool) -> Vec4 {
+        let (width, height) = self.level_size(level);
+        let wrap = |i: i32, size: i32| match self.wrap {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        let (i, j) = (wrap(i, width as i32), wrap(j, height as i32));
+        let color = self.texel(level, i as u32, j as u32);
+        if as_linear && !self.linear {
+            srgb_to_rgb(color)
+        } else {
+            color
+        }
+    }
+
+    fn eval_bilinear(&self, level: usize, uv: &Vec2, as_linear: bool) -> Vec4 {
+        let level = usize::min(level, self.mip_count());
+        let (width, height) = self.level_size(level);
+        let (s, t) = (uv.x * width as f32, uv.y * height as f32);
+        let (i, j) = (s.floor() as i32, t.floor() as i32);
+        let (u, v) = (s - i as f32, t - j as f32);
+        self.lookup_level(level, i, j, as_linear) * (1.0 - u) * (1.0 - v)
+            + self.lookup_level(level, i, j + 1, as_linear) * (1.0 - u) * v
+            + self.lookup_level(level, i + 1, j, as_linear) * u * (1.0 - v)
+            + self.lookup_level(level, i + 1, j + 1, as_linear) * u * v
+    }
+
+    // unfiltered lookup, bilinear or nearest
+    pub fn eval(&self, uv: &Vec2, as_linear: bool, no_interpolation: bool) -> Vec4 {
+        if no_interpolation {
+            let (s, t) = (uv.x * self.width as f32, uv.y * self.height as f32);
+            self.lookup_level(0, s.floor() as i32, t.floor() as i32, as_linear)
+        } else {
+            self.eval_bilinear(0, uv, as_linear)
+        }
+    }
+
+    // tile of a udim set holding a texcoord, with the texcoord inside it;
+    // tiles are ten wide and loaded on first use. texcoords are stored with v
+    // flipped, so rows count up from v = 1 and go back to the flipped v
+    // inside their tile
+    pub fn eval_tile(&self, uv: &Vec2) -> Option<(&Texture, Vec2)> {
+        let (u, v) = (uv.x.floor(), (1.0 - uv.y).floor());
+        if !(0.0..10.0).contains(&u) || v < 0.0 {
+            return None;
+        }
+        let tile = self.tiles.get(&(1001 + u as u32 + 10 * v as u32))?;
+        let texture = tile.texture.get_or_init(|| {
+            let mut texture = Texture {
+                wrap: WrapMode::Clamp,
+                ..Default::default()
+            };
+            let result = match &tile.cache {
+                Some(cache) => {
+                    LazyTexture::open(&tile.path, cache.clone()).map(|lazy| texture.set_lazy(lazy))
+                }
+                None => crate::model_io::texture::read_texture(&tile.path, &mut texture)
+                    .map(|_| texture.build_mips()),
+            };
+            result.ok().map(|_| texture)
+        });
+        Some((texture.as_ref()?, vec2(uv.x - u, uv.y + v)))
+    }
+
+    // elliptical weighted average over the footprint given by the texcoord
+    // derivatives [Heckbert 1989], as in pbrt
+    fn eval_ewa(
+        &self,
+        level: usize,
+        uv: &Vec2,
+        duvdx: &Vec2,
+        duvdy: &Vec2,
+        as_linear: bool,
+    ) -> Vec4 {
+        let level = usize::min(level, self.mip_count());
+        let (width, height) = self.level_size(level);
+        let scale = vec2(width as f32, height as f32);
+        let st = vec_comp_mul!(uv, &scale);
+        let (dst0, dst1) = (vec_comp_mul!(duvdx, &scale), vec_comp_mul!(duvdy, &scale));
+        // ellipse coefficients, widened by a texel so it covers at least one
+        let mut a = dst0.y * dst0.y + dst1.y * dst1.y + 1.0;
+        let mut b = -2.0 * (dst0.x * dst0.y + dst1.x * dst1.y);
+        let mut c = dst0.x * dst0.x + dst1.x * dst1.x + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+        // bounding box of the ellipse in texels
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = 1.0 / det;
+        let (u_sqrt, v_sqrt) = (f32::sqrt(det * c), f32::sqrt(a * det));
+        let s0 = (st.x - 2.0 * inv_det * u_sqrt).ceil() as i32;
+        let s1 = (st.x + 2.0 * inv_det * u_sqrt).floor() as i32;
+        let t0 = (st.y - 2.0 * inv_det * v_sqrt).ceil() as i32;
+        let t1 = (st.y + 2.0 * inv_det * v_sqrt).floor() as i32;
+        // gaussian weights over the texels inside the ellipse
+        let alpha = 2.0;
+        let mut sum = zero4!();
+        let mut weight_sum = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f32 - st.y;
+            for is in s0..=s1 {
+                let ss = is as f32 - st.x;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let weight = f32::exp(-alpha * r2) - f32::exp(-alpha);
+                    sum += self.lookup_level(level, is, it, as_linear) * weight;
+                    weight_sum += weight;
+                }
+            }
+        }
+        if weight_sum > 0.0 {
+            sum / weight_sum
+        } else {
+            self.eval_bilinear(level, uv, as_linear)
+        }
+    }
+
+    // filtered lookup over the footprint spanned by the texcoord derivatives,
+    // nearly isotropic footprints blend two trilinear levels, elongated ones
+    // use ewa along the minor axis
+    pub fn eval_filtered(&self, uv: &Vec2, duvdx: &Vec2, duvdy: &Vec2, as_linear: bool) -> Vec4 {
+        let (mut major, mut minor) = (*duvdx, *duvdy);
+        if glm::length(&major) < glm::length(&minor) {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        let size = u32::max(self.width, self.height) as f32;
+        let (major_length, mut minor_length) = (glm::length(&major), glm::length(&minor));
+        if major_length * size <= 1.0 || self.mip_count() == 0 {
+            return self.eval_bilinear(0, uv, as_linear);
+        }
+        if minor_length * EWA_ANISOTROPY >= major_length {
+            let lod = f32::max(0.0, f32::log2(major_length * size));
+            let level = lod.floor() as usize;
+            let t = lod - level as f32;
+            return self.eval_bilinear(level, uv, as_linear) * (1.0 - t)
+                + self.eval_bilinear(level + 1, uv, as_linear) * t;
+        }
+        if minor_length * MAX_ANISOTROPY < major_length {
+            // degenerate footprints widen perpendicular to the major axis
+            let direction = if minor_length > 0.0 {
+                minor / minor_length
+            } else {
+                vec2(-major.y, major.x) / major_length
+            };
+            minor_length = major_length / MAX_ANISOTROPY;
+            minor = direction * minor_length;
+        }
+        let lod = f32::max(0.0, f32::log2(minor_length * size));
+        let level = lod.floor() as usize;
+        let t = lod - level as f32;
+        self.eval_ewa(level, uv, &major, &minor, as_linear) * (1.0 - t)
+            + self.eval_ewa(level + 1, uv, &major, &minor, as_linear) * t
+    }
+
     pub fn lookup(&self, i: u32, j: u32, as_linear: bool) -> Vec4 {
-        let color = if !self.hdr.is_empty() {
+        let color = if let Some(lazy) = &self.lazy {
+            lazy.texel(0, i, j)
+        } else if !self.hdr.is_empty() {
             // handle hdr
             let color = self.hdr[(j * self.width + i) as usize].0;
-            vec4(color[0], color[1], color[2], 1.0)
+            vec4(color[0], color[1], color[2], color[3])
         } else if !self.bytes.is_empty() {
             // handle bytes
             let color = self.bytes.get_pixel(i, j);
@@ -175,6 +733,15 @@ impl Texture {
                 color[2] as f32 / 255.0,
                 color[3] as f32 / 255.0,
             )
+        } else if !self.shorts.is_empty() {
+            // handle 16-bit images
+            let color = self.shorts.get_pixel(i, j);
+            vec4(
+                color[0] as f32 / 65535.0,
+                color[1] as f32 / 65535.0,
+                color[2] as f32 / 65535.0,
+                color[3] as f32 / 65535.0,
+            )
         } else {
             one4!()
         };
@@ -186,6 +753,684 @@ impl Texture {
     }
 }
 
+// shading point of a texture lookup, texcoords with their derivatives and
+// positions and normals for textures defined in space, with the offsets to
+// where the differential rays land, zero without differentials
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TexturePoint {
+    pub texcoord: Vec2,
+    pub duvdx: Vec2,
+    pub duvdy: Vec2,
+    pub position: Vec3,
+    pub local_position: Vec3,
+    pub normal: Vec3,
+    pub local_normal: Vec3,
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub local_dpdx: Vec3,
+    pub local_dpdy: Vec3,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProceduralType {
+    Fbm,
+    Turbulence,
+    Checker,
+    Grid,
+    Voronoi,
+    Gradient,
+}
+
+// coordinates procedural textures are evaluated in, texcoords or positions
+// in the frame of the instance or of the world
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureSpace {
+    Uv,
+    Object,
+    World,
+}
+
+// patterns blend color0 into color1, gradients instead interpolate the ramp
+// along their direction when one is given
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Procedural {
+    #[serde(rename = "type")]
+    pub p_type: ProceduralType,
+    pub space: TextureSpace,
+    pub scale: f32,
+    pub color0: Vec4,
+    pub color1: Vec4,
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+    pub simplex: bool,
+    pub line_width: f32,
+    pub jitter: f32,
+    pub direction: Vec3,
+    pub ramp_positions: Vec<f32>,
+    pub ramp_colors: Vec<Vec4>,
+}
+
+impl Default for Procedural {
+    fn default() -> Self {
+        Procedural {
+            p_type: ProceduralType::Fbm,
+            space: TextureSpace::Uv,
+            scale: 1.0,
+            color0: vec4(0.0, 0.0, 0.0, 1.0),
+            color1: one4!(),
+            octaves: 6,
+            lacunarity: 2.0,
+            gain: 0.5,
+            simplex: false,
+            line_width: 0.05,
+            jitter: 1.0,
+            direction: vec3(1.0, 0.0, 0.0),
+            ramp_positions: vec![],
+            ramp_colors: vec![],
+        }
+    }
+}
+
+impl Procedural {
+    // color at a point in the space of the texture, planar points only use
+    // x and y so checkers and grids tile texcoords without a z pattern
+    pub fn eval(&self, point: &Vec3, planar: bool) -> Vec4 {
+        let p = point * self.scale;
+        let t = match self.p_type {
+            ProceduralType::Fbm => {
+                let noise = fractal_noise(
+                    &p,
+                    self.octaves,
+                    self.lacunarity,
+                    self.gain,
+                    self.simplex,
+                    false,
+                );
+                f32::clamp(0.5 + 0.5 * noise, 0.0, 1.0)
+            }
+            ProceduralType::Turbulence => f32::clamp(
+                fractal_noise(
+                    &p,
+                    self.octaves,
+                    self.lacunarity,
+                    self.gain,
+                    self.simplex,
+                    true,
+                ),
+                0.0,
+                1.0,
+            ),
+            ProceduralType::Checker => {
+                let z = if planar { 0.0 } else { p.z.floor() };
+                let sum = p.x.floor() + p.y.floor() + z;
+                if sum.rem_euclid(2.0) < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            ProceduralType::Grid => {
+                let on_line = |x: f32| {
+                    let f = x - x.floor();
+                    f < self.line_width / 2.0 || f > 1.0 - self.line_width / 2.0
+                };
+                if on_line(p.x) || on_line(p.y) || (!planar && on_line(p.z)) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ProceduralType::Voronoi => f32::clamp(voronoi_noise(&p, self.jitter), 0.0, 1.0),
+            ProceduralType::Gradient => {
+                let t = glm::dot(&p, &self.direction);
+                if !self.ramp_colors.is_empty() {
+                    return eval_ramp(&self.ramp_positions, &self.ramp_colors, t);
+                }
+                f32::clamp(t, 0.0, 1.0)
+            }
+        };
+        self.color0 * (1.0 - t) + self.color1 * t
+    }
+}
+
+// piecewise linear ramp, positions default to evenly spaced in [0, 1] and
+// an empty ramp passes the value through as gray
+pub fn eval_ramp(positions: &[f32], colors: &[Vec4], t: f32) -> Vec4 {
+    if colors.is_empty() {
+        return vec4(t, t, t, 1.0);
+    }
+    let position = |i: usize| {
+        if positions.len() == colors.len() {
+            positions[i]
+        } else if colors.len() > 1 {
+            i as f32 / (colors.len() - 1) as f32
+        } else {
+            0.0
+        }
+    };
+    if t <= position(0) {
+        return colors[0];
+    }
+    for i in 1..colors.len() {
+        let (t0, t1) = (position(i - 1), position(i));
+        if t <= t1 {
+            let u = if t1 > t0 { (t - t0) / (t1 - t0) } else { 1.0 };
+            return colors[i - 1] * (1.0 - u) + colors[i] * u;
+        }
+    }
+    colors[colors.len() - 1]
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeType {
+    Value,
+    Coordinates,
+    Texture,
+    Math,
+    Mix,
+    Ramp,
+    Split,
+    Transform,
+    Triplanar,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MathOperation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Minimum,
+    Maximum,
+    Clamp,
+    Invert,
+}
+
+// node of a material graph, every node outputs a vec4 and reads the outputs
+// of earlier nodes in the graph through its inputs; scalars are in x and uvs
+// in xy. values stand in for missing inputs, textures without a uv input
+// are looked up at the shading texcoord with its filter footprint
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Node {
+    #[serde(rename = "type")]
+    pub n_type: NodeType,
+    pub inputs: Vec<usize>,
+    pub value: Vec4,
+    // textures and triplanar projections
+    pub texture: usize,
+    pub as_linear: bool,
+    pub sharpness: f32,
+    // coordinates and triplanar projections
+    pub space: TextureSpace,
+    pub operation: MathOperation,
+    pub channel: usize,
+    pub ramp_positions: Vec<f32>,
+    pub ramp_colors: Vec<Vec4>,
+    // uv transforms, rotation in degrees
+    pub offset: Vec2,
+    pub scale: Vec2,
+    pub rotation: f32,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node {
+            n_type: NodeType::Value,
+            inputs: vec![],
+            value: zero4!(),
+            texture: INVALID,
+            as_linear: true,
+            sharpness: 4.0,
+            space: TextureSpace::Uv,
+            operation: MathOperation::Multiply,
+            channel: 0,
+            ramp_positions: vec![],
+            ramp_colors: vec![],
+            offset: zero2!(),
+            scale: vec2(1.0, 1.0),
+            rotation: 0.0,
+        }
+    }
+}
+
+impl Node {
+    // output of an input slot, or the node value if it is not connected
+    pub fn input(&self, values: &[Vec4], slot: usize) -> Vec4 {
+        match self.inputs.get(slot) {
+            Some(&idx) => values[idx],
+            None => self.value,
+        }
+    }
+
+    pub fn eval_math(&self, a: &Vec4, b: &Vec4) -> Vec4 {
+        match self.operation {
+            MathOperation::Add => a + b,
+            MathOperation::Subtract => a - b,
+            MathOperation::Multiply => vec_comp_mul!(a, b),
+            MathOperation::Divide => a.zip_map(b, |x, y| if y != 0.0 { x / y } else { 0.0 }),
+            MathOperation::Power => a.zip_map(b, |x, y| f32::powf(f32::max(x, 0.0), y)),
+            MathOperation::Minimum => glm::min2(a, b),
+            MathOperation::Maximum => glm::max2(a, b),
+            MathOperation::Clamp => glm::clamp(a, 0.0, 1.0),
+            MathOperation::Invert => one4!() - a,
+        }
+    }
+
+    // scales, then rotates about the origin and offsets a texcoord
+    pub fn transform_uv(&self, uv: &Vec2) -> Vec2 {
+        let (sin, cos) = f32::sin_cos(self.rotation.to_radians());
+        let scaled = vec_comp_mul!(uv, &self.scale);
+        vec2(
+            cos * scaled.x - sin * scaled.y,
+            sin * scaled.x + cos * scaled.y,
+        ) + self.offset
+    }
+}
+
+// weights of the projections along x, y and z, sharper blends narrow the
+// seams between them
+pub fn triplanar_weights(normal: &Vec3, sharpness: f32) -> Vec3 {
+    let weights = normal.map(|c| f32::powf(c.abs(), sharpness));
+    let sum = weights.sum();
+    if sum > 0.0 {
+        weights / sum
+    } else {
+        vec3(0.0, 0.0, 1.0)
+    }
+}
+
+// texcoord of a position projected along an axis, onto the yz, zx or xy plane
+pub fn axis_texcoord(position: &Vec3, axis: usize) -> Vec2 {
+    match axis {
+        0 => vec2(position.y, position.z),
+        1 => vec2(position.z, position.x),
+        _ => vec2(position.x, position.y),
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectionType {
+    #[default]
+    Uv,
+    Planar,
+    Spherical,
+    Cylindrical,
+    Box,
+    Triplanar,
+}
+
+// texcoords computed from hit positions instead of the shape texcoords,
+// in the space of the instance unless world space is asked; planar maps
+// project along z, spherical and cylindrical ones wrap around z, box maps
+// take the plane facing the normal and triplanar ones blend all three
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Projection {
+    #[serde(rename = "type")]
+    pub p_type: ProjectionType,
+    pub space: TextureSpace,
+    pub scale: f32,
+    pub sharpness: f32,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection {
+            p_type: ProjectionType::Uv,
+            space: TextureSpace::Object,
+            scale: 1.0,
+            sharpness: 4.0,
+        }
+    }
+}
+
+impl Projection {
+    // weights of the projection axes for a normal, only box and triplanar
+    // projections use more than the first
+    pub fn eval_weights(&self, normal: &Vec3) -> Vec3 {
+        match self.p_type {
+            ProjectionType::Box => {
+                let mut weights = zero3!();
+                weights[normal.abs().imax()] = 1.0;
+                weights
+            }
+            ProjectionType::Triplanar => triplanar_weights(normal, self.sharpness),
+            _ => vec3(1.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn eval_texcoord(&self, position: &Vec3, axis: usize) -> Vec2 {
+        let p = position * self.scale;
+        match self.p_type {
+            ProjectionType::Uv | ProjectionType::Planar => vec2(p.x, p.y),
+            ProjectionType::Spherical => {
+                let direction = if glm::is_null(&p, 0.0) {
+                    vec3(0.0, 0.0, 1.0)
+                } else {
+                    normalize(&p)
+                };
+                vec2(
+                    f32::atan2(direction.y, direction.x) / (2.0 * PI) + 0.5,
+                    f32::acos(direction.z.clamp(-1.0, 1.0)) / PI,
+                )
+            }
+            ProjectionType::Cylindrical => vec2(f32::atan2(p.y, p.x) / (2.0 * PI) + 0.5, p.z),
+            ProjectionType::Box | ProjectionType::Triplanar => axis_texcoord(&p, axis),
+        }
+    }
+
+    // gradients of the texcoords of an axis in the space of the projection,
+    // zero on the axis of spherical and cylindrical maps where u is singular
+    pub fn eval_gradients(&self, position: &Vec3, axis: usize) -> (Vec3, Vec3) {
+        let p = position * self.scale;
+        let unit = |idx: usize| {
+            let mut gradient = zero3!();
+            gradient[idx] = self.scale;
+            gradient
+        };
+        match self.p_type {
+            ProjectionType::Uv | ProjectionType::Planar => (unit(0), unit(1)),
+            ProjectionType::Box | ProjectionType::Triplanar => match axis {
+                0 => (unit(1), unit(2)),
+                1 => (unit(2), unit(0)),
+                _ => (unit(0), unit(1)),
+            },
+            ProjectionType::Spherical | ProjectionType::Cylindrical => {
+                let radius2 = p.x * p.x + p.y * p.y;
+                if radius2 < 1e-12 {
+                    return (zero3!(), zero3!());
+                }
+                let du = vec3(-p.y, p.x, 0.0) * (self.scale / (2.0 * PI * radius2));
+                if self.p_type == ProjectionType::Cylindrical {
+                    return (du, unit(2));
+                }
+                // v = acos(z / |p|) / pi
+                let length2 = p.norm_squared();
+                let dv = (p * p.z - vec3(0.0, 0.0, length2))
+                    * (self.scale / (PI * radius2.sqrt() * length2));
+                (du, dv)
+            }
+        }
+    }
+
+    // whether u wraps around, so its derivatives are taken modulo one
+    pub fn is_periodic(&self) -> bool {
+        matches!(
+            self.p_type,
+            ProjectionType::Spherical | ProjectionType::Cylindrical
+        )
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Volume {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub bbox_min: Vec3,
+    pub bbox_max: Vec3,
+    pub scale: f32,
+    #[serde(skip)]
+    pub density: Vec<f32>,
+    #[serde(skip)]
+    pub max_density: f32,
+    pub uri: String,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Volume {
+            width: 0,
+            height: 0,
+            depth: 0,
+            bbox_min: vec3(-1.0, -1.0, -1.0),
+            bbox_max: vec3(1.0, 1.0, 1.0),
+            scale: 1.0,
+            density: Vec::new(),
+            max_density: 0.0,
+            uri: String::new(),
+        }
+    }
+}
+
+impl Volume {
+    pub fn lookup(&self, i: u32, j: u32, k: u32) -> f32 {
+        if self.density.is_empty() {
+            return 0.0;
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        self.density[(k as usize * height + j as usize) * width + i as usize] * self.scale
+    }
+}
+
+// resolution of the tables used to importance sample measured brdfs
+const BRDF_SAMPLING_OUT: usize = 32;
+const BRDF_SAMPLING_THETA: usize = 32;
+const BRDF_SAMPLING_PHI: usize = 64;
+
+// isotropic measured brdf tabulated over the half and difference angles of
+// [Rusinkiewicz 1998] with the MERL layout, the half angle is stored with a
+// square root mapping
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MeasuredBrdf {
+    pub theta_half: u32,
+    pub theta_diff: u32,
+    pub phi_diff: u32,
+    #[serde(skip)]
+    pub values: Vec<Vec3>,
+    // per outgoing elevation, cdfs over the incoming elevation and over the
+    // incoming azimuth relative to the outgoing one, with the cell densities
+    #[serde(skip)]
+    pub marginal: Vec<f32>,
+    #[serde(skip)]
+    pub conditional: Vec<f32>,
+    #[serde(skip)]
+    pub density: Vec<f32>,
+    pub uri: String,
+}
+
+impl Default for MeasuredBrdf {
+    fn default() -> Self {
+        MeasuredBrdf {
+            theta_half: 90,
+            theta_diff: 90,
+            phi_diff: 180,
+            values: Vec::new(),
+            marginal: Vec::new(),
+            conditional: Vec::new(),
+            density: Vec::new(),
+            uri: String::new(),
+        }
+    }
+}
+
+impl MeasuredBrdf {
+    pub fn lookup(&self, i: u32, j: u32, k: u32) -> Vec3 {
+        if self.values.is_empty() {
+            return zero3!();
+        }
+        self.values[((i * self.theta_diff + j) * self.phi_diff + k) as usize]
+    }
+
+    // brdf value for directions in the local frame of the surface
+    pub fn eval(&self, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 || self.values.is_empty() {
+            return zero3!();
+        }
+        let halfway = normalize(&(outgoing + incoming));
+        let theta_half = f32::acos(f32::clamp(halfway.z, -1.0, 1.0));
+        let phi_half = f32::atan2(halfway.y, halfway.x);
+        // rotate the incoming direction so that the halfway vector is the pole
+        let (sin_p, cos_p) = f32::sin_cos(-phi_half);
+        let rotated = vec3(
+            incoming.x * cos_p - incoming.y * sin_p,
+            incoming.x * sin_p + incoming.y * cos_p,
+            incoming.z,
+        );
+        let (sin_t, cos_t) = f32::sin_cos(-theta_half);
+        let diff = vec3(
+            rotated.x * cos_t + rotated.z * sin_t,
+            rotated.y,
+            -rotated.x * sin_t + rotated.z * cos_t,
+        );
+        let theta_diff = f32::acos(f32::clamp(diff.z, -1.0, 1.0));
+        let mut phi_diff = f32::atan2(diff.y, diff.x);
+        // reciprocity makes the table periodic over half a turn
+        if phi_diff < 0.0 {
+            phi_diff += PI;
+        }
+
+        // continuous table coordinates, interpolated trilinearly
+        let (nh, nd, np) = (self.theta_half, self.theta_diff, self.phi_diff);
+        let x = f32::clamp(
+            f32::sqrt(theta_half / (PI / 2.0)) * nh as f32,
+            0.0,
+            (nh - 1) as f32,
+        );
+        let y = f32::clamp(theta_diff / (PI / 2.0) * nd as f32, 0.0, (nd - 1) as f32);
+        let z = phi_diff / PI * np as f32;
+        let (i, j, k) = (x as u32, y as u32, z as u32 % np);
+        let (ii, jj, kk) = (
+            u32::min(i + 1, nh - 1),
+            u32::min(j + 1, nd - 1),
+            (k + 1) % np,
+        );
+        let (u, v, w) = (x - i as f32, y - j as f32, z - z.floor());
+        (self.lookup(i, j, k) * (1.0 - u) * (1.0 - v)
+            + self.lookup(i, jj, k) * (1.0 - u) * v
+            + self.lookup(ii, j, k) * u * (1.0 - v)
+            + self.lookup(ii, jj, k) * u * v)
+            * (1.0 - w)
+            + (self.lookup(i, j, kk) * (1.0 - u) * (1.0 - v)
+                + self.lookup(i, jj, kk) * (1.0 - u) * v
+                + self.lookup(ii, j, kk) * u * (1.0 - v)
+                + self.lookup(ii, jj, kk) * u * v)
+                * w
+    }
+
+    // tabulates the cosine-weighted brdf per outgoing elevation and builds the
+    // marginal and conditional cdfs used for importance sampling
+    pub fn init_sampling(&mut self) {
+        let (nt, np) = (BRDF_SAMPLING_THETA, BRDF_SAMPLING_PHI);
+        let cell_area = (PI / 2.0 / nt as f32) * (2.0 * PI / np as f32);
+        self.marginal = vec![0.0; BRDF_SAMPLING_OUT * nt];
+        self.conditional = vec![0.0; BRDF_SAMPLING_OUT * nt * np];
+        self.density = vec![0.0; BRDF_SAMPLING_OUT * nt * np];
+        for o in 0..BRDF_SAMPLING_OUT {
+            let theta_out = (o as f32 + 0.5) / BRDF_SAMPLING_OUT as f32 * PI / 2.0;
+            let outgoing = vec3(f32::sin(theta_out), 0.0, f32::cos(theta_out));
+            let mut weights = vec![0.0; nt * np];
+            for t in 0..nt {
+                let theta = (t as f32 + 0.5) / nt as f32 * PI / 2.0;
+                for p in 0..np {
+                    let phi = (p as f32 + 0.5) / np as f32 * 2.0 * PI;
+                    let incoming = vec3(
+                        f32::sin(theta) * f32::cos(phi),
+                        f32::sin(theta) * f32::sin(phi),
+                        f32::cos(theta),
+                    );
+                    weights[t * np + p] =
+                        mean3(&self.eval(&outgoing, &incoming)) * f32::cos(theta) * f32::sin(theta);
+                }
+            }
+            // black rows fall back to uniform sampling of the parameter space
+            let total: f32 = weights.iter().sum();
+            if total <= 0.0 {
+                weights.iter_mut().for_each(|w| *w = 1.0);
+            }
+            let total: f32 = weights.iter().sum();
+            let mut row_sum = 0.0;
+            for t in 0..nt {
+                let row = &weights[t * np..(t + 1) * np];
+                let row_total: f32 = row.iter().sum();
+                let mut sum = 0.0;
+                for (p, &weight) in row.iter().enumerate() {
+                    let cell = (o * nt + t) * np + p;
+                    sum += weight;
+                    self.conditional[cell] = if row_total > 0.0 {
+                        sum / row_total
+                    } else {
+                        (p + 1) as f32 / np as f32
+                    };
+                    self.density[cell] = weight / (total * cell_area);
+                }
+                row_sum += row_total;
+                self.marginal[o * nt + t] = row_sum / total;
+            }
+        }
+    }
+
+    fn sampling_row(&self, outgoing: &Vec3) -> usize {
+        let theta_out = f32::acos(f32::clamp(outgoing.z, 0.0, 1.0));
+        usize::min(
+            (theta_out / (PI / 2.0) * BRDF_SAMPLING_OUT as f32) as usize,
+            BRDF_SAMPLING_OUT - 1,
+        )
+    }
+
+    // samples an incoming direction in the local frame from the tabulated cdfs
+    pub fn sample(&self, outgoing: &Vec3, rn: &Vec2) -> Vec3 {
+        if self.density.is_empty() || outgoing.z <= 0.0 {
+            return zero3!();
+        }
+        let (nt, np) = (BRDF_SAMPLING_THETA, BRDF_SAMPLING_PHI);
+        let o = self.sampling_row(outgoing);
+        let invert = |cdf: &[f32], r: f32| -> (usize, f32) {
+            let idx = usize::min(cdf.partition_point(|&c| c <= r), cdf.len() - 1);
+            let start = if idx == 0 { 0.0 } else { cdf[idx - 1] };
+            let width = cdf[idx] - start;
+            let offset = if width > 0.0 {
+                (r - start) / width
+            } else {
+                0.5
+            };
+            (idx, f32::clamp(offset, 0.0, 1.0))
+        };
+        let (t, dt) = invert(&self.marginal[o * nt..(o + 1) * nt], rn.x);
+        let (p, dp) = invert(
+            &self.conditional[(o * nt + t) * np..(o * nt + t + 1) * np],
+            rn.y,
+        );
+        let theta = (t as f32 + dt) / nt as f32 * PI / 2.0;
+        let phi = (p as f32 + dp) / np as f32 * 2.0 * PI + f32::atan2(outgoing.y, outgoing.x);
+        vec3(
+            f32::sin(theta) * f32::cos(phi),
+            f32::sin(theta) * f32::sin(phi),
+            f32::cos(theta),
+        )
+    }
+
+    // solid angle density of sample for directions in the local frame
+    pub fn sample_pdf(&self, outgoing: &Vec3, incoming: &Vec3) -> f32 {
+        if self.density.is_empty() || outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return 0.0;
+        }
+        let (nt, np) = (BRDF_SAMPLING_THETA, BRDF_SAMPLING_PHI);
+        let o = self.sampling_row(outgoing);
+        let theta = f32::acos(f32::clamp(incoming.z, 0.0, 1.0));
+        let mut phi = f32::atan2(incoming.y, incoming.x) - f32::atan2(outgoing.y, outgoing.x);
+        phi = phi.rem_euclid(2.0 * PI);
+        let t = usize::min((theta / (PI / 2.0) * nt as f32) as usize, nt - 1);
+        let p = usize::min((phi / (2.0 * PI) * np as f32) as usize, np - 1);
+        let sin_theta = f32::sin(theta);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.density[(o * nt + t) * np + p] / sin_theta
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(default)]
 pub struct Instance {
@@ -222,6 +1467,304 @@ impl Default for Environment {
     }
 }
 
+// how line elements are swept into curves, flat ribbons facing the ray are
+// the default; cubic curves take four control points per line, the endpoints
+// and the handles stored after the first for bezier, the neighbors along the
+// strand for b-splines and catmull-rom
+#[derive(PartialEq, Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurveType {
+    #[default]
+    Flat,
+    Round,
+    Bezier,
+    Bspline,
+    Catmullrom,
+}
+
+impl CurveType {
+    pub fn is_cubic(&self) -> bool {
+        matches!(
+            self,
+            CurveType::Bezier | CurveType::Bspline | CurveType::Catmullrom
+        )
+    }
+
+    // basis weights of the control points and their derivatives
+    pub fn basis(&self, u: f32) -> (Vec4, Vec4) {
+        let (u2, u3) = (u * u, u * u * u);
+        let v = 1.0 - u;
+        match self {
+            CurveType::Flat | CurveType::Round => (vec4(v, 0.0, 0.0, u), vec4(-1.0, 0.0, 0.0, 1.0)),
+            CurveType::Bezier => (
+                vec4(v * v * v, 3.0 * u * v * v, 3.0 * u2 * v, u3),
+                vec4(
+                    -3.0 * v * v,
+                    3.0 * v * v - 6.0 * u * v,
+                    6.0 * u * v - 3.0 * u2,
+                    3.0 * u2,
+                ),
+            ),
+            CurveType::Bspline => (
+                vec4(
+                    v * v * v,
+                    3.0 * u3 - 6.0 * u2 + 4.0,
+                    -3.0 * u3 + 3.0 * u2 + 3.0 * u + 1.0,
+                    u3,
+                ) / 6.0,
+                vec4(
+                    -3.0 * v * v,
+                    9.0 * u2 - 12.0 * u,
+                    -9.0 * u2 + 6.0 * u + 3.0,
+                    3.0 * u2,
+                ) / 6.0,
+            ),
+            CurveType::Catmullrom => (
+                vec4(
+                    -u3 + 2.0 * u2 - u,
+                    3.0 * u3 - 5.0 * u2 + 2.0,
+                    -3.0 * u3 + 4.0 * u2 + u,
+                    u3 - u2,
+                ) / 2.0,
+                vec4(
+                    -3.0 * u2 + 4.0 * u - 1.0,
+                    9.0 * u2 - 10.0 * u,
+                    -9.0 * u2 + 8.0 * u + 1.0,
+                    3.0 * u2 - 2.0 * u,
+                ) / 2.0,
+            ),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrimitiveType {
+    Sphere,
+    Disk,
+    Cylinder,
+    Rectangle,
+    Plane,
+}
+
+// analytic shapes in their local frame; spheres are centered at the origin,
+// disks, rectangles and planes lie on z = 0 facing +z, cylinders are open and
+// run along z centered at the origin
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Primitive {
+    #[serde(rename = "type")]
+    pub p_type: PrimitiveType,
+    pub radius: f32,
+    pub height: f32,
+    pub size: Vec2,
+}
+
+impl Default for Primitive {
+    fn default() -> Self {
+        Primitive {
+            p_type: PrimitiveType::Sphere,
+            radius: 1.0,
+            height: 2.0,
+            size: vec2(2.0, 2.0),
+        }
+    }
+}
+
+// bounds used for infinite planes
+const PLANE_EXTENT: f32 = 1e5;
+
+impl Primitive {
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let extent = match self.p_type {
+            PrimitiveType::Sphere => vec3(self.radius, self.radius, self.radius),
+            PrimitiveType::Disk => vec3(self.radius, self.radius, 0.0),
+            PrimitiveType::Cylinder => vec3(self.radius, self.radius, self.height / 2.0),
+            PrimitiveType::Rectangle => vec3(self.size.x / 2.0, self.size.y / 2.0, 0.0),
+            PrimitiveType::Plane => vec3(PLANE_EXTENT, PLANE_EXTENT, 0.0),
+        };
+        (-extent, extent)
+    }
+
+    // closest hit in (tmin, tmax) as distance, uv and unnormalized normal
+    pub fn intersect(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+        tmin: f32,
+        tmax: f32,
+    ) -> Option<(f32, Vec2, Vec3)> {
+        match self.p_type {
+            PrimitiveType::Sphere => {
+                let a = glm::dot(direction, direction);
+                let b = glm::dot(origin, direction);
+                let c = glm::dot(origin, origin) - self.radius * self.radius;
+                let roots = solve_quadratic(a, b, c)?;
+                let distance = [roots.0, roots.1]
+                    .into_iter()
+                    .find(|&t| t > tmin && t < tmax)?;
+                let position = origin + direction * distance;
+                Some((distance, self.eval_uv(&position), position))
+            }
+            PrimitiveType::Cylinder => {
+                let (o, d) = (origin.xy(), direction.xy());
+                let roots = solve_quadratic(
+                    glm::dot(&d, &d),
+                    glm::dot(&o, &d),
+                    glm::dot(&o, &o) - self.radius * self.radius,
+                )?;
+                [roots.0, roots.1].into_iter().find_map(|distance| {
+                    let position = origin + direction * distance;
+                    if distance > tmin
+                        && distance < tmax
+                        && f32::abs(position.z) <= self.height / 2.0
+                    {
+                        Some((
+                            distance,
+                            self.eval_uv(&position),
+                            vec3(position.x, position.y, 0.0),
+                        ))
+                    } else {
+                        None
+                    }
+                })
+            }
+            PrimitiveType::Disk | PrimitiveType::Rectangle | PrimitiveType::Plane => {
+                if direction.z == 0.0 {
+                    return None;
+                }
+                let distance = -origin.z / direction.z;
+                if distance <= tmin || distance >= tmax {
+                    return None;
+                }
+                let position = origin + direction * distance;
+                let inside = match self.p_type {
+                    PrimitiveType::Disk => glm::length(&position.xy()) <= self.radius,
+                    PrimitiveType::Rectangle => {
+                        f32::abs(position.x) <= self.size.x / 2.0
+                            && f32::abs(position.y) <= self.size.y / 2.0
+                    }
+                    _ => true,
+                };
+                if inside {
+                    Some((distance, self.eval_uv(&position), vec3(0.0, 0.0, 1.0)))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn eval_uv(&self, position: &Vec3) -> Vec2 {
+        let mut phi = f32::atan2(position.y, position.x) / (2.0 * PI);
+        if phi < 0.0 {
+            phi += 1.0;
+        }
+        match self.p_type {
+            PrimitiveType::Sphere => vec2(
+                phi,
+                f32::acos(f32::clamp(position.z / self.radius, -1.0, 1.0)) / PI,
+            ),
+            PrimitiveType::Disk => vec2(phi, glm::length(&position.xy()) / self.radius),
+            PrimitiveType::Cylinder => vec2(phi, position.z / self.height + 0.5),
+            PrimitiveType::Rectangle => vec2(
+                position.x / self.size.x + 0.5,
+                position.y / self.size.y + 0.5,
+            ),
+            PrimitiveType::Plane => position.xy(),
+        }
+    }
+
+    pub fn eval_position(&self, uv: &Vec2) -> Vec3 {
+        let (sin, cos) = f32::sin_cos(2.0 * PI * uv.x);
+        match self.p_type {
+            PrimitiveType::Sphere => {
+                self.radius
+                    * vec3(
+                        cos * f32::sin(PI * uv.y),
+                        sin * f32::sin(PI * uv.y),
+                        f32::cos(PI * uv.y),
+                    )
+            }
+            PrimitiveType::Disk => self.radius * uv.y * vec3(cos, sin, 0.0),
+            PrimitiveType::Cylinder => vec3(
+                self.radius * cos,
+                self.radius * sin,
+                (uv.y - 0.5) * self.height,
+            ),
+            PrimitiveType::Rectangle => {
+                vec3((uv.x - 0.5) * self.size.x, (uv.y - 0.5) * self.size.y, 0.0)
+            }
+            PrimitiveType::Plane => vec3(uv.x, uv.y, 0.0),
+        }
+    }
+
+    pub fn eval_normal(&self, uv: &Vec2) -> Vec3 {
+        match self.p_type {
+            PrimitiveType::Sphere => self.eval_position(uv) / self.radius,
+            PrimitiveType::Cylinder => {
+                let (sin, cos) = f32::sin_cos(2.0 * PI * uv.x);
+                vec3(cos, sin, 0.0)
+            }
+            _ => vec3(0.0, 0.0, 1.0),
+        }
+    }
+
+    // direction of increasing u
+    pub fn eval_tangent(&self, uv: &Vec2) -> Vec3 {
+        match self.p_type {
+            PrimitiveType::Rectangle | PrimitiveType::Plane => vec3(1.0, 0.0, 0.0),
+            _ => {
+                let (sin, cos) = f32::sin_cos(2.0 * PI * uv.x);
+                vec3(-sin, cos, 0.0)
+            }
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        match self.p_type {
+            PrimitiveType::Sphere => 4.0 * PI * self.radius * self.radius,
+            PrimitiveType::Disk => PI * self.radius * self.radius,
+            PrimitiveType::Cylinder => 2.0 * PI * self.radius * self.height,
+            PrimitiveType::Rectangle => self.size.x * self.size.y,
+            PrimitiveType::Plane => f32::INFINITY,
+        }
+    }
+
+    // area once placed by an instance frame, which scales the local surface by
+    // the area spanned by its tangent axes; spheres and the cross section of
+    // cylinders are assumed to scale uniformly
+    pub fn area_in(&self, frame: &Mat3x4) -> f32 {
+        let scale = match self.p_type {
+            PrimitiveType::Cylinder => frame.column(0).cross(&frame.column(2)).norm(),
+            _ => frame.column(0).cross(&frame.column(1)).norm(),
+        };
+        self.area() * scale
+    }
+
+    // uv distributed uniformly over the surface area
+    pub fn sample_uv(&self, ruv: &Vec2) -> Vec2 {
+        match self.p_type {
+            PrimitiveType::Sphere => vec2(ruv.x, f32::acos(1.0 - 2.0 * ruv.y) / PI),
+            PrimitiveType::Disk => vec2(ruv.x, f32::sqrt(ruv.y)),
+            _ => *ruv,
+        }
+    }
+}
+
+// roots of a t^2 + 2 b t + c in increasing order
+fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    let discriminant = b * b - a * c;
+    if a == 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    let root = f32::sqrt(discriminant);
+    // avoid cancellation in the smaller root
+    let q = if b < 0.0 { -b + root } else { -b - root };
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((f32::min(t0, t1), f32::max(t0, t1)))
+}
+
 #[derive(Default, Debug, Deserialize)]
 #[serde(default)]
 pub struct Shape {
@@ -238,10 +1781,77 @@ pub struct Shape {
     pub colors: Vec<Vec4>,
     pub radius: Vec<f32>,
     pub tangents: Vec<Vec4>,
+    // curve used for lines
+    pub curve: CurveType,
+    // analytic shapes replace the elements
+    pub primitive: Option<Primitive>,
+    // ply or obj file with the elements and vertex data
     pub uri: String,
 }
 
 impl Shape {
+    // control points of the curve swept along a line, lines at the ends of a
+    // strand repeat their endpoint in place of the missing neighbor
+    pub fn curve_controls(&self, element: usize) -> [usize; 4] {
+        let line = self.lines[element];
+        let (x, y) = (line.x as usize, line.y as usize);
+        match self.curve {
+            CurveType::Flat | CurveType::Round => [x, x, y, y],
+            CurveType::Bezier => [x, x + 1, x + 2, y],
+            CurveType::Bspline | CurveType::Catmullrom => {
+                let prev = match element.checked_sub(1).map(|e| self.lines[e]) {
+                    Some(l) if l.y == line.x => l.x as usize,
+                    _ => x,
+                };
+                let next = match self.lines.get(element + 1) {
+                    Some(l) if l.x == line.y => l.y as usize,
+                    _ => y,
+                };
+                [prev, x, y, next]
+            }
+        }
+    }
+
+    // bezier lines span their two handles, so each has to end three vertices
+    // after it starts
+    pub fn check_curves(&self) -> std::io::Result<()> {
+        if self.curve != CurveType::Bezier {
+            return Ok(());
+        }
+        let count = self.positions.len() as i32;
+        match self
+            .lines
+            .iter()
+            .find(|line| line.x < 0 || line.y != line.x + 3 || line.y >= count)
+        {
+            Some(line) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "bezier line ({}, {}) does not span four vertices",
+                    line.x, line.y
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // center, tangent and radius of the curve at u along a line
+    pub fn eval_curve(&self, element: usize, u: f32) -> (Vec3, Vec3, f32) {
+        let controls = self.curve_controls(element);
+        let (weights, derivatives) = self.curve.basis(u);
+        let mut position = zero3!();
+        let mut tangent = zero3!();
+        let mut radius = 0.0;
+        for (k, &control) in controls.iter().enumerate() {
+            position += self.positions[control] * weights[k];
+            tangent += self.positions[control] * derivatives[k];
+            if !self.radius.is_empty() {
+                radius += self.radius[control] * weights[k];
+            }
+        }
+        (position, normalize(&tangent), radius)
+    }
+
     pub fn eval_position(&self, element: usize, uv: &Vec2) -> Vec3 {
         if !self.triangles.is_empty() {
             let triangle = &self.triangles[element];
@@ -261,12 +1871,7 @@ impl Shape {
                 uv,
             )
         } else if !self.lines.is_empty() {
-            let line = &self.lines[element];
-            interpolate_line(
-                &self.positions[line.x as usize],
-                &self.positions[line.y as usize],
-                uv.x,
-            )
+            self.eval_curve(element, uv.x).0
         } else if !self.points.is_empty() {
             let point = self.points[element];
             self.positions[point as usize]
@@ -313,6 +1918,158 @@ impl Shape {
             zero3!()
         }
     }
+
+    // mikktspace tangents for shapes with texcoords that do not supply them,
+    // so that normal maps baked against them line up across faces; tangents
+    // are generated per face corner, and vertices whose corners disagree, on
+    // mirrored uv seams or hard edges, are split
+    pub fn compute_tangents(&mut self) {
+        if !self.tangents.is_empty()
+            || self.texcoords.is_empty()
+            || (self.triangles.is_empty() && self.quads.is_empty())
+        {
+            return;
+        }
+        let faces = if !self.triangles.is_empty() {
+            self.triangles.len()
+        } else {
+            self.quads.len()
+        };
+        let mut geometry = TangentSpace {
+            shape: self,
+            corners: vec![vec4(0.0, 0.0, 0.0, 1.0); faces * 4],
+        };
+        if !mikktspace::generate_tangents(&mut geometry) {
+            return;
+        }
+        let corners = geometry.corners;
+        let mut tangents: Vec<Option<Vec4>> = vec![None; self.positions.len()];
+        // copies of each split vertex
+        let mut copies: HashMap<usize, Vec<usize>> = HashMap::new();
+        let same = |a: &Vec4, b: &Vec4| a.w == b.w && (a.xyz() - b.xyz()).norm() < 1e-4;
+        for face in 0..faces {
+            let is_triangle = !self.triangles.is_empty();
+            let count = if is_triangle || self.quads[face].z == self.quads[face].w {
+                3
+            } else {
+                4
+            };
+            for vert in 0..count {
+                let tangent = corners[face * 4 + vert];
+                let vertex = if is_triangle {
+                    self.triangles[face][vert] as usize
+                } else {
+                    self.quads[face][vert] as usize
+                };
+                let existing = tangents[vertex];
+                let split = match existing {
+                    None => {
+                        tangents[vertex] = Some(tangent);
+                        continue;
+                    }
+                    Some(other) if same(&other, &tangent) => continue,
+                    Some(_) => copies.entry(vertex).or_default(),
+                };
+                let found = split
+                    .iter()
+                    .copied()
+                    .find(|&copy| same(&tangents[copy].unwrap(), &tangent));
+                let copy = match found {
+                    Some(copy) => copy,
+                    None => {
+                        let copy = self.copy_vertex(vertex);
+                        tangents.push(Some(tangent));
+                        split.push(copy);
+                        copy
+                    }
+                };
+                if is_triangle {
+                    self.triangles[face][vert] = copy as i32;
+                } else {
+                    self.quads[face][vert] = copy as i32;
+                }
+            }
+            if count == 3 && !is_triangle {
+                self.quads[face].w = self.quads[face].z;
+            }
+        }
+        self.tangents = tangents
+            .into_iter()
+            .map(|tangent| tangent.unwrap_or(vec4(0.0, 0.0, 0.0, 1.0)))
+            .collect();
+    }
+
+    // new vertex with the data of an existing one
+    fn copy_vertex(&mut self, vertex: usize) -> usize {
+        self.positions.push(self.positions[vertex]);
+        if !self.normals.is_empty() {
+            self.normals.push(self.normals[vertex]);
+        }
+        self.texcoords.push(self.texcoords[vertex]);
+        if !self.colors.is_empty() {
+            self.colors.push(self.colors[vertex]);
+        }
+        if !self.radius.is_empty() {
+            self.radius.push(self.radius[vertex]);
+        }
+        self.positions.len() - 1
+    }
+}
+
+// faces of a shape as seen by mikktspace, quads with a repeated last vertex
+// are triangles; tangents are kept by face corner
+struct TangentSpace<'a> {
+    shape: &'a Shape,
+    corners: Vec<Vec4>,
+}
+
+impl TangentSpace<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        if !self.shape.triangles.is_empty() {
+            self.shape.triangles[face][vert] as usize
+        } else {
+            self.shape.quads[face][vert] as usize
+        }
+    }
+}
+
+impl mikktspace::Geometry for TangentSpace<'_> {
+    fn num_faces(&self) -> usize {
+        if !self.shape.triangles.is_empty() {
+            self.shape.triangles.len()
+        } else {
+            self.shape.quads.len()
+        }
+    }
+
+    fn num_vertices_of_face(&self, face: usize) -> usize {
+        if !self.shape.triangles.is_empty() || self.shape.quads[face].z == self.shape.quads[face].w
+        {
+            3
+        } else {
+            4
+        }
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.shape.positions[self.vertex(face, vert)].into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        if !self.shape.normals.is_empty() {
+            return self.shape.normals[self.vertex(face, vert)].into();
+        }
+        let position = |vert: usize| &self.shape.positions[self.vertex(face, vert)];
+        triangle_normal(position(0), position(1), position(2)).into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.shape.texcoords[self.vertex(face, vert)].into()
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corners[face * 4 + vert] = tangent.into();
+    }
 }
 
 #[derive(Debug)]
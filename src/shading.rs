@@ -1,11 +1,29 @@
-use crate::scene_components::MaterialType;
+use crate::scene_components::{MaterialType, MeasuredBrdf};
 use crate::utils::*;
 use crate::*;
-use glm::{dot, epsilon, is_null, lerp, normalize, vec2, vec3};
-use glm::{Vec2, Vec3};
+use glm::{clamp, cross, dot, epsilon, is_null, lerp, make_mat3, mat3x4, normalize, vec2, vec3};
+use glm::{Mat3, Mat3x4, Vec2, Vec3};
+use parking_lot::RwLock;
 use std::collections::VecDeque;
 use std::f32::consts::PI;
-
+use std::sync::{Arc, OnceLock};
+
+const INVALID: usize = usize::MAX;
+const CLEARCOAT_IOR: f32 = 1.5;
+// fraction of measured brdf samples drawn from the cosine lobe, so that
+// directions missed by the tabulated cdfs keep a nonzero density
+const MEASURED_COSINE_FRACTION: f32 = 0.1;
+// scattering orders of the hair lobes modeled explicitly, R, TT and TRT,
+// higher orders are lumped in a single lobe
+const HAIR_MAX_ORDER: usize = 3;
+// resolution of the tabulated film response, cosines and thickness step in
+// nm, and the largest tables and number of index sets kept per material
+const FILM_COSINES: usize = 128;
+const FILM_THICKNESS_STEP: f32 = 4.0;
+const FILM_MAX_THICKNESSES: usize = 256;
+const FILM_MAX_TABLES: usize = 16;
+
+#[derive(Clone)]
 pub struct MaterialPoint {
     pub m_type: MaterialType,
     pub emission: Vec3,
@@ -18,6 +36,29 @@ pub struct MaterialPoint {
     pub scanisotropy: f32,
     pub trdepth: f32,
     pub opacity: f32,
+    pub priority: i32,
+    pub outer_ior: f32,
+    pub anisotropy: f32,
+    pub eta: Vec3,
+    pub etak: Vec3,
+    pub tangent: Vec3,
+    pub volume: usize,
+    pub frame: Mat3x4,
+    pub specular: Vec3,
+    pub sheen: Vec3,
+    pub sheen_roughness: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub transmission: f32,
+    pub film_thickness: f32,
+    pub film_ior: f32,
+    pub film: Option<Arc<FilmResponse>>,
+    pub melanin: f32,
+    pub melanin_redness: f32,
+    pub azimuthal_roughness: f32,
+    pub tilt: f32,
+    pub hair_offset: f32,
+    pub brdf: Option<Arc<MeasuredBrdf>>,
 }
 
 impl Default for MaterialPoint {
@@ -34,6 +75,29 @@ impl Default for MaterialPoint {
             scanisotropy: 0.0,
             trdepth: 0.01,
             opacity: 1.0,
+            priority: 0,
+            outer_ior: 1.0,
+            anisotropy: 0.0,
+            eta: zero3!(),
+            etak: zero3!(),
+            tangent: zero3!(),
+            volume: INVALID,
+            frame: mat3x4(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0),
+            specular: one3!(),
+            sheen: zero3!(),
+            sheen_roughness: 0.09,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03 * 0.03,
+            transmission: 0.0,
+            film_thickness: 0.0,
+            film_ior: 1.33,
+            film: None,
+            melanin: 0.0,
+            melanin_redness: 0.0,
+            azimuthal_roughness: 0.3,
+            tilt: 2.0,
+            hair_offset: 0.0,
+            brdf: None,
         }
     }
 }
@@ -51,11 +115,15 @@ impl MaterialPoint {
         match self.m_type {
             MaterialType::Matte => self.sample_matte(normal, outgoing, rn),
             MaterialType::Glossy => self.sample_glossy(normal, outgoing, rnl, rn),
-            MaterialType::Reflective => self.sample_reflective(normal, outgoing, rn),
+            MaterialType::Reflective => self.sample_reflective(normal, outgoing, rnl, rn),
             MaterialType::Transparent => self.sample_transparent(normal, outgoing, rnl, rn),
             MaterialType::Refractive => self.sample_refractive(normal, outgoing, rnl, rn),
             MaterialType::Subsurface => self.sample_refractive(normal, outgoing, rnl, rn),
             MaterialType::Gltfpbr => self.sample_gltfpbr(normal, outgoing, rnl, rn),
+            MaterialType::Conductor => self.sample_conductor(normal, outgoing, rnl, rn),
+            MaterialType::Principled => self.sample_principled(normal, outgoing, rnl, rn),
+            MaterialType::Measured => self.sample_measured(normal, outgoing, rnl, rn),
+            MaterialType::Hair => self.sample_hair(normal, outgoing, rnl, rn),
             _ => zero3!(),
         }
     }
@@ -72,6 +140,10 @@ impl MaterialPoint {
             MaterialType::Refractive => self.eval_refractive(normal, outgoing, incoming),
             MaterialType::Subsurface => self.eval_refractive(normal, outgoing, incoming),
             MaterialType::Gltfpbr => self.eval_gltfpbr(normal, outgoing, incoming),
+            MaterialType::Conductor => self.eval_conductor(normal, outgoing, incoming),
+            MaterialType::Principled => self.eval_principled(normal, outgoing, incoming),
+            MaterialType::Measured => self.eval_measured(normal, outgoing, incoming),
+            MaterialType::Hair => self.eval_hair(normal, outgoing, incoming),
             _ => zero3!(),
         }
     }
@@ -88,6 +160,10 @@ impl MaterialPoint {
             MaterialType::Refractive => self.sample_refractive_pdf(normal, outgoing, incoming),
             MaterialType::Subsurface => self.sample_refractive_pdf(normal, outgoing, incoming),
             MaterialType::Gltfpbr => self.sample_gltfpbr_pdf(normal, outgoing, incoming),
+            MaterialType::Conductor => self.sample_conductor_pdf(normal, outgoing, incoming),
+            MaterialType::Principled => self.sample_principled_pdf(normal, outgoing, incoming),
+            MaterialType::Measured => self.sample_measured_pdf(normal, outgoing, incoming),
+            MaterialType::Hair => self.sample_hair_pdf(normal, outgoing, incoming),
             _ => 0.0,
         }
     }
@@ -98,8 +174,10 @@ impl MaterialPoint {
         }
         match self.m_type {
             MaterialType::Reflective => self.sample_reflective_delta(normal, outgoing),
+            MaterialType::Conductor => self.sample_reflective_delta(normal, outgoing),
             MaterialType::Transparent => self.sample_transparent_delta(normal, outgoing, rnl),
             MaterialType::Refractive => self.sample_refractive_delta(normal, outgoing, rnl),
+            MaterialType::Subsurface => self.sample_refractive_delta(normal, outgoing, rnl),
             MaterialType::Volumetric => self.sample_passthrough(outgoing),
             _ => zero3!(),
         }
@@ -111,8 +189,10 @@ impl MaterialPoint {
         }
         match self.m_type {
             MaterialType::Reflective => self.eval_reflective_delta(normal, outgoing, incoming),
+            MaterialType::Conductor => self.eval_conductor_delta(normal, outgoing, incoming),
             MaterialType::Transparent => self.eval_transparent_delta(normal, outgoing, incoming),
             MaterialType::Refractive => self.eval_refractive_delta(normal, outgoing, incoming),
+            MaterialType::Subsurface => self.eval_refractive_delta(normal, outgoing, incoming),
             MaterialType::Volumetric => self.eval_passthrough(normal, outgoing, incoming),
             _ => zero3!(),
         }
@@ -126,12 +206,16 @@ impl MaterialPoint {
             MaterialType::Reflective => {
                 self.sample_reflective_pdf_delta(normal, outgoing, incoming)
             }
+            MaterialType::Conductor => self.sample_reflective_pdf_delta(normal, outgoing, incoming),
             MaterialType::Transparent => {
                 self.sample_transparent_pdf_delta(normal, outgoing, incoming)
             }
             MaterialType::Refractive => {
                 self.sample_refractive_pdf_delta(normal, outgoing, incoming)
             }
+            MaterialType::Subsurface => {
+                self.sample_refractive_pdf_delta(normal, outgoing, incoming)
+            }
             MaterialType::Volumetric => self.sample_passthrough_pdf(normal, outgoing, incoming),
             _ => 0.0,
         }
@@ -171,8 +255,11 @@ impl MaterialPoint {
         } else {
             *normal
         };
-        if rnl < fresnel_dielectric(self.ior, &up_normal, outgoing) {
-            let halfway = sample_microfacet(self.roughness, &up_normal, rn, true);
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let f = mean3(&self.fresnel_film(1.0, self.ior, &up_normal, outgoing))
+            * microfacet_albedo(&roughness, dot(&up_normal, outgoing));
+        if rnl < f {
+            let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
             let incoming = glm::reflect_vec(&(-outgoing), &halfway);
             if !same_hemisphere(&up_normal, outgoing, &incoming) {
                 zero3!()
@@ -193,14 +280,17 @@ impl MaterialPoint {
         } else {
             *normal
         };
-        let f1 = fresnel_dielectric(self.ior, &up_normal, outgoing);
+        let f1 = self.fresnel_film(1.0, self.ior, &up_normal, outgoing);
         let halfway = normalize(&(incoming + outgoing));
-        let f = fresnel_dielectric(self.ior, &halfway, incoming);
-        let d = microfacet_distribution(self.roughness, &up_normal, &halfway, true);
-        let g = microfacet_shadowing(self.roughness, &up_normal, &halfway, outgoing, incoming);
-        self.color * (1.0 - f1) / PI * f32::abs(dot(&up_normal, incoming))
-            + one3!() * f * d * g / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, incoming))
+        let f = self.fresnel_film(1.0, self.ior, &halfway, incoming);
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let d = microfacet_distribution(&roughness, &frame, &halfway);
+        let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
+        let reflectivity = eta_to_reflectivity(&vec3(self.ior, self.ior, self.ior));
+        vec_comp_mul!(self.color, &(one3!() - f1)) / PI * f32::abs(dot(&up_normal, incoming))
+            + f * d * g / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, incoming))
                 * f32::abs(dot(&up_normal, incoming))
+            + microfacet_compensation(&roughness, &reflectivity, &up_normal, outgoing, incoming)
     }
 
     fn sample_glossy_pdf(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
@@ -209,20 +299,29 @@ impl MaterialPoint {
         } else {
             *normal
         };
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return 0.0;
+        }
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
         let halfway = normalize(&(outgoing + incoming));
-        let f = fresnel_dielectric(self.ior, &up_normal, outgoing);
-        f * sample_microfacet_pdf(self.roughness, &up_normal, &halfway)
+        let f = mean3(&self.fresnel_film(1.0, self.ior, &up_normal, outgoing))
+            * microfacet_albedo(&roughness, dot(&up_normal, outgoing));
+        f * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing)
             / (4.0 * f32::abs(dot(outgoing, &halfway)))
             + (1.0 - f) * sample_hemisphere_cos_pdf(&up_normal, incoming)
     }
 
-    fn sample_reflective(&self, normal: &Vec3, outgoing: &Vec3, rn: &Vec2) -> Vec3 {
+    fn sample_reflective(&self, normal: &Vec3, outgoing: &Vec3, rnl: f32, rn: &Vec2) -> Vec3 {
         let up_normal = if dot(normal, outgoing) <= 0.0 {
             -normal
         } else {
             *normal
         };
-        let halfway = sample_microfacet(self.roughness, &up_normal, rn, true);
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        if rnl >= microfacet_albedo(&roughness, dot(&up_normal, outgoing)) {
+            return sample_hemisphere_cos(&up_normal, rn);
+        }
+        let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
         let incoming = glm::reflect_vec(&(-outgoing), &halfway);
         if !same_hemisphere(&up_normal, outgoing, &incoming) {
             zero3!()
@@ -250,16 +349,18 @@ impl MaterialPoint {
             *normal
         };
         let halfway = normalize(&(incoming + outgoing));
-        let f = fresnel_conductor(
-            &reflectivity_to_eta(&self.color),
+        let f = self.fresnel_film_conductor(
+            &(reflectivity_to_eta(&self.color) * self.outer_ior),
             &zero3!(),
             &halfway,
             incoming,
         );
-        let d = microfacet_distribution(self.roughness, &up_normal, &halfway, true);
-        let g = microfacet_shadowing(self.roughness, &up_normal, &halfway, outgoing, incoming);
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let d = microfacet_distribution(&roughness, &frame, &halfway);
+        let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
         f * d * g / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, incoming))
             * f32::abs(dot(&up_normal, incoming))
+            + microfacet_compensation(&roughness, &self.color, &up_normal, outgoing, incoming)
     }
 
     fn eval_reflective_delta(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
@@ -271,8 +372,8 @@ impl MaterialPoint {
         } else {
             *normal
         };
-        fresnel_conductor(
-            &reflectivity_to_eta(&self.color),
+        self.fresnel_film_conductor(
+            &(reflectivity_to_eta(&self.color) * self.outer_ior),
             &zero3!(),
             &up_normal,
             outgoing,
@@ -288,9 +389,12 @@ impl MaterialPoint {
         } else {
             *normal
         };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
         let halfway = normalize(&(outgoing + incoming));
-        return sample_microfacet_pdf(self.roughness, &up_normal, &halfway)
-            / (4.0 * f32::abs(dot(outgoing, &halfway)));
+        let f = microfacet_albedo(&roughness, dot(&up_normal, outgoing));
+        f * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing)
+            / (4.0 * f32::abs(dot(outgoing, &halfway)))
+            + (1.0 - f) * sample_hemisphere_cos_pdf(&up_normal, incoming)
     }
 
     fn sample_reflective_pdf_delta(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
@@ -306,8 +410,9 @@ impl MaterialPoint {
         } else {
             *normal
         };
-        let halfway = sample_microfacet(self.roughness, &up_normal, rn, true);
-        if rnl < fresnel_dielectric(self.ior, &halfway, outgoing) {
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
+        if rnl < mean3(&self.fresnel_film(1.0, self.ior, &halfway, outgoing)) {
             let incoming = glm::reflect_vec(&(-outgoing), &halfway);
             if !same_hemisphere(&up_normal, outgoing, &incoming) {
                 return zero3!();
@@ -331,7 +436,7 @@ impl MaterialPoint {
         } else {
             *normal
         };
-        if rnl < fresnel_dielectric(self.ior, &up_normal, outgoing) {
+        if rnl < mean3(&self.fresnel_film(1.0, self.ior, &up_normal, outgoing)) {
             glm::reflect_vec(&(-outgoing), &up_normal)
         } else {
             -outgoing
@@ -344,21 +449,21 @@ impl MaterialPoint {
         } else {
             *normal
         };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
             let halfway = normalize(&(incoming + outgoing));
-            let f = fresnel_dielectric(self.ior, &halfway, outgoing);
-            let d = microfacet_distribution(self.roughness, &up_normal, &halfway, true);
-            let g = microfacet_shadowing(self.roughness, &up_normal, &halfway, outgoing, incoming);
-            one3!() * f * d * g / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, incoming))
+            let f = self.fresnel_film(1.0, self.ior, &halfway, outgoing);
+            let d = microfacet_distribution(&roughness, &frame, &halfway);
+            let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
+            f * d * g / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, incoming))
                 * f32::abs(dot(&up_normal, incoming))
         } else {
             let reflected = glm::reflect_vec(incoming, &up_normal);
             let halfway = normalize(&(reflected + outgoing));
-            let f = fresnel_dielectric(self.ior, &halfway, outgoing);
-            let d = microfacet_distribution(self.roughness, &up_normal, &halfway, true);
-            let g =
-                microfacet_shadowing(self.roughness, &up_normal, &halfway, outgoing, &reflected);
-            self.color * (1.0 - f) * d * g
+            let f = self.fresnel_film(1.0, self.ior, &halfway, outgoing);
+            let d = microfacet_distribution(&roughness, &frame, &halfway);
+            let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, &reflected);
+            vec_comp_mul!(self.color, &(one3!() - f)) * d * g
                 / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, &reflected))
                 * f32::abs(dot(&up_normal, &reflected))
         }
@@ -371,9 +476,12 @@ impl MaterialPoint {
             *normal
         };
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
-            one3!() * fresnel_dielectric(self.ior, &up_normal, outgoing)
+            self.fresnel_film(1.0, self.ior, &up_normal, outgoing)
         } else {
-            self.color * (1.0 - fresnel_dielectric(self.ior, &up_normal, outgoing))
+            vec_comp_mul!(
+                self.color,
+                &(one3!() - self.fresnel_film(1.0, self.ior, &up_normal, outgoing))
+            )
         }
     }
 
@@ -383,16 +491,17 @@ impl MaterialPoint {
         } else {
             *normal
         };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
             let halfway = normalize(&(incoming + outgoing));
-            fresnel_dielectric(self.ior, &halfway, outgoing)
-                * sample_microfacet_pdf(self.roughness, &up_normal, &halfway)
+            mean3(&self.fresnel_film(1.0, self.ior, &halfway, outgoing))
+                * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing)
                 / (4.0 * f32::abs(dot(outgoing, &halfway)))
         } else {
             let reflected = glm::reflect_vec(incoming, &up_normal);
             let halfway = normalize(&(reflected + outgoing));
-            let d = (1.0 - fresnel_dielectric(self.ior, &halfway, outgoing))
-                * sample_microfacet_pdf(self.roughness, &up_normal, &halfway);
+            let d = (1.0 - mean3(&self.fresnel_film(1.0, self.ior, &halfway, outgoing)))
+                * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing);
             d / (4.0 * f32::abs(dot(outgoing, &halfway)))
         }
     }
@@ -404,22 +513,33 @@ impl MaterialPoint {
             *normal
         };
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
-            fresnel_dielectric(self.ior, &up_normal, outgoing)
+            mean3(&self.fresnel_film(1.0, self.ior, &up_normal, outgoing))
+        } else {
+            1.0 - mean3(&self.fresnel_film(1.0, self.ior, &up_normal, outgoing))
+        }
+    }
+
+    // indices of the outgoing and incoming sides of a refractive interface,
+    // against the medium the surface is nested in
+    pub(crate) fn relative_ior(&self, normal: &Vec3, outgoing: &Vec3) -> (f32, f32) {
+        if dot(normal, outgoing) >= 0.0 {
+            (self.outer_ior, self.ior)
         } else {
-            1.0 - fresnel_dielectric(self.ior, &up_normal, outgoing)
+            (self.ior, self.outer_ior)
         }
     }
 
     fn sample_refractive(&self, normal: &Vec3, outgoing: &Vec3, rnl: f32, rn: &Vec2) -> Vec3 {
-        let entering = dot(normal, outgoing) >= 0.0;
         let up_normal = if dot(normal, outgoing) <= 0.0 {
             -normal
         } else {
             *normal
         };
-        let halfway = sample_microfacet(self.roughness, &up_normal, rn, true);
-        let rel_ior = if entering { self.ior } else { 1.0 / self.ior };
-        if rnl < fresnel_dielectric(rel_ior, &halfway, outgoing) {
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
+        let (eta_i, eta_t) = self.relative_ior(normal, outgoing);
+        let rel_ior = eta_t / eta_i;
+        if rnl < mean3(&self.fresnel_film(eta_i, eta_t, &halfway, outgoing)) {
             let incoming = glm::reflect_vec(&(-outgoing), &halfway);
             if !same_hemisphere(&up_normal, outgoing, &incoming) {
                 zero3!()
@@ -437,17 +557,17 @@ impl MaterialPoint {
     }
 
     fn sample_refractive_delta(&self, normal: &Vec3, outgoing: &Vec3, rnl: f32) -> Vec3 {
-        if f32::abs(self.ior - 1.0) < 1e-3 {
+        if f32::abs(self.ior - self.outer_ior) < 1e-3 {
             return -outgoing;
         }
-        let entering = dot(normal, outgoing) >= 0.0;
         let up_normal = if dot(normal, outgoing) <= 0.0 {
             -normal
         } else {
             *normal
         };
-        let rel_ior = if entering { self.ior } else { 1.0 / self.ior };
-        if rnl < fresnel_dielectric(rel_ior, &up_normal, outgoing) {
+        let (eta_i, eta_t) = self.relative_ior(normal, outgoing);
+        let rel_ior = eta_t / eta_i;
+        if rnl < mean3(&self.fresnel_film(eta_i, eta_t, &up_normal, outgoing)) {
             glm::reflect_vec(&(-outgoing), &up_normal)
         } else {
             glm::refract_vec(&(-outgoing), &up_normal, 1.0 / rel_ior)
@@ -461,27 +581,28 @@ impl MaterialPoint {
         } else {
             *normal
         };
-        let rel_ior = if entering { self.ior } else { 1.0 / self.ior };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let (eta_i, eta_t) = self.relative_ior(normal, outgoing);
+        let rel_ior = eta_t / eta_i;
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
             let halfway = normalize(&(incoming + outgoing));
-            let f = fresnel_dielectric(rel_ior, &halfway, outgoing);
-            let d = microfacet_distribution(self.roughness, &up_normal, &halfway, true);
-            let g = microfacet_shadowing(self.roughness, &up_normal, &halfway, outgoing, incoming);
-            one3!() * f * d * g / f32::abs(4.0 * dot(normal, outgoing) * dot(normal, incoming))
+            let f = self.fresnel_film(eta_i, eta_t, &halfway, outgoing);
+            let d = microfacet_distribution(&roughness, &frame, &halfway);
+            let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
+            f * d * g / f32::abs(4.0 * dot(normal, outgoing) * dot(normal, incoming))
                 * f32::abs(dot(normal, incoming))
         } else {
             let rel_sign = if entering { 1.0 } else { -1.0 };
             let halfway = -normalize(&(rel_ior * incoming + outgoing)) * rel_sign;
-            let f = fresnel_dielectric(rel_ior, &halfway, outgoing);
-            let d = microfacet_distribution(self.roughness, &up_normal, &halfway, true);
-            let g = microfacet_shadowing(self.roughness, &up_normal, &halfway, outgoing, incoming);
+            let f = self.fresnel_film(eta_i, eta_t, &halfway, outgoing);
+            let d = microfacet_distribution(&roughness, &frame, &halfway);
+            let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
             // [Walter 2007] equation 21
-            one3!()
+            (one3!() - f)
                 * f32::abs(
                     (dot(outgoing, &halfway) * dot(incoming, &halfway))
                         / (dot(outgoing, normal) * dot(incoming, normal)),
                 )
-                * (1.0 - f)
                 * d
                 * g
                 / f32::powf(
@@ -493,26 +614,25 @@ impl MaterialPoint {
     }
 
     fn eval_refractive_delta(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
-        if f32::abs(self.ior - 1.0) < 1e-3 {
+        if f32::abs(self.ior - self.outer_ior) < 1e-3 {
             if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
                 return one3!();
             } else {
                 return zero3!();
             }
         }
-        let entering = dot(normal, outgoing) >= 0.0;
         let up_normal = if dot(normal, outgoing) <= 0.0 {
             -normal
         } else {
             *normal
         };
-        let rel_ior = if entering { self.ior } else { 1.0 / self.ior };
+        let (eta_i, eta_t) = self.relative_ior(normal, outgoing);
+        let rel_ior = eta_t / eta_i;
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
-            one3!() * fresnel_dielectric(rel_ior, &up_normal, outgoing)
+            self.fresnel_film(eta_i, eta_t, &up_normal, outgoing)
         } else {
-            one3!()
+            (one3!() - self.fresnel_film(eta_i, eta_t, &up_normal, outgoing))
                 * (1.0 / (rel_ior * rel_ior))
-                * (1.0 - fresnel_dielectric(rel_ior, &up_normal, outgoing))
         }
     }
 
@@ -523,44 +643,52 @@ impl MaterialPoint {
         } else {
             *normal
         };
-        let rel_ior = if entering { self.ior } else { 1.0 / self.ior };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let (eta_i, eta_t) = self.relative_ior(normal, outgoing);
+        let rel_ior = eta_t / eta_i;
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
             let halfway = normalize(&(incoming + outgoing));
-            fresnel_dielectric(rel_ior, &halfway, outgoing) *
-                   sample_microfacet_pdf(self.roughness, &up_normal, &halfway) /
-                   //  sample_microfacet_pdf(roughness, up_normal, halfway, outgoing) /
-                   (4.0 * f32::abs(dot(outgoing, &halfway)))
+            mean3(&self.fresnel_film(eta_i, eta_t, &halfway, outgoing))
+                * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing)
+                / (4.0 * f32::abs(dot(outgoing, &halfway)))
         } else {
             let rel_sign = if entering { 1.0 } else { -1.0 };
             let halfway = -normalize(&(rel_ior * incoming + outgoing)) * rel_sign;
+            // the refracted direction must be on the far side of the microfacet
+            if dot(&halfway, incoming) * dot(&halfway, outgoing) >= 0.0 {
+                return 0.0;
+            }
             // [Walter 2007] equation 17
-            (1.0 - fresnel_dielectric(rel_ior, &halfway, outgoing)) *
-                   sample_microfacet_pdf(self.roughness, &up_normal, &halfway) *
-                   //  sample_microfacet_pdf(roughness, up_normal, halfway, outgoing) /
-                   f32::abs(dot(&halfway, incoming)) /  // here we use incoming as from pbrt
-                   f32::powf(rel_ior * dot(&halfway, incoming) + dot(&halfway, outgoing), 2.0)
+            (1.0 - mean3(&self.fresnel_film(eta_i, eta_t, &halfway, outgoing)))
+                * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing)
+                * rel_ior
+                * rel_ior
+                * f32::abs(dot(&halfway, incoming))
+                / f32::powf(
+                    rel_ior * dot(&halfway, incoming) + dot(&halfway, outgoing),
+                    2.0,
+                )
         }
     }
 
     fn sample_refractive_pdf_delta(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
-        if f32::abs(self.ior - 1.0) < 1e-3 {
+        if f32::abs(self.ior - self.outer_ior) < 1e-3 {
             if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
                 return 1.0;
             } else {
                 return 0.0;
             }
         }
-        let entering = dot(normal, outgoing) >= 0.0;
         let up_normal = if dot(normal, outgoing) <= 0.0 {
             -normal
         } else {
             *normal
         };
-        let rel_ior = if entering { self.ior } else { 1.0 / self.ior };
+        let (eta_i, eta_t) = self.relative_ior(normal, outgoing);
         if dot(normal, incoming) * dot(normal, outgoing) >= 0.0 {
-            fresnel_dielectric(rel_ior, &up_normal, outgoing)
+            mean3(&self.fresnel_film(eta_i, eta_t, &up_normal, outgoing))
         } else {
-            1.0 - fresnel_dielectric(rel_ior, &up_normal, outgoing)
+            1.0 - mean3(&self.fresnel_film(eta_i, eta_t, &up_normal, outgoing))
         }
     }
 
@@ -575,8 +703,11 @@ impl MaterialPoint {
             &self.color,
             self.metallic,
         );
-        if rnl < mean3(&fresnel_schlick(&reflectivity, &up_normal, outgoing)) {
-            let halfway = sample_microfacet(self.roughness, &up_normal, rn, true);
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let f = mean3(&fresnel_schlick(&reflectivity, &up_normal, outgoing))
+            * microfacet_albedo(&roughness, dot(&up_normal, outgoing));
+        if rnl < f {
+            let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
             let incoming = glm::reflect_vec(&(-outgoing), &halfway);
             if !same_hemisphere(&up_normal, outgoing, &incoming) {
                 zero3!()
@@ -605,12 +736,14 @@ impl MaterialPoint {
         let f1 = fresnel_schlick(&reflectivity, &up_normal, outgoing);
         let halfway = normalize(&(incoming + outgoing));
         let f = fresnel_schlick(&reflectivity, &halfway, incoming);
-        let d = microfacet_distribution(self.roughness, &up_normal, &halfway, true);
-        let g = microfacet_shadowing(self.roughness, &up_normal, &halfway, outgoing, incoming);
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let d = microfacet_distribution(&roughness, &frame, &halfway);
+        let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
         vec_comp_mul!(self.color * (1.0 - self.metallic), &((one3!() - f1) / PI))
             * f32::abs(dot(&up_normal, incoming))
             + f * d * g / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, incoming))
                 * f32::abs(dot(&up_normal, incoming))
+            + microfacet_compensation(&roughness, &reflectivity, &up_normal, outgoing, incoming)
     }
 
     fn sample_gltfpbr_pdf(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
@@ -622,18 +755,554 @@ impl MaterialPoint {
         } else {
             *normal
         };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
         let halfway = normalize(&(outgoing + incoming));
         let reflectivity = glm::lerp(
             &eta_to_reflectivity(&vec3(self.ior, self.ior, self.ior)),
             &self.color,
             self.metallic,
         );
-        let f = mean3(&fresnel_schlick(&reflectivity, &up_normal, outgoing));
-        f * sample_microfacet_pdf(self.roughness, &up_normal, &halfway)
+        let f = mean3(&fresnel_schlick(&reflectivity, &up_normal, outgoing))
+            * microfacet_albedo(&roughness, dot(&up_normal, outgoing));
+        f * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing)
+            / (4.0 * f32::abs(dot(outgoing, &halfway)))
+            + (1.0 - f) * sample_hemisphere_cos_pdf(&up_normal, incoming)
+    }
+
+    // alphas and tangent frame of the microfacet lobes
+    fn microfacet_lobe(&self, up_normal: &Vec3) -> (Vec2, Mat3) {
+        (
+            anisotropic_roughness(self.roughness, self.anisotropy),
+            microfacet_frame(up_normal, &self.tangent),
+        )
+    }
+
+    fn conductor_eta(&self) -> (Vec3, Vec3) {
+        if is_null(&self.eta, epsilon()) && is_null(&self.etak, epsilon()) {
+            (reflectivity_to_eta(&self.color), zero3!())
+        } else {
+            (self.eta, self.etak)
+        }
+    }
+
+    fn sample_conductor(&self, normal: &Vec3, outgoing: &Vec3, rnl: f32, rn: &Vec2) -> Vec3 {
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        if rnl >= microfacet_albedo(&roughness, dot(&up_normal, outgoing)) {
+            return sample_hemisphere_cos(&up_normal, rn);
+        }
+        let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
+        let incoming = glm::reflect_vec(&(-outgoing), &halfway);
+        if !same_hemisphere(&up_normal, outgoing, &incoming) {
+            zero3!()
+        } else {
+            incoming
+        }
+    }
+
+    fn eval_conductor(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return zero3!();
+        }
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let (eta, etak) = self.conductor_eta();
+        let halfway = normalize(&(incoming + outgoing));
+        let f = self.fresnel_film_conductor(&eta, &etak, &halfway, incoming);
+        let d = microfacet_distribution(&roughness, &frame, &halfway);
+        let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
+        let reflectivity = fresnel_conductor(
+            &(eta / self.outer_ior),
+            &(etak / self.outer_ior),
+            &up_normal,
+            &up_normal,
+        );
+        f * d * g / (4.0 * dot(&up_normal, outgoing) * dot(&up_normal, incoming))
+            * f32::abs(dot(&up_normal, incoming))
+            + microfacet_compensation(&roughness, &reflectivity, &up_normal, outgoing, incoming)
+    }
+
+    fn eval_conductor_delta(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return zero3!();
+        }
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let (eta, etak) = self.conductor_eta();
+        self.fresnel_film_conductor(&eta, &etak, &up_normal, outgoing)
+    }
+
+    fn sample_conductor_pdf(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return 0.0;
+        }
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let halfway = normalize(&(outgoing + incoming));
+        let f = microfacet_albedo(&roughness, dot(&up_normal, outgoing));
+        f * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing)
             / (4.0 * f32::abs(dot(outgoing, &halfway)))
             + (1.0 - f) * sample_hemisphere_cos_pdf(&up_normal, incoming)
     }
 
+    // fresnel of the interface from eta_i to eta_t, modulated by interference
+    // in the thin film when present
+    fn fresnel_film(&self, eta_i: f32, eta_t: f32, normal: &Vec3, outgoing: &Vec3) -> Vec3 {
+        if self.film_thickness <= 0.0 {
+            return one3!() * fresnel_dielectric(eta_t / eta_i, normal, outgoing);
+        }
+        self.thin_film(
+            eta_i,
+            &vec3(eta_t, eta_t, eta_t),
+            &zero3!(),
+            f32::abs(dot(normal, outgoing)),
+        )
+    }
+
+    // same for conductors, whose indices are absolute and seen from the
+    // medium around the surface
+    fn fresnel_film_conductor(
+        &self,
+        eta: &Vec3,
+        etak: &Vec3,
+        normal: &Vec3,
+        outgoing: &Vec3,
+    ) -> Vec3 {
+        if self.film_thickness <= 0.0 {
+            return fresnel_conductor(
+                &(eta / self.outer_ior),
+                &(etak / self.outer_ior),
+                normal,
+                outgoing,
+            );
+        }
+        self.thin_film(self.outer_ior, eta, etak, f32::abs(dot(normal, outgoing)))
+    }
+
+    // film reflectance from the response tabulated for the material, or
+    // integrated on the spot without one
+    fn thin_film(&self, eta_i: f32, eta: &Vec3, etak: &Vec3, cosine: f32) -> Vec3 {
+        match &self.film {
+            Some(film) => film.eval(eta_i, eta, etak, self.film_thickness, cosine),
+            None => fresnel_thin_film(eta_i, self.film_ior, eta, etak, self.film_thickness, cosine),
+        }
+    }
+
+    // alphas and frame of the isotropic clearcoat lobe
+    fn clearcoat_lobe(&self, up_normal: &Vec3) -> (Vec2, Mat3) {
+        (
+            vec2(self.clearcoat_roughness, self.clearcoat_roughness),
+            basis_fromz(up_normal),
+        )
+    }
+
+    // specular reflectivity of the principled base, a tinted dielectric that
+    // turns into the base color as the material becomes metallic
+    fn principled_reflectivity(&self) -> Vec3 {
+        let dielectric = vec_comp_mul!(
+            eta_to_reflectivity(&vec3(self.ior, self.ior, self.ior)),
+            &self.specular
+        );
+        lerp(&glm::min(&dielectric, 1.0), &self.color, self.metallic)
+    }
+
+    // probabilities of the principled lobes from the outgoing direction, in
+    // order clearcoat, sheen, specular, diffuse and transmission; each layer
+    // takes the energy reflected by the layers above it, and the transmissive
+    // part of the base is a rough dielectric through ior in place of the
+    // specular and diffuse lobes
+    fn principled_weights(&self, up_normal: &Vec3, outgoing: &Vec3) -> [f32; 5] {
+        let cosine = dot(up_normal, outgoing);
+        let (roughness, _) = self.microfacet_lobe(up_normal);
+        let coat = self.clearcoat * fresnel_dielectric(CLEARCOAT_IOR, up_normal, outgoing);
+        let sheen = (1.0 - coat) * self.sheen.max() * sheen_albedo(self.sheen_roughness, cosine);
+        let below = 1.0 - coat - sheen;
+        let transmission = below * (1.0 - self.metallic) * self.transmission;
+        let opaque = below - transmission;
+        let specular = opaque
+            * mean3(&fresnel_schlick(
+                &self.principled_reflectivity(),
+                up_normal,
+                outgoing,
+            ))
+            * microfacet_albedo(&roughness, cosine);
+        [coat, sheen, specular, opaque - specular, transmission]
+    }
+
+    fn sample_principled(&self, normal: &Vec3, outgoing: &Vec3, rnl: f32, rn: &Vec2) -> Vec3 {
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let [coat, sheen, specular, diffuse, transmission] =
+            self.principled_weights(&up_normal, outgoing);
+        let opaque = coat + sheen + specular + diffuse;
+        if rnl >= opaque {
+            // reuse the remaining random number to pick reflection or refraction
+            let rnl = f32::min((rnl - opaque) / transmission, 1.0 - f32::EPSILON);
+            return self.sample_refractive(normal, outgoing, rnl, rn);
+        }
+        let (roughness, frame) = if rnl < coat {
+            self.clearcoat_lobe(&up_normal)
+        } else {
+            self.microfacet_lobe(&up_normal)
+        };
+        if rnl < coat || (rnl >= coat + sheen && rnl < coat + sheen + specular) {
+            let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
+            let incoming = glm::reflect_vec(&(-outgoing), &halfway);
+            if !same_hemisphere(&up_normal, outgoing, &incoming) {
+                zero3!()
+            } else {
+                incoming
+            }
+        } else {
+            sample_hemisphere_cos(&up_normal, rn)
+        }
+    }
+
+    fn eval_principled(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let cos_o = dot(&up_normal, outgoing);
+        let reflectivity = self.principled_reflectivity();
+        let coat_attenuation =
+            1.0 - self.clearcoat * fresnel_dielectric(CLEARCOAT_IOR, &up_normal, outgoing);
+        let sheen_attenuation = 1.0 - self.sheen.max() * sheen_albedo(self.sheen_roughness, cos_o);
+        let transmission = (1.0 - self.metallic) * self.transmission;
+        // the base color tints each crossing by its square root, so that
+        // entering and leaving a solid object gives the color
+        let dielectric = self.eval_refractive(normal, outgoing, incoming)
+            * transmission
+            * coat_attenuation
+            * sheen_attenuation;
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return vec_comp_mul!(dielectric, &glm::sqrt(&self.color));
+        }
+        let base = vec_comp_mul!(
+            self.color * (1.0 - self.metallic),
+            &(one3!() - fresnel_schlick(&reflectivity, &up_normal, outgoing))
+        ) * coat_attenuation
+            * sheen_attenuation;
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let cos_i = dot(&up_normal, incoming);
+        let halfway = normalize(&(incoming + outgoing));
+
+        let (coat_roughness, coat_frame) = self.clearcoat_lobe(&up_normal);
+        let coat = self.clearcoat
+            * fresnel_dielectric(CLEARCOAT_IOR, &halfway, incoming)
+            * microfacet_distribution(&coat_roughness, &coat_frame, &halfway)
+            * microfacet_shadowing(&coat_roughness, &coat_frame, &halfway, outgoing, incoming)
+            / (4.0 * cos_o * cos_i)
+            * f32::abs(cos_i);
+
+        let sheen = self.sheen
+            * sheen_distribution(self.sheen_roughness, &up_normal, &halfway)
+            * sheen_visibility(&up_normal, outgoing, incoming)
+            * f32::abs(cos_i);
+
+        let f = fresnel_schlick(&reflectivity, &halfway, incoming);
+        let d = microfacet_distribution(&roughness, &frame, &halfway);
+        let g = microfacet_shadowing(&roughness, &frame, &halfway, outgoing, incoming);
+        let specular = (f * d * g / (4.0 * cos_o * cos_i) * f32::abs(cos_i)
+            + microfacet_compensation(&roughness, &reflectivity, &up_normal, outgoing, incoming))
+            * (1.0 - transmission);
+
+        let diffuse = base * (1.0 - transmission) / PI * f32::abs(cos_i);
+
+        one3!() * coat
+            + (sheen + specular * sheen_attenuation) * coat_attenuation
+            + diffuse
+            + dielectric
+    }
+
+    fn sample_principled_pdf(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let [coat, sheen, specular, diffuse, transmission] =
+            self.principled_weights(&up_normal, outgoing);
+        let dielectric = transmission * self.sample_refractive_pdf(normal, outgoing, incoming);
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return dielectric;
+        }
+        let (roughness, frame) = self.microfacet_lobe(&up_normal);
+        let halfway = normalize(&(outgoing + incoming));
+        let (coat_roughness, coat_frame) = self.clearcoat_lobe(&up_normal);
+        (coat * sample_microfacet_pdf(&coat_roughness, &coat_frame, &halfway, outgoing)
+            + specular * sample_microfacet_pdf(&roughness, &frame, &halfway, outgoing))
+            / (4.0 * f32::abs(dot(outgoing, &halfway)))
+            + (sheen + diffuse) * sample_hemisphere_cos_pdf(&up_normal, incoming)
+            + dielectric
+    }
+
+    fn sample_measured(&self, normal: &Vec3, outgoing: &Vec3, rnl: f32, rn: &Vec2) -> Vec3 {
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let brdf = match &self.brdf {
+            Some(brdf) => brdf,
+            None => return zero3!(),
+        };
+        if rnl < MEASURED_COSINE_FRACTION {
+            return sample_hemisphere_cos(&up_normal, rn);
+        }
+        let frame = basis_fromz(&up_normal);
+        let local_outgoing = frame.transpose() * outgoing;
+        transform_direction_mat(&frame, &brdf.sample(&local_outgoing, rn))
+    }
+
+    fn eval_measured(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return zero3!();
+        }
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let brdf = match &self.brdf {
+            Some(brdf) => brdf,
+            None => return zero3!(),
+        };
+        let frame = basis_fromz(&up_normal);
+        brdf.eval(
+            &(frame.transpose() * outgoing),
+            &(frame.transpose() * incoming),
+        ) * f32::abs(dot(&up_normal, incoming))
+    }
+
+    fn sample_measured_pdf(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
+        if dot(normal, incoming) * dot(normal, outgoing) <= 0.0 {
+            return 0.0;
+        }
+        let up_normal = if dot(normal, outgoing) <= 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        let brdf = match &self.brdf {
+            Some(brdf) => brdf,
+            None => return 0.0,
+        };
+        let frame = basis_fromz(&up_normal);
+        MEASURED_COSINE_FRACTION * sample_hemisphere_cos_pdf(&up_normal, incoming)
+            + (1.0 - MEASURED_COSINE_FRACTION)
+                * brdf.sample_pdf(
+                    &(frame.transpose() * outgoing),
+                    &(frame.transpose() * incoming),
+                )
+    }
+
+    // hair frame with the fiber direction as x and the direction facing the
+    // viewer across the fiber as z, together with the offset of the hit
+    // across the width; flat curves face the viewer and store the offset,
+    // round curves pass their true normal, whose tilt away from the viewer
+    // gives the offset
+    fn hair_frame(&self, normal: &Vec3, outgoing: &Vec3) -> (Mat3, f32) {
+        let tangent = if is_null(&self.tangent, epsilon()) {
+            basis_fromz(normal).column(0).into()
+        } else {
+            normalize(&self.tangent)
+        };
+        let facing = outgoing - tangent * dot(outgoing, &tangent);
+        let facing = if glm::length(&facing) > 1e-4 {
+            normalize(&facing)
+        } else {
+            *normal
+        };
+        let tangent = orthonormalize(&tangent, &facing);
+        let bitangent = cross(&facing, &tangent);
+        let offset = f32::clamp(self.hair_offset + dot(normal, &bitangent), -1.0, 1.0);
+        (
+            make_mat3(&[tangent.as_slice(), bitangent.as_slice(), facing.as_slice()].concat()),
+            offset,
+        )
+    }
+
+    fn hair_lobe(&self, offset: f32) -> HairLobe {
+        // roughness is stored squared
+        let beta_m = f32::sqrt(self.roughness);
+        let beta_n = self.azimuthal_roughness;
+        let v0 = f32::powi(
+            0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20),
+            2,
+        );
+        let s = f32::sqrt(PI / 8.0)
+            * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+        let mut sin_2k_alpha = [f32::sin(self.tilt.to_radians()), 0.0, 0.0];
+        let mut cos_2k_alpha = [f32::sqrt(1.0 - sin_2k_alpha[0] * sin_2k_alpha[0]), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1] * cos_2k_alpha[i - 1]
+                - sin_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+        }
+        let absorption = if self.melanin > 0.0 {
+            hair_melanin_absorption(self.melanin, self.melanin_redness)
+        } else {
+            hair_color_absorption(&self.color, beta_n)
+        };
+        HairLobe {
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+            absorption,
+            h: offset,
+            gamma_o: f32::asin(offset),
+            eta: self.ior,
+        }
+    }
+
+    fn sample_hair(&self, normal: &Vec3, outgoing: &Vec3, rnl: f32, rn: &Vec2) -> Vec3 {
+        let (frame, offset) = self.hair_frame(normal, outgoing);
+        let lobe = self.hair_lobe(offset);
+        let local = frame.transpose() * outgoing;
+        let sin_theta_o = local.x;
+        let cos_theta_o = f32::sqrt(f32::max(1.0 - sin_theta_o * sin_theta_o, 0.0));
+        let phi_o = f32::atan2(local.z, local.y);
+
+        // pick the scattering order, reusing the remainder of the random number
+        let pdfs = lobe.order_pdfs(cos_theta_o, sin_theta_o);
+        let mut rnl = rnl;
+        let mut p = HAIR_MAX_ORDER;
+        for (order, &pdf) in pdfs.iter().enumerate().take(HAIR_MAX_ORDER) {
+            if rnl < pdf {
+                p = order;
+                break;
+            }
+            rnl -= pdf;
+        }
+        let rnp = f32::clamp(rnl / pdfs[p], 0.0, 1.0 - f32::EPSILON);
+
+        // longitudinal scattering around the tilted specular cone
+        let (sin_theta_op, cos_theta_op) = lobe.tilt(p, sin_theta_o, cos_theta_o);
+        let u = f32::max(rn.x, 1e-5);
+        let cos_theta = 1.0 + lobe.v[p] * f32::ln(u + (1.0 - u) * f32::exp(-2.0 / lobe.v[p]));
+        let sin_theta = f32::sqrt(f32::max(1.0 - cos_theta * cos_theta, 0.0));
+        let cos_phi = f32::cos(2.0 * PI * rn.y);
+        let sin_theta_i = f32::clamp(
+            -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op,
+            -1.0,
+            1.0,
+        );
+        let cos_theta_i = f32::sqrt(f32::max(1.0 - sin_theta_i * sin_theta_i, 0.0));
+
+        // azimuthal scattering
+        let gamma_t = lobe.gamma_t(sin_theta_o, cos_theta_o);
+        let dphi = if p < HAIR_MAX_ORDER {
+            hair_phi(p, lobe.gamma_o, gamma_t) + sample_trimmed_logistic(rnp, lobe.s, -PI, PI)
+        } else {
+            2.0 * PI * rnp
+        };
+        let phi_i = phi_o + dphi;
+        let incoming = vec3(
+            sin_theta_i,
+            cos_theta_i * f32::cos(phi_i),
+            cos_theta_i * f32::sin(phi_i),
+        );
+        transform_direction_mat(&frame, &incoming)
+    }
+
+    fn eval_hair(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> Vec3 {
+        let (frame, offset) = self.hair_frame(normal, outgoing);
+        let lobe = self.hair_lobe(offset);
+        let (local_o, local_i) = (frame.transpose() * outgoing, frame.transpose() * incoming);
+        let sin_theta_o = local_o.x;
+        let cos_theta_o = f32::sqrt(f32::max(1.0 - sin_theta_o * sin_theta_o, 0.0));
+        let phi_o = f32::atan2(local_o.z, local_o.y);
+        let sin_theta_i = local_i.x;
+        let cos_theta_i = f32::sqrt(f32::max(1.0 - sin_theta_i * sin_theta_i, 0.0));
+        let phi_i = f32::atan2(local_i.z, local_i.y);
+
+        let attenuations = lobe.attenuations(cos_theta_o, sin_theta_o);
+        let gamma_t = lobe.gamma_t(sin_theta_o, cos_theta_o);
+        let phi = phi_i - phi_o;
+        // the lobes already include the cosine of the incoming direction
+        let mut sum = zero3!();
+        for (p, attenuation) in attenuations.iter().enumerate().take(HAIR_MAX_ORDER) {
+            let (sin_theta_op, cos_theta_op) = lobe.tilt(p, sin_theta_o, cos_theta_o);
+            sum += attenuation
+                * hair_longitudinal(
+                    cos_theta_i,
+                    cos_theta_op,
+                    sin_theta_i,
+                    sin_theta_op,
+                    lobe.v[p],
+                )
+                * hair_azimuthal(phi, p, lobe.s, lobe.gamma_o, gamma_t);
+        }
+        sum + attenuations[HAIR_MAX_ORDER]
+            * hair_longitudinal(
+                cos_theta_i,
+                cos_theta_o,
+                sin_theta_i,
+                sin_theta_o,
+                lobe.v[HAIR_MAX_ORDER],
+            )
+            / (2.0 * PI)
+    }
+
+    fn sample_hair_pdf(&self, normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
+        let (frame, offset) = self.hair_frame(normal, outgoing);
+        let lobe = self.hair_lobe(offset);
+        let (local_o, local_i) = (frame.transpose() * outgoing, frame.transpose() * incoming);
+        let sin_theta_o = local_o.x;
+        let cos_theta_o = f32::sqrt(f32::max(1.0 - sin_theta_o * sin_theta_o, 0.0));
+        let phi_o = f32::atan2(local_o.z, local_o.y);
+        let sin_theta_i = local_i.x;
+        let cos_theta_i = f32::sqrt(f32::max(1.0 - sin_theta_i * sin_theta_i, 0.0));
+        let phi_i = f32::atan2(local_i.z, local_i.y);
+
+        let pdfs = lobe.order_pdfs(cos_theta_o, sin_theta_o);
+        let gamma_t = lobe.gamma_t(sin_theta_o, cos_theta_o);
+        let phi = phi_i - phi_o;
+        let mut pdf = 0.0;
+        for (p, order_pdf) in pdfs.iter().enumerate().take(HAIR_MAX_ORDER) {
+            let (sin_theta_op, cos_theta_op) = lobe.tilt(p, sin_theta_o, cos_theta_o);
+            pdf += order_pdf
+                * hair_longitudinal(
+                    cos_theta_i,
+                    cos_theta_op,
+                    sin_theta_i,
+                    sin_theta_op,
+                    lobe.v[p],
+                )
+                * hair_azimuthal(phi, p, lobe.s, lobe.gamma_o, gamma_t);
+        }
+        pdf + pdfs[HAIR_MAX_ORDER]
+            * hair_longitudinal(
+                cos_theta_i,
+                cos_theta_o,
+                sin_theta_i,
+                sin_theta_o,
+                lobe.v[HAIR_MAX_ORDER],
+            )
+            / (2.0 * PI)
+    }
+
     fn sample_passthrough(&self, outgoing: &Vec3) -> Vec3 {
         -outgoing
     }
@@ -722,6 +1391,30 @@ impl MaterialPoint {
     }
 }
 
+// single-scattering albedo that gives the requested multiple-scattering
+// albedo on a semi-infinite slab [Chiang et al. 2016]
+pub fn subsurface_albedo(color: &Vec3) -> Vec3 {
+    let invert = |albedo: f32| -> f32 {
+        let albedo = albedo.clamp(0.0, 1.0);
+        let s = 4.09712 + 4.20863 * albedo
+            - f32::sqrt(9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo);
+        1.0 - s * s
+    };
+    vec3(invert(color.x), invert(color.y), invert(color.z))
+}
+
+// extinction that makes the radius the scattering distance seen on the
+// surface, scaled by the albedo dependent fit of the diffusion profile
+// [Chiang et al. 2016]
+pub fn subsurface_density(color: &Vec3, radius: &Vec3) -> Vec3 {
+    let scale = |albedo: f32| -> f32 {
+        let albedo = albedo.clamp(0.0, 1.0);
+        1.9 - albedo + 3.5 * (albedo - 0.8) * (albedo - 0.8)
+    };
+    let scale = vec3(scale(color.x), scale(color.y), scale(color.z));
+    vec_comp_div!(one3!(), &glm::max(&vec_comp_mul!(radius, &scale), 0.0001))
+}
+
 #[inline(always)]
 fn reflectivity_to_eta(reflectivity: &Vec3) -> Vec3 {
     let r_clamp = glm::clamp(reflectivity, 0.0, 0.99);
@@ -732,7 +1425,7 @@ fn reflectivity_to_eta(reflectivity: &Vec3) -> Vec3 {
 }
 
 #[inline(always)]
-fn eta_to_reflectivity(eta: &Vec3) -> Vec3 {
+pub(crate) fn eta_to_reflectivity(eta: &Vec3) -> Vec3 {
     let eta_minus = vec_comp_mul!(eta - one3!(), &(eta - one3!()));
     let eta_plus = vec_comp_mul!(eta + one3!(), &(eta + one3!()));
     vec_comp_div!(eta_minus, &eta_plus)
@@ -813,93 +1506,686 @@ fn fresnel_conductor(eta: &Vec3, etak: &Vec3, normal: &Vec3, outgoing: &Vec3) ->
     (rp + rs) / 2.0
 }
 
+// complex amplitudes of the thin film interference
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Complex {
+        Complex { re, im }
+    }
+
+    fn real(re: f32) -> Complex {
+        Complex { re, im: 0.0 }
+    }
+
+    fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Complex {
+        let norm = f32::sqrt(self.norm_sqr());
+        let re = f32::sqrt(f32::max((norm + self.re) / 2.0, 0.0));
+        let im = f32::sqrt(f32::max((norm - self.re) / 2.0, 0.0));
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    // e^(i z), decaying when z has a positive imaginary part
+    fn exp_i(self) -> Complex {
+        let scale = f32::exp(-self.im);
+        Complex::new(scale * f32::cos(self.re), scale * f32::sin(self.re))
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let norm = other.norm_sqr();
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / norm,
+            (self.im * other.re - self.re * other.im) / norm,
+        )
+    }
+}
+
+// reflectance of a film between the incident medium and a possibly absorbing
+// base, summing the Airy series of the multiple reflections in the film;
+// thickness and wavelength are in nm, this is the spectral form of the film
+pub fn thin_film_reflectance(
+    eta_i: f32,
+    film: f32,
+    eta_t: f32,
+    etak_t: f32,
+    thickness: f32,
+    cosine: f32,
+    wavelength: f32,
+) -> f32 {
+    let one = Complex::real(1.0);
+    let (eta1, eta2, eta3) = (
+        Complex::real(eta_i),
+        Complex::real(film),
+        Complex::new(eta_t, etak_t),
+    );
+    let sin2 = Complex::real(eta_i * eta_i * (1.0 - cosine * cosine));
+    let cos1 = Complex::real(cosine);
+    let cos2 = (one - sin2 / (eta2 * eta2)).sqrt();
+    let cos3 = (one - sin2 / (eta3 * eta3)).sqrt();
+
+    // amplitude coefficients at the top and bottom interfaces
+    let rs12 = (eta1 * cos1 - eta2 * cos2) / (eta1 * cos1 + eta2 * cos2);
+    let rp12 = (eta2 * cos1 - eta1 * cos2) / (eta2 * cos1 + eta1 * cos2);
+    let rs23 = (eta2 * cos2 - eta3 * cos3) / (eta2 * cos2 + eta3 * cos3);
+    let rp23 = (eta3 * cos2 - eta2 * cos3) / (eta3 * cos2 + eta2 * cos3);
+
+    // phase of one round trip through the film
+    let shift = (eta2 * cos2 * Complex::real(4.0 * PI * thickness / wavelength)).exp_i();
+    let airy =
+        |r12: Complex, r23: Complex| ((r12 + r23 * shift) / (one + r12 * r23 * shift)).norm_sqr();
+    f32::clamp((airy(rs12, rs23) + airy(rp12, rp23)) / 2.0, 0.0, 1.0)
+}
+
+const FILM_WAVELENGTHS: usize = 40;
+
+// rgb weights of wavelengths evenly spaced in [380, 780] nm, from the fit of
+// the CIE matching functions of [Wyman et al. 2013], normalized so that a
+// constant spectrum maps to white
+fn film_weights() -> &'static [(f32, Vec3)] {
+    static WEIGHTS: OnceLock<Vec<(f32, Vec3)>> = OnceLock::new();
+    WEIGHTS.get_or_init(|| {
+        let lobe = |wavelength: f32, mean: f32, left: f32, right: f32| {
+            let t = (wavelength - mean) / if wavelength < mean { left } else { right };
+            f32::exp(-0.5 * t * t)
+        };
+        let weights: Vec<(f32, Vec3)> = (0..FILM_WAVELENGTHS)
+            .map(|i| {
+                let w = 380.0 + 400.0 * (i as f32 + 0.5) / FILM_WAVELENGTHS as f32;
+                let x = 1.056 * lobe(w, 599.8, 37.9, 31.0) + 0.362 * lobe(w, 442.0, 16.0, 26.7)
+                    - 0.065 * lobe(w, 501.1, 20.4, 26.2);
+                let y = 0.821 * lobe(w, 568.8, 46.9, 40.5) + 0.286 * lobe(w, 530.9, 16.3, 31.1);
+                let z = 1.217 * lobe(w, 437.0, 11.8, 36.0) + 0.681 * lobe(w, 459.0, 26.0, 13.8);
+                let rgb = vec3(
+                    3.2406 * x - 1.5372 * y - 0.4986 * z,
+                    -0.9689 * x + 1.8758 * y + 0.0415 * z,
+                    0.0557 * x - 0.2040 * y + 1.0570 * z,
+                );
+                (w, rgb)
+            })
+            .collect();
+        let total = weights.iter().fold(zero3!(), |sum, (_, rgb)| sum + rgb);
+        weights
+            .into_iter()
+            .map(|(w, rgb)| (w, vec_comp_div!(rgb, &total)))
+            .collect()
+    })
+}
+
+// rgb quantities are taken at the same wavelengths used for conductors and
+// interpolated in between
+fn rgb_at_wavelength(value: &Vec3, wavelength: f32) -> f32 {
+    if wavelength <= 465.0 {
+        value.z
+    } else if wavelength <= 532.0 {
+        value.z + (value.y - value.z) * (wavelength - 465.0) / (532.0 - 465.0)
+    } else if wavelength <= 630.0 {
+        value.y + (value.x - value.y) * (wavelength - 532.0) / (630.0 - 532.0)
+    } else {
+        value.x
+    }
+}
+
+// rgb form of the film reflectance, integrating the spectral reflectance
+// against the color matching functions
+pub fn fresnel_thin_film(
+    eta_i: f32,
+    film: f32,
+    eta: &Vec3,
+    etak: &Vec3,
+    thickness: f32,
+    cosine: f32,
+) -> Vec3 {
+    let reflectance = film_weights()
+        .iter()
+        .fold(zero3!(), |sum, (wavelength, weight)| {
+            sum + weight
+                * thin_film_reflectance(
+                    eta_i,
+                    film,
+                    rgb_at_wavelength(eta, *wavelength),
+                    rgb_at_wavelength(etak, *wavelength),
+                    thickness,
+                    cosine,
+                    *wavelength,
+                )
+        });
+    clamp(&reflectance, 0.0, 1.0)
+}
+
+// reflectances by thickness, then cosine
+type FilmTable = Arc<Vec<Vec3>>;
+
+// rgb film reflectance of a material tabulated over thickness and cosine,
+// since integrating the spectrum on every lookup is too slow to render;
+// there is a table for each set of indices around the film, and index sets
+// past the limit, like those of textured colors, or films thicker than the
+// material's are integrated directly
+#[derive(Debug)]
+pub struct FilmResponse {
+    film: f32,
+    max_thickness: f32,
+    thicknesses: usize,
+    tables: RwLock<Vec<([f32; 7], FilmTable)>>,
+}
+
+impl FilmResponse {
+    pub fn new(film: f32, max_thickness: f32) -> FilmResponse {
+        let thicknesses = (f32::ceil(max_thickness / FILM_THICKNESS_STEP) as usize + 1)
+            .clamp(2, FILM_MAX_THICKNESSES);
+        FilmResponse {
+            film,
+            max_thickness,
+            thicknesses,
+            tables: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn eval(&self, eta_i: f32, eta: &Vec3, etak: &Vec3, thickness: f32, cosine: f32) -> Vec3 {
+        let table = if thickness <= self.max_thickness {
+            self.table(eta_i, eta, etak)
+        } else {
+            None
+        };
+        let Some(table) = table else {
+            return fresnel_thin_film(eta_i, self.film, eta, etak, thickness, cosine);
+        };
+        let t = thickness / self.max_thickness * (self.thicknesses - 1) as f32;
+        let c = cosine.clamp(0.0, 1.0) * (FILM_COSINES - 1) as f32;
+        let i = usize::min(t as usize, self.thicknesses - 2);
+        let j = usize::min(c as usize, FILM_COSINES - 2);
+        let at = |i: usize, j: usize| &table[i * FILM_COSINES + j];
+        lerp(
+            &lerp(at(i, j), at(i, j + 1), c - j as f32),
+            &lerp(at(i + 1, j), at(i + 1, j + 1), c - j as f32),
+            t - i as f32,
+        )
+    }
+
+    fn table(&self, eta_i: f32, eta: &Vec3, etak: &Vec3) -> Option<FilmTable> {
+        let key = [eta_i, eta.x, eta.y, eta.z, etak.x, etak.y, etak.z];
+        let find = |tables: &[([f32; 7], FilmTable)]| {
+            tables
+                .iter()
+                .find(|(other, _)| *other == key)
+                .map(|(_, table)| table.clone())
+        };
+        if let Some(table) = find(&self.tables.read()) {
+            return Some(table);
+        }
+        let mut tables = self.tables.write();
+        if let Some(table) = find(&tables) {
+            return Some(table);
+        }
+        if tables.len() >= FILM_MAX_TABLES {
+            return None;
+        }
+        let table = Arc::new(
+            (0..self.thicknesses * FILM_COSINES)
+                .map(|idx| {
+                    let (i, j) = (idx / FILM_COSINES, idx % FILM_COSINES);
+                    let thickness = self.max_thickness * i as f32 / (self.thicknesses - 1) as f32;
+                    let cosine = j as f32 / (FILM_COSINES - 1) as f32;
+                    fresnel_thin_film(eta_i, self.film, eta, etak, thickness, cosine)
+                })
+                .collect::<Vec<_>>(),
+        );
+        tables.push((key, table.clone()));
+        Some(table)
+    }
+}
+
+fn same_hemisphere(normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> bool {
+    dot(normal, outgoing) * dot(normal, incoming) >= 0.0
+}
+
+// tangent frame of the microfacet lobes, columns are tangent, bitangent and
+// normal; without a tangent any frame around the normal is used
+fn microfacet_frame(normal: &Vec3, tangent: &Vec3) -> Mat3 {
+    if is_null(tangent, epsilon()) || f32::abs(dot(normal, tangent)) > 0.9999 {
+        return basis_fromz(normal);
+    }
+    let x = orthonormalize(tangent, normal);
+    let y = cross(normal, &x);
+    make_mat3(&[x.as_slice(), y.as_slice(), normal.as_slice()].concat())
+}
+
+// [Burley 2012] mapping from roughness and anisotropy to the GGX alphas along
+// the tangent and bitangent, roughness is already squared
+#[inline(always)]
+fn anisotropic_roughness(roughness: f32, anisotropy: f32) -> Vec2 {
+    let aspect = f32::sqrt(1.0 - 0.9 * anisotropy.clamp(-1.0, 1.0));
+    vec2(
+        f32::max(roughness / aspect, 1e-4),
+        f32::max(roughness * aspect, 1e-4),
+    )
+}
+
+fn microfacet_distribution(roughness: &Vec2, frame: &Mat3, halfway: &Vec3) -> f32 {
+    let local = frame.transpose() * halfway;
+    if local.z <= 0.0 {
+        return 0.0;
+    }
+    let (ax, ay) = (roughness.x, roughness.y);
+    let e = (local.x * local.x) / (ax * ax) + (local.y * local.y) / (ay * ay) + local.z * local.z;
+    1.0 / (PI * ax * ay * e * e)
+}
+
 fn microfacet_shadowing(
-    roughness: f32,
-    normal: &Vec3,
+    roughness: &Vec2,
+    frame: &Mat3,
     halfway: &Vec3,
     outgoing: &Vec3,
     incoming: &Vec3,
 ) -> f32 {
-    microfacet_shadowing1(roughness, normal, halfway, outgoing, true)
-        * microfacet_shadowing1(roughness, normal, halfway, incoming, true)
+    microfacet_shadowing1(roughness, frame, halfway, outgoing)
+        * microfacet_shadowing1(roughness, frame, halfway, incoming)
 }
 
-fn microfacet_shadowing1(
-    roughness: f32,
-    normal: &Vec3,
-    halfway: &Vec3,
-    direction: &Vec3,
-    ggx: bool,
-) -> f32 {
-    // https://google.github.io/filament/Filament.html#materialsystem/specularbrdf
-    // http://graphicrants.blogspot.com/2013/08/specular-brdf-reference.html
-    // https://github.com/KhronosGroup/glTF/tree/master/specification/2.0#appendix-b-brdf-implementation
-    let cosine = dot(normal, direction);
-    let cosineh = dot(halfway, direction);
-    if cosine * cosineh <= 0.0 {
+fn microfacet_shadowing1(roughness: &Vec2, frame: &Mat3, halfway: &Vec3, direction: &Vec3) -> f32 {
+    let local = frame.transpose() * direction;
+    if local.z * dot(halfway, direction) <= 0.0 {
         return 0.0;
     }
-    let roughness2 = roughness * roughness;
-    let cosine2 = cosine * cosine;
-    if ggx {
-        2.0 * f32::abs(cosine)
-            / (f32::abs(cosine) + f32::sqrt(cosine2 - roughness2 * cosine2 + roughness2))
+    let (ax, ay) = (roughness.x, roughness.y);
+    let tan2 = (ax * ax * local.x * local.x + ay * ay * local.y * local.y) / (local.z * local.z);
+    2.0 / (1.0 + f32::sqrt(1.0 + tan2))
+}
+
+// visible normal sampling [Heitz 2018], the outgoing direction is stretched
+// to the unit roughness configuration and the projected disk is sampled
+fn sample_microfacet(roughness: &Vec2, frame: &Mat3, outgoing: &Vec3, rn: &Vec2) -> Vec3 {
+    let local = frame.transpose() * outgoing;
+    let (ax, ay) = (roughness.x, roughness.y);
+    let vh = normalize(&vec3(ax * local.x, ay * local.y, f32::abs(local.z)));
+    let lensq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if lensq > 0.0 {
+        vec3(-vh.y, vh.x, 0.0) / f32::sqrt(lensq)
     } else {
-        let ci = f32::abs(cosine) / (roughness * f32::sqrt(1.0 - cosine2));
-        if ci < 1.6 {
-            (3.535 * ci + 2.181 * ci * ci) / (1.0 + 2.276 * ci + 2.577 * ci * ci)
-        } else {
-            1.0
+        vec3(1.0, 0.0, 0.0)
+    };
+    let t2 = cross(&vh, &t1);
+    let r = f32::sqrt(rn.x);
+    let phi = 2.0 * PI * rn.y;
+    let p1 = r * f32::cos(phi);
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * f32::sqrt(f32::max(0.0, 1.0 - p1 * p1)) + s * r * f32::sin(phi);
+    let nh = t1 * p1 + t2 * p2 + vh * f32::sqrt(f32::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+    let local_half_vector = vec3(ax * nh.x, ay * nh.y, f32::max(1e-6, nh.z));
+    transform_direction_mat(frame, &local_half_vector)
+}
+
+// density of the visible normals, D(h) G1(o) |o.h| / |o.n|
+fn sample_microfacet_pdf(roughness: &Vec2, frame: &Mat3, halfway: &Vec3, outgoing: &Vec3) -> f32 {
+    let cosine = dot(&frame.column(2).into(), outgoing);
+    let cosineh = dot(halfway, outgoing);
+    if cosine == 0.0 || cosineh * cosine <= 0.0 {
+        return 0.0;
+    }
+    microfacet_distribution(roughness, frame, halfway)
+        * microfacet_shadowing1(roughness, frame, halfway, outgoing)
+        * f32::abs(cosineh)
+        / f32::abs(cosine)
+}
+
+const ALBEDO_LUT_SIZE: usize = 32;
+
+const ALBEDO_LUT_STRATA: usize = 32;
+
+// directional albedo of a white lobe, indexed by alpha and cosine, with its
+// cosine-weighted average per alpha; computed once on first use
+struct AlbedoLut {
+    albedo: Vec<f32>,
+    average: Vec<f32>,
+}
+
+impl AlbedoLut {
+    // the estimator returns the sample weight of a lobe for the given alpha,
+    // outgoing direction in the local frame and stratified random numbers
+    fn precompute(estimator: impl Fn(f32, &Vec3, &Vec2) -> f32) -> AlbedoLut {
+        let size = ALBEDO_LUT_SIZE;
+        let strata = ALBEDO_LUT_STRATA;
+        let mut albedo = vec![0.0; size * size];
+        for a in 0..size {
+            let alpha = f32::max(a as f32 / (size - 1) as f32, 1e-4);
+            for c in 0..size {
+                let cosine = f32::max(c as f32 / (size - 1) as f32, 1e-3);
+                let outgoing = vec3(f32::sqrt(1.0 - cosine * cosine), 0.0, cosine);
+                let mut sum = 0.0;
+                for i in 0..strata * strata {
+                    let rn = vec2(
+                        ((i % strata) as f32 + 0.5) / strata as f32,
+                        ((i / strata) as f32 + 0.5) / strata as f32,
+                    );
+                    sum += estimator(alpha, &outgoing, &rn);
+                }
+                albedo[a * size + c] = sum / (strata * strata) as f32;
+            }
         }
+        // E_avg = 2 int E(mu) mu dmu, trapezoidal over the cosine nodes
+        let average = (0..size)
+            .map(|a| {
+                let step = 1.0 / (size - 1) as f32;
+                (0..size)
+                    .map(|c| {
+                        let weight = if c == 0 || c == size - 1 { 0.5 } else { 1.0 };
+                        weight * albedo[a * size + c] * c as f32 * step
+                    })
+                    .sum::<f32>()
+                    * 2.0
+                    * step
+            })
+            .collect();
+        AlbedoLut { albedo, average }
+    }
+
+    fn albedo(&self, alpha: f32, cosine: f32) -> f32 {
+        let size = ALBEDO_LUT_SIZE;
+        let x = f32::clamp(cosine, 0.0, 1.0) * (size - 1) as f32;
+        let y = f32::clamp(alpha, 0.0, 1.0) * (size - 1) as f32;
+        let (i, j) = (
+            usize::min(x as usize, size - 2),
+            usize::min(y as usize, size - 2),
+        );
+        let (u, v) = (x - i as f32, y - j as f32);
+        let lookup = |c: usize, a: usize| self.albedo[a * size + c];
+        lookup(i, j) * (1.0 - u) * (1.0 - v)
+            + lookup(i + 1, j) * u * (1.0 - v)
+            + lookup(i, j + 1) * (1.0 - u) * v
+            + lookup(i + 1, j + 1) * u * v
     }
+
+    fn average(&self, alpha: f32) -> f32 {
+        let size = ALBEDO_LUT_SIZE;
+        let y = f32::clamp(alpha, 0.0, 1.0) * (size - 1) as f32;
+        let j = usize::min(y as usize, size - 2);
+        let v = y - j as f32;
+        self.average[j] * (1.0 - v) + self.average[j + 1] * v
+    }
+}
+
+fn albedo_lut() -> &'static AlbedoLut {
+    static LUT: OnceLock<AlbedoLut> = OnceLock::new();
+    LUT.get_or_init(|| {
+        AlbedoLut::precompute(|alpha, outgoing, rn| {
+            let roughness = vec2(alpha, alpha);
+            let frame = Mat3::identity();
+            let halfway = sample_microfacet(&roughness, &frame, outgoing, rn);
+            let incoming = glm::reflect_vec(&(-outgoing), &halfway);
+            if incoming.z <= 0.0 {
+                return 0.0;
+            }
+            // with visible normals the estimator reduces to G1(i)
+            microfacet_shadowing1(&roughness, &frame, &halfway, &incoming)
+        })
+    })
+}
+
+fn sheen_lut() -> &'static AlbedoLut {
+    static LUT: OnceLock<AlbedoLut> = OnceLock::new();
+    LUT.get_or_init(|| {
+        AlbedoLut::precompute(|alpha, outgoing, rn| {
+            let normal = vec3(0.0, 0.0, 1.0);
+            let incoming = sample_hemisphere_cos(&normal, rn);
+            let halfway = normalize(&(outgoing + incoming));
+            sheen_distribution(alpha, &normal, &halfway)
+                * sheen_visibility(&normal, outgoing, &incoming)
+                * PI
+        })
+    })
 }
 
-fn microfacet_distribution(roughness: f32, normal: &Vec3, halfway: &Vec3, ggx: bool) -> f32 {
-    // https://google.github.io/filament/Filament.html#materialsystem/specularbrdf
-    // http://graphicrants.blogspot.com/2013/08/specular-brdf-reference.html
+// [Estevez and Kulla 2017] inverted gaussian distribution of the sheen fibers
+fn sheen_distribution(roughness: f32, normal: &Vec3, halfway: &Vec3) -> f32 {
     let cosine = dot(normal, halfway);
-    if cosine <= 0.0 {
-        return 0.0;
+    let sin2 = f32::max(1.0 - cosine * cosine, 0.0);
+    let inv = 1.0 / roughness;
+    (2.0 + inv) * f32::powf(sin2, 0.5 * inv) / (2.0 * PI)
+}
+
+// [Neubelt and Pettineo 2013] visibility term used with the sheen distribution
+fn sheen_visibility(normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> f32 {
+    let cos_o = f32::abs(dot(normal, outgoing));
+    let cos_i = f32::abs(dot(normal, incoming));
+    1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o))
+}
+
+fn sheen_albedo(roughness: f32, cosine: f32) -> f32 {
+    sheen_lut().albedo(roughness, cosine)
+}
+
+// single scattering albedo of the microfacet lobe, anisotropic alphas are
+// reduced to their geometric mean
+fn microfacet_albedo(roughness: &Vec2, cosine: f32) -> f32 {
+    albedo_lut().albedo(f32::sqrt(roughness.x * roughness.y), cosine)
+}
+
+// [Kulla and Conty 2017] energy lost to multiple bounces between microfacets,
+// added back as a diffuse-like lobe tinted by the average fresnel
+fn microfacet_compensation(
+    roughness: &Vec2,
+    reflectivity: &Vec3,
+    normal: &Vec3,
+    outgoing: &Vec3,
+    incoming: &Vec3,
+) -> Vec3 {
+    let alpha = f32::sqrt(roughness.x * roughness.y);
+    let lut = albedo_lut();
+    let average = lut.average(alpha);
+    if average >= 1.0 - 1e-4 {
+        return zero3!();
     }
-    let roughness2 = roughness * roughness;
-    let cosine2 = cosine * cosine;
-    if ggx {
-        roughness2
-            / (PI * (cosine2 * roughness2 + 1.0 - cosine2) * (cosine2 * roughness2 + 1.0 - cosine2))
-    } else {
-        f32::exp((cosine2 - 1.0) / (roughness2 * cosine2)) / (PI * roughness2 * cosine2 * cosine2)
+    let albedo_o = lut.albedo(alpha, dot(normal, outgoing));
+    let albedo_i = lut.albedo(alpha, dot(normal, incoming));
+    let fresnel = reflectivity * (20.0 / 21.0) + one3!() / 21.0;
+    let fresnel_ms = vec_comp_div!(
+        vec_comp_mul!(fresnel, &fresnel) * average,
+        &(one3!() - fresnel * (1.0 - average))
+    );
+    fresnel_ms * (1.0 - albedo_o) * (1.0 - albedo_i) / (PI * (1.0 - average))
+        * f32::abs(dot(normal, incoming))
+}
+
+// [Chiang et al. 2016] hair scattering parameters, as in pbrt; v are the
+// longitudinal variances per order and s the azimuthal logistic scale
+struct HairLobe {
+    v: [f32; HAIR_MAX_ORDER + 1],
+    s: f32,
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3],
+    absorption: Vec3,
+    h: f32,
+    gamma_o: f32,
+    eta: f32,
+}
+
+impl HairLobe {
+    // angle of the refracted ray inside the fiber
+    fn gamma_t(&self, sin_theta_o: f32, cos_theta_o: f32) -> f32 {
+        let etap = f32::sqrt(f32::max(
+            self.eta * self.eta - sin_theta_o * sin_theta_o,
+            0.0,
+        )) / f32::max(cos_theta_o, 1e-6);
+        f32::asin(f32::clamp(self.h / etap, -1.0, 1.0))
+    }
+
+    // fresnel and absorption along each scattering order
+    fn attenuations(&self, cos_theta_o: f32, sin_theta_o: f32) -> [Vec3; HAIR_MAX_ORDER + 1] {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = f32::sqrt(f32::max(1.0 - sin_theta_t * sin_theta_t, 0.0));
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o);
+        let transmittance =
+            glm::exp(&(-self.absorption * (2.0 * f32::cos(gamma_t) / f32::max(cos_theta_t, 1e-6))));
+        let cosine = cos_theta_o * f32::sqrt(f32::max(1.0 - self.h * self.h, 0.0));
+        let f = fresnel_dielectric(
+            self.eta,
+            &vec3(0.0, 0.0, 1.0),
+            &vec3(f32::sqrt(f32::max(1.0 - cosine * cosine, 0.0)), 0.0, cosine),
+        );
+        let r = one3!() * f;
+        let tt = transmittance * (1.0 - f) * (1.0 - f);
+        let trt = vec_comp_mul!(tt, &transmittance) * f;
+        let rest = vec_comp_div!(
+            vec_comp_mul!(trt, &transmittance) * f,
+            &glm::max(&(one3!() - transmittance * f), 1e-6)
+        );
+        [r, tt, trt, rest]
+    }
+
+    // probabilities of sampling each order, proportional to its luminance
+    fn order_pdfs(&self, cos_theta_o: f32, sin_theta_o: f32) -> [f32; HAIR_MAX_ORDER + 1] {
+        let attenuations = self.attenuations(cos_theta_o, sin_theta_o);
+        let total: f32 = attenuations.iter().map(luminance).sum();
+        let mut pdfs = [0.0; HAIR_MAX_ORDER + 1];
+        for (pdf, attenuation) in pdfs.iter_mut().zip(attenuations.iter()) {
+            *pdf = if total > 0.0 {
+                luminance(attenuation) / total
+            } else {
+                1.0 / (HAIR_MAX_ORDER + 1) as f32
+            };
+        }
+        pdfs
+    }
+
+    // outgoing elevation rotated by the cuticle scales for each order
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin, cos) = match p {
+            0 => (
+                sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1],
+            ),
+            1 => (
+                sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0],
+            ),
+            2 => (
+                sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin, f32::abs(cos))
     }
 }
 
-fn same_hemisphere(normal: &Vec3, outgoing: &Vec3, incoming: &Vec3) -> bool {
-    dot(normal, outgoing) * dot(normal, incoming) >= 0.0
+// absorption of eumelanin and pheomelanin [d'Eon et al. 2011]
+fn hair_melanin_absorption(melanin: f32, redness: f32) -> Vec3 {
+    let eumelanin = melanin * (1.0 - redness);
+    let pheomelanin = melanin * redness;
+    vec3(0.419, 0.697, 1.37) * eumelanin + vec3(0.187, 0.4, 1.05) * pheomelanin
 }
 
-fn sample_microfacet(roughness: f32, normal: &Vec3, rn: &Vec2, ggx: bool) -> Vec3 {
-    let phi = 2.0 * PI * rn.x;
-    let roughness2 = roughness * roughness;
-    let theta = if ggx {
-        f32::atan(roughness * f32::sqrt(rn.y / (1.0 - rn.y)))
+// absorption that yields approximately the given multiple-scattering color
+fn hair_color_absorption(color: &Vec3, beta_n: f32) -> Vec3 {
+    let scale = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+        + 5.574 * beta_n.powi(4)
+        + 0.245 * beta_n.powi(5);
+    let log = glm::log(&glm::max(color, 1e-4)) / scale;
+    vec_comp_mul!(log, &log)
+}
+
+fn bessel_i0(x: f32) -> f32 {
+    let (mut value, mut x2i, mut ifact, mut i4) = (0.0, 1.0, 1.0, 1.0);
+    let x2 = x * x;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f32;
+        }
+        value += x2i / (i4 * ifact * ifact);
+        x2i *= x2;
+        i4 *= 4.0;
+    }
+    value
+}
+
+fn log_bessel_i0(x: f32) -> f32 {
+    if x > 12.0 {
+        x + 0.5 * (-f32::ln(2.0 * PI) + f32::ln(1.0 / x) + 1.0 / (8.0 * x))
     } else {
-        f32::atan(f32::sqrt(-roughness2 * f32::ln(1.0 - rn.y)))
-    };
-    let local_half_vector = vec3(
-        f32::cos(phi) * f32::sin(theta),
-        f32::sin(phi) * f32::sin(theta),
-        f32::cos(theta),
-    );
-    transform_direction_mat(&basis_fromz(&normal), &local_half_vector)
+        f32::ln(bessel_i0(x))
+    }
 }
 
-fn sample_microfacet_pdf(roughness: f32, normal: &Vec3, halfway: &Vec3) -> f32 {
-    let cosine = dot(normal, halfway);
-    if cosine < 0.0 {
-        0.0
+fn hair_longitudinal(
+    cos_theta_i: f32,
+    cos_theta_o: f32,
+    sin_theta_i: f32,
+    sin_theta_o: f32,
+    v: f32,
+) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        f32::exp(log_bessel_i0(a) - b - 1.0 / v + std::f32::consts::LN_2 + f32::ln(1.0 / (2.0 * v)))
     } else {
-        microfacet_distribution(roughness, normal, halfway, true) * cosine
+        f32::exp(-b) * bessel_i0(a) / (f32::sinh(1.0 / v) * 2.0 * v)
     }
 }
 
+fn hair_phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    2.0 * p as f32 * gamma_t - 2.0 * gamma_o + p as f32 * PI
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let x = f32::abs(x);
+    f32::exp(-x / s) / (s * f32::powi(1.0 + f32::exp(-x / s), 2))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1.0 / (1.0 + f32::exp(-x / s))
+}
+
+fn trimmed_logistic(x: f32, s: f32, a: f32, b: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * f32::ln(1.0 / (u * k + logistic_cdf(a, s)) - 1.0);
+    f32::clamp(x, a, b)
+}
+
+fn hair_azimuthal(phi: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi - hair_phi(p, gamma_o, gamma_t);
+    // remap to [-pi, pi]
+    dphi = (dphi + PI).rem_euclid(2.0 * PI) - PI;
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
 #[inline(always)]
 pub fn sample_uniform(size: usize, r: f32) -> usize {
     usize::clamp((r * size as f32) as usize, 0, size - 1)
@@ -0,0 +1,558 @@
+// on demand texture loading through a cache of tiles bounded by a memory
+// budget; single tiles are read from tiled and mipmapped cache files, which
+// are either kept next to their image and reused across runs, or written to
+// a temporary file on first use and removed with the texture; images are
+// converted one at a time, with their decoded levels counted in the budget
+use crate::model_io::texture::{read_texture, read_texture_size};
+use crate::scene_components::{MipLevel, Texture};
+use glm::{vec4, Vec4};
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+
+// tile side in texels
+pub const TILE_SIZE: u32 = 64;
+// tiles each thread keeps at hand ahead of the shared cache
+const RECENT_TILES: usize = 8;
+const MAGIC: &[u8; 4] = b"RTX1";
+const HEADER_SIZE: u64 = 22;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheOptions {
+    // memory budget in bytes
+    pub budget: usize,
+    // convert textures to tiled cache files on first load
+    pub convert: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub memory: usize,
+}
+
+// texels are kept with the precision of the image they come from, four
+// channels each
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Encoding {
+    Bytes,
+    Shorts,
+    Floats,
+}
+
+impl Encoding {
+    pub(crate) fn of(texture: &Texture) -> Encoding {
+        if !texture.hdr.is_empty() {
+            Encoding::Floats
+        } else if !texture.shorts.is_empty() {
+            Encoding::Shorts
+        } else {
+            Encoding::Bytes
+        }
+    }
+
+    pub(crate) fn texel_size(&self) -> usize {
+        match self {
+            Encoding::Bytes => 4,
+            Encoding::Shorts => 8,
+            Encoding::Floats => 16,
+        }
+    }
+
+    pub(crate) fn decode(&self, data: &[u8]) -> Vec4 {
+        let channel = |c: usize| match self {
+            Encoding::Bytes => data[c] as f32 / 255.0,
+            Encoding::Shorts => u16::from_le_bytes([data[2 * c], data[2 * c + 1]]) as f32 / 65535.0,
+            Encoding::Floats => f32::from_le_bytes(data[4 * c..4 * c + 4].try_into().unwrap()),
+        };
+        vec4(channel(0), channel(1), channel(2), channel(3))
+    }
+
+    pub(crate) fn encode(&self, color: &Vec4, data: &mut Vec<u8>) {
+        for c in color.iter() {
+            match self {
+                Encoding::Bytes => data.push((c.clamp(0.0, 1.0) * 255.0).round() as u8),
+                Encoding::Shorts => data.extend_from_slice(
+                    &((c.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes(),
+                ),
+                Encoding::Floats => data.extend_from_slice(&c.to_le_bytes()),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Tile {
+    pub width: u32,
+    pub height: u32,
+    pub encoding: Encoding,
+    pub data: Vec<u8>,
+}
+
+impl Tile {
+    fn texel(&self, i: u32, j: u32) -> Vec4 {
+        let size = self.encoding.texel_size();
+        let offset = (j * self.width + i) as usize * size;
+        self.encoding.decode(&self.data[offset..offset + size])
+    }
+}
+
+// texture, level and tile coordinates
+type TileKey = (usize, u32, u32, u32);
+
+// ids of lazy textures, unique over all caches
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+// suffixes of the partial cache files being written by this process
+static NEXT_PARTIAL: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // tiles last used by this thread, most recent first, so that the texels
+    // of a filter footprint skip the lock of the shared cache; they are not
+    // kept alive once evicted, so that only the cache holds texels
+    static RECENT: RefCell<Vec<(TileKey, Weak<Tile>)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    // tiles with the time they were last used, and the reverse order
+    tiles: HashMap<TileKey, (Arc<Tile>, u64)>,
+    lru: BTreeMap<u64, TileKey>,
+    clock: u64,
+    memory: usize,
+    // tiles being loaded, shared with the threads waiting for them; tiles
+    // that failed to load stay here as none, so they are not read again
+    loading: HashMap<TileKey, Arc<OnceLock<Option<Arc<Tile>>>>>,
+}
+
+#[derive(Debug)]
+pub struct TextureCache {
+    pub options: CacheOptions,
+    state: Mutex<CacheState>,
+    // held while an image is converted to a cache file
+    converting: Mutex<()>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl TextureCache {
+    pub fn new(options: CacheOptions) -> Self {
+        TextureCache {
+            options,
+            state: Mutex::new(CacheState::default()),
+            converting: Mutex::new(()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            memory: self.state.lock().memory,
+        }
+    }
+
+    // tile from the cache, or from the loader on a miss; a tile is loaded
+    // by one thread while the others asking for it wait, and the least
+    // recently used tiles are evicted down to the budget; none when the
+    // tile cannot be loaded
+    fn get(&self, key: TileKey, load: impl FnOnce() -> Result<Tile>) -> Option<Arc<Tile>> {
+        let slot = {
+            let mut state = self.state.lock();
+            let state = &mut *state;
+            if let Some((tile, stamp)) = state.tiles.get_mut(&key) {
+                state.lru.remove(stamp);
+                state.clock += 1;
+                *stamp = state.clock;
+                state.lru.insert(state.clock, key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(tile.clone());
+            }
+            state.loading.entry(key).or_default().clone()
+        };
+
+        // decoding happens outside the lock
+        slot.get_or_init(|| {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            let tile = Arc::new(load().ok()?);
+            let mut state = self.state.lock();
+            let state = &mut *state;
+            state.loading.remove(&key);
+            state.clock += 1;
+            state.memory += tile.data.len();
+            state.tiles.insert(key, (tile.clone(), state.clock));
+            state.lru.insert(state.clock, key);
+            self.evict(state, 1);
+            Some(tile)
+        })
+        .clone()
+    }
+
+    // least recently used tiles evicted down to the budget, keeping the
+    // most recent ones
+    fn evict(&self, state: &mut CacheState, keep: usize) {
+        while state.memory > self.options.budget && state.lru.len() > keep {
+            let (_, oldest) = state.lru.pop_first().unwrap();
+            let (tile, _) = state.tiles.remove(&oldest).unwrap();
+            state.memory -= tile.data.len();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // memory held outside the tiles, made room for by evicting them
+    fn reserve(&self, bytes: usize) {
+        let mut state = self.state.lock();
+        state.memory += bytes;
+        self.evict(&mut state, 0);
+    }
+
+    fn release(&self, bytes: usize) {
+        self.state.lock().memory -= bytes;
+    }
+
+    // decodes an image into a cache file; conversions run one at a time and
+    // the two levels held by `write_tiled` count against the budget
+    fn convert(&self, source: &Path, tiled: &Path) -> Result<()> {
+        let _converting = self.converting.lock();
+        let (width, height, linear) = read_texture_size(source)?;
+        let working = |encoding: Encoding| {
+            let texels: usize = mip_sizes(width, height)
+                .iter()
+                .take(2)
+                .map(|&(width, height)| width as usize * height as usize)
+                .sum();
+            texels * encoding.texel_size()
+        };
+        // 16-bit images are only told apart once decoded
+        let mut reserved = working(if linear {
+            Encoding::Floats
+        } else {
+            Encoding::Bytes
+        });
+        self.reserve(reserved);
+        let mut texture = Texture::default();
+        let result = read_texture(source, &mut texture).and_then(|_| {
+            let decoded = working(Encoding::of(&texture));
+            if decoded > reserved {
+                self.reserve(decoded - reserved);
+                reserved = decoded;
+            }
+            write_tiled(tiled, texture)
+        });
+        self.release(reserved);
+        result
+    }
+}
+
+// sizes of the levels of a mip pyramid, halved down to a single texel as
+// in `Texture::build_mips`
+pub fn mip_sizes(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let mut sizes = vec![(width, height)];
+    let (mut width, mut height) = (width, height);
+    while width > 1 || height > 1 {
+        (width, height) = (u32::max(width / 2, 1), u32::max(height / 2, 1));
+        sizes.push((width, height));
+    }
+    sizes
+}
+
+// cache file opened for reading tiles, with the offsets of its tiles
+#[derive(Debug)]
+struct TiledFile {
+    path: PathBuf,
+    // removed when the texture is dropped
+    temporary: bool,
+    encoding: Encoding,
+    // byte offsets of the tiles, by level
+    offsets: Vec<Vec<u64>>,
+}
+
+impl TiledFile {
+    fn open(path: PathBuf, temporary: bool) -> Result<TiledFile> {
+        let (encoding, _, tile_size, levels) = read_header(&path)?;
+        let texel_size = encoding.texel_size() as u64;
+        let mut offset = HEADER_SIZE;
+        let mut offsets = Vec::with_capacity(levels.len());
+        for &(width, height) in &levels {
+            let mut level = vec![];
+            for ty in 0..height.div_ceil(tile_size) {
+                for tx in 0..width.div_ceil(tile_size) {
+                    level.push(offset);
+                    let tile_width = u32::min(tile_size, width - tx * tile_size) as u64;
+                    let tile_height = u32::min(tile_size, height - ty * tile_size) as u64;
+                    offset += tile_width * tile_height * texel_size;
+                }
+            }
+            offsets.push(level);
+        }
+        // files cut short by an interrupted conversion
+        if std::fs::metadata(&path)?.len() != offset {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "truncated texture cache file",
+            ));
+        }
+        Ok(TiledFile {
+            path,
+            temporary,
+            encoding,
+            offsets,
+        })
+    }
+}
+
+impl Drop for TiledFile {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// texture whose texels are loaded through the cache
+#[derive(Debug)]
+pub struct LazyTexture {
+    id: usize,
+    cache: Arc<TextureCache>,
+    source: PathBuf,
+    // cache file, written by the first thread missing a tile when textures
+    // are not converted ahead; none when the conversion failed
+    tiled: OnceLock<Option<TiledFile>>,
+    tile_size: u32,
+    pub linear: bool,
+    pub levels: Vec<(u32, u32)>,
+}
+
+impl LazyTexture {
+    // reads only the size of the image, or converts it to a cache file
+    // when the cache asks for it and the file is missing, stale or truncated
+    pub fn open(path: &Path, cache: Arc<TextureCache>) -> Result<LazyTexture> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        if !cache.options.convert {
+            let (width, height, linear) = read_texture_size(path)?;
+            return Ok(LazyTexture {
+                id,
+                cache,
+                source: path.to_path_buf(),
+                tiled: OnceLock::new(),
+                // temporary cache files are written with the default tiles
+                tile_size: TILE_SIZE,
+                linear,
+                levels: mip_sizes(width, height),
+            });
+        }
+        let mut tiled = path.as_os_str().to_owned();
+        tiled.push(".rtx");
+        let tiled = PathBuf::from(tiled);
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified());
+        let fresh = match (modified(&tiled), modified(path)) {
+            (Ok(tiled), Ok(source)) => tiled >= source,
+            _ => false,
+        };
+        let file = match fresh.then(|| TiledFile::open(tiled.clone(), false)) {
+            Some(Ok(file)) => file,
+            _ => {
+                cache.convert(path, &tiled)?;
+                TiledFile::open(tiled, false)?
+            }
+        };
+        let (_, linear, tile_size, levels) = read_header(&file.path)?;
+        Ok(LazyTexture {
+            id,
+            cache,
+            source: path.to_path_buf(),
+            tiled: OnceLock::from(Some(file)),
+            tile_size,
+            linear,
+            levels,
+        })
+    }
+
+    // texels of tiles that cannot be loaded are white, as for textures
+    // without an image
+    pub fn texel(&self, level: usize, i: u32, j: u32) -> Vec4 {
+        let (tx, ty) = (i / self.tile_size, j / self.tile_size);
+        let key = (self.id, level as u32, tx, ty);
+        let recent = RECENT.with_borrow_mut(|recent| {
+            let position = recent.iter().position(|(tile_key, _)| *tile_key == key)?;
+            recent[..=position].rotate_right(1);
+            recent[0].1.upgrade()
+        });
+        let tile = match recent {
+            Some(tile) => {
+                self.cache.hits.fetch_add(1, Ordering::Relaxed);
+                tile
+            }
+            None => {
+                let Some(tile) = self.cache.get(key, || self.load_tile(level, tx, ty)) else {
+                    return vec4(1.0, 1.0, 1.0, 1.0);
+                };
+                RECENT.with_borrow_mut(|recent| {
+                    recent.retain(|(tile_key, _)| *tile_key != key);
+                    recent.insert(0, (key, Arc::downgrade(&tile)));
+                    recent.truncate(RECENT_TILES);
+                });
+                tile
+            }
+        };
+        tile.texel(i - tx * self.tile_size, j - ty * self.tile_size)
+    }
+
+    // cache file of the texture, converted to a temporary file once by the
+    // first thread missing any of its tiles
+    fn tiled(&self) -> Result<&TiledFile> {
+        self.tiled
+            .get_or_init(|| {
+                let path = std::env::temp_dir().join(format!(
+                    "rtrace-{}-{}.rtx",
+                    std::process::id(),
+                    self.id
+                ));
+                self.cache
+                    .convert(&self.source, &path)
+                    .and_then(|_| TiledFile::open(path, true))
+                    .ok()
+            })
+            .as_ref()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unable to convert {}", self.source.display()),
+                )
+            })
+    }
+
+    fn load_tile(&self, level: usize, tx: u32, ty: u32) -> Result<Tile> {
+        let tiled = self.tiled()?;
+        let (width, height) = self.levels[level];
+        let tiles_x = width.div_ceil(self.tile_size);
+        let tile_width = u32::min(self.tile_size, width - tx * self.tile_size);
+        let tile_height = u32::min(self.tile_size, height - ty * self.tile_size);
+        let mut file = File::open(&tiled.path)?;
+        file.seek(SeekFrom::Start(
+            tiled.offsets[level][(ty * tiles_x + tx) as usize],
+        ))?;
+        let mut data = vec![0; (tile_width * tile_height) as usize * tiled.encoding.texel_size()];
+        file.read_exact(&mut data)?;
+        Ok(Tile {
+            width: tile_width,
+            height: tile_height,
+            encoding: tiled.encoding,
+            data,
+        })
+    }
+}
+
+// cache file with a small header followed by the tiles of all the levels,
+// each level in rows of tiles and each tile in rows of texels; every level
+// is halved from the one before it, which is dropped once written, so at
+// most two levels are held at once, in the encoding of the image; the file
+// is written aside and renamed into place, so that an interrupted or
+// concurrent conversion never leaves a partial file under its name
+pub fn write_tiled(path: &Path, texture: Texture) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(format!(
+        ".{}-{}.partial",
+        std::process::id(),
+        NEXT_PARTIAL.fetch_add(1, Ordering::Relaxed)
+    ));
+    let partial = PathBuf::from(partial);
+    let result = write_levels(&partial, texture).and_then(|_| std::fs::rename(&partial, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result
+}
+
+fn write_levels(path: &Path, texture: Texture) -> Result<()> {
+    let encoding = Encoding::of(&texture);
+    let (width, height, linear) = (texture.width, texture.height, texture.linear);
+    let levels = mip_sizes(width, height);
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    for value in [width, height, TILE_SIZE, levels.len() as u32] {
+        file.write_all(&value.to_le_bytes())?;
+    }
+    file.write_all(&[encoding as u8, linear as u8])?;
+    write_level(&mut file, encoding, width, height, |i, j| {
+        texture.texel(0, i, j)
+    })?;
+    let mut level = (levels.len() > 1).then(|| {
+        MipLevel::downsample(width, height, linear, encoding, |i, j| {
+            texture.texel(0, i, j)
+        })
+    });
+    drop(texture);
+    while let Some(mip) = level {
+        write_level(&mut file, encoding, mip.width, mip.height, |i, j| {
+            mip.texel(i, j)
+        })?;
+        level = (mip.width > 1 || mip.height > 1).then(|| mip.halve(linear));
+    }
+    file.flush()
+}
+
+fn write_level<W: Write>(
+    file: &mut W,
+    encoding: Encoding,
+    width: u32,
+    height: u32,
+    texel: impl Fn(u32, u32) -> Vec4,
+) -> Result<()> {
+    let mut data = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize * encoding.texel_size());
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        for x in (0..width).step_by(TILE_SIZE as usize) {
+            data.clear();
+            for j in y..u32::min(y + TILE_SIZE, height) {
+                for i in x..u32::min(x + TILE_SIZE, width) {
+                    encoding.encode(&texel(i, j), &mut data);
+                }
+            }
+            file.write_all(&data)?;
+        }
+    }
+    Ok(())
+}
+
+// encoding, linear flag, tile side and level sizes of a cache file
+type Header = (Encoding, bool, u32, Vec<(u32, u32)>);
+
+fn read_header(path: &Path) -> Result<Header> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = [0; HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "not a texture cache file",
+        ));
+    }
+    let value =
+        |idx: usize| u32::from_le_bytes(header[4 + 4 * idx..8 + 4 * idx].try_into().unwrap());
+    let (width, height, tile_size, count) = (value(0), value(1), value(2), value(3));
+    let encoding = match header[20] {
+        0 => Encoding::Bytes,
+        1 => Encoding::Shorts,
+        2 => Encoding::Floats,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "unknown texel encoding")),
+    };
+    let levels = mip_sizes(width, height);
+    if tile_size == 0 || levels.len() != count as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "corrupt texture cache file",
+        ));
+    }
+    Ok((encoding, header[21] != 0, tile_size, levels))
+}
@@ -1,5 +1,6 @@
 use crate::bvh::*;
 use crate::scene::*;
+use crate::scene_components::MaterialType;
 use crate::shading::*;
 use crate::utils::*;
 use crate::{one3, vec_comp_mul, zero3, zero4};
@@ -10,6 +11,8 @@ use rand::prelude::SmallRng;
 use rayon::prelude::*;
 
 const RAY_EPS: f32 = 1e-4;
+const INVALID: usize = usize::MAX;
+const SUBSURFACE_MAX_STEPS: i32 = 256;
 
 #[derive(Debug)]
 pub struct Ray {
@@ -17,6 +20,16 @@ pub struct Ray {
     pub direction: Vec3,
     pub tmin: f32,
     pub tmax: f32,
+    pub differential: Option<RayDifferential>,
+}
+
+// rays through the next pixels in x and y, used to estimate texture footprints
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
@@ -43,10 +56,66 @@ impl Default for Ray {
             direction: vec3(0.0, 0.0, 1.0),
             tmin: RAY_EPS,
             tmax: f32::MAX,
+            differential: None,
         }
     }
 }
 
+// differentials after a delta bounce, following pbrt with the normal assumed
+// constant over the footprint; the offset rays restart where they cross the
+// tangent plane at the hit
+pub fn specular_differential(
+    ray: &Ray,
+    position: &Vec3,
+    normal: &Vec3,
+    incoming: &Vec3,
+    material: &MaterialPoint,
+) -> Option<RayDifferential> {
+    let differential = ray.differential.as_ref()?;
+    let outgoing = -ray.direction;
+    let entering = dot(normal, &outgoing) >= 0.0;
+    let up_normal = if entering { *normal } else { -normal };
+    let reflected = dot(normal, &outgoing) * dot(normal, incoming) > 0.0;
+    // ratio of the indices on the outgoing and incoming sides, passthrough
+    // materials keep the direction
+    let eta = if is_volumetric(material) && material.m_type != MaterialType::Volumetric {
+        let (eta_i, eta_t) = material.relative_ior(normal, &outgoing);
+        eta_i / eta_t
+    } else {
+        1.0
+    };
+    let offset = |origin: &Vec3, direction: &Vec3| {
+        let origin =
+            origin + direction * (dot(normal, &(position - origin)) / dot(normal, direction));
+        let doutgoing = -direction - outgoing;
+        let ddn = dot(&doutgoing, &up_normal);
+        let direction = if reflected {
+            incoming - doutgoing + up_normal * (2.0 * ddn)
+        } else {
+            let dmu = (eta
+                - eta * eta * dot(&outgoing, &up_normal) / f32::abs(dot(incoming, &up_normal)))
+                * ddn;
+            incoming - doutgoing * eta + up_normal * dmu
+        };
+        (origin, direction)
+    };
+    let (rx_origin, rx_direction) = offset(&differential.rx_origin, &differential.rx_direction);
+    let (ry_origin, ry_direction) = offset(&differential.ry_origin, &differential.ry_direction);
+    if !is_finite(&rx_origin)
+        || !is_finite(&rx_direction)
+        || !is_finite(&ry_origin)
+        || !is_finite(&ry_direction)
+    {
+        return None;
+    }
+    Some(RayDifferential {
+        rx_origin,
+        rx_direction,
+        ry_origin,
+        ry_direction,
+    })
+}
+
 pub fn raytrace_samples(
     state: &mut RaytraceState,
     params: &RaytraceParams,
@@ -69,7 +138,8 @@ pub fn raytrace_samples(
                 (i as f32 + puv.x) / state.width as f32,
                 (j as f32 + puv.y) / state.height as f32,
             );
-            let mut ray = camera.eval(uv, sample_disk(rand2(rng)));
+            let pixel = vec2(1.0 / state.width as f32, 1.0 / state.height as f32);
+            let mut ray = camera.eval_differential(uv, sample_disk(rand2(rng)), pixel);
             let mut radiance = (params.shader)(scene, bvh, &mut ray, rng, params);
             if params.clamp != 0.0 && radiance.max() > params.clamp {
                 radiance = radiance * (params.clamp / radiance.max());
@@ -91,7 +161,7 @@ pub fn shade_color(
     if !intersection.hit {
         return zero4!();
     }
-    let material = scene.eval_material(&intersection);
+    let material = scene.eval_material(&intersection, ray);
     vec3_to_vec4(&material.color)
 }
 
@@ -107,7 +177,8 @@ pub fn shade_normals(
         return zero4!();
     }
     let outgoing = -ray.direction;
-    let mut normal = scene.eval_shading_normal(&intersection, &outgoing);
+    let material = scene.eval_material(&intersection, ray);
+    let mut normal = scene.eval_shading_normal(&intersection, &outgoing, &material);
     normal = (normal * 0.5).add_scalar(0.5);
     vec3_to_vec4(&normal)
 }
@@ -143,8 +214,8 @@ pub fn shade_eyelight(
 
     // prepare shading point
     let outgoing = -ray.direction;
-    let normal = scene.eval_shading_normal(&intersection, &outgoing);
-    let material = scene.eval_material(&intersection);
+    let material = scene.eval_material(&intersection, ray);
+    let normal = scene.eval_shading_normal(&intersection, &outgoing, &material);
 
     // accumulate emission
     radiance += material.eval_emission(&normal, &outgoing);
@@ -175,8 +246,8 @@ pub fn shade_naive(
         // prepare shading point
         let outgoing = -ray.direction;
         let position = scene.eval_shading_position(&intersection);
-        let normal = scene.eval_shading_normal(&intersection, &outgoing);
-        let material = scene.eval_material(&intersection);
+        let material = scene.eval_material(&intersection, ray);
+        let normal = scene.eval_shading_normal(&intersection, &outgoing, &material);
 
         // handle opacity
         if material.opacity < 1.0 && rand1(rng) >= material.opacity {
@@ -227,6 +298,11 @@ pub fn shade_naive(
 
         // setup next iteration
         bounce += 1;
+        ray.differential = if is_delta(&material) {
+            specular_differential(ray, &position, &normal, &incoming, &material)
+        } else {
+            None
+        };
         ray.origin = position;
         ray.direction = incoming;
     }
@@ -242,7 +318,7 @@ pub fn shade_raytrace(
 ) -> Vec4 {
     let mut radiance = zero3!();
     let mut weight = one3!();
-    let mut volume_stack = Vec::<MaterialPoint>::new();
+    let mut volume_stack = Vec::<(usize, MaterialPoint)>::new();
     let mut bounce = 0;
     let mut hit_alpha = 0.0;
     while bounce < params.bounces {
@@ -253,12 +329,20 @@ pub fn shade_raytrace(
         }
 
         let mut in_volume = false;
-        if !volume_stack.is_empty() {
-            let extinction = volume_stack.last().unwrap();
-            let distance =
-                extinction.sample_transmittance(intersection.distance, rand1(rng), rand1(rng));
-            let transmittance = extinction.eval_transmittance(distance)
-                / extinction.sample_transmittance_pdf(distance, intersection.distance);
+        // subsurface media are crossed by their own random walk, media that
+        // neither scatter nor emit only attenuate the path to the next surface
+        if let Some((_, extinction)) = current_medium(&volume_stack, INVALID)
+            .filter(|(_, medium)| medium.m_type != MaterialType::Subsurface)
+        {
+            let (distance, transmittance) = if is_null(&extinction.scattering, 0.0)
+                && is_null(&extinction.emission, 0.0)
+            {
+                let transmittance =
+                    scene.eval_volume_transmittance(extinction, ray, intersection.distance, rng);
+                (intersection.distance, transmittance)
+            } else {
+                scene.sample_volume_distance(extinction, ray, intersection.distance, rng)
+            };
             weight = vec_comp_mul!(weight, &transmittance);
             in_volume = distance < intersection.distance;
             intersection.distance = distance;
@@ -267,8 +351,8 @@ pub fn shade_raytrace(
             // prepare shading point
             let outgoing = -ray.direction;
             let position = scene.eval_shading_position(&intersection);
-            let normal = scene.eval_shading_normal(&intersection, &outgoing);
-            let material = scene.eval_material(&intersection);
+            let mut material = scene.eval_material(&intersection, ray);
+            let normal = scene.eval_shading_normal(&intersection, &outgoing, &material);
 
             // handle opacity
             if material.opacity < 1.0 && rand1(rng) >= material.opacity {
@@ -276,6 +360,19 @@ pub fn shade_raytrace(
                 bounce -= 1;
                 continue;
             }
+
+            // handle nested media, interfaces inside a medium with higher
+            // priority are skipped and only update the stack; surfaces see
+            // the index of the medium around them
+            let outer = current_medium(&volume_stack, intersection.instance);
+            if is_volumetric(&material)
+                && matches!(outer, Some((_, outer)) if outer.priority > material.priority)
+            {
+                toggle_medium(&mut volume_stack, intersection.instance, material);
+                ray.origin = position;
+                continue;
+            }
+            material.outer_ior = outer.map_or(1.0, |(_, outer)| medium_ior(outer));
             if bounce == 0 {
                 hit_alpha = 1.0;
             }
@@ -308,21 +405,36 @@ pub fn shade_raytrace(
                 weight = vec_comp_mul!(weight, &delta);
             }
 
+            let differential = if is_delta(&material) {
+                specular_differential(ray, &position, &normal, &incoming, &material)
+            } else {
+                None
+            };
+
             if is_volumetric(&material) && dot(&normal, &outgoing) * dot(&normal, &incoming) < 0.0 {
-                if volume_stack.is_empty() {
-                    volume_stack.push(material);
-                } else {
-                    volume_stack.pop();
-                }
+                toggle_medium(&mut volume_stack, intersection.instance, material);
             }
 
             // setup next iteration
+            ray.differential = differential;
             ray.origin = position;
             ray.direction = incoming;
+
+            // the walk stops right before the path leaves the medium, so the
+            // next intersection is the exit point on the boundary
+            if let Some((instance, medium)) = current_medium(&volume_stack, INVALID) {
+                if medium.m_type == MaterialType::Subsurface {
+                    let walk = trace_subsurface(scene, bvh, medium, *instance, ray, rng);
+                    weight = vec_comp_mul!(weight, &walk);
+                }
+            }
         } else {
             let position = ray.origin + ray.direction * intersection.distance;
             let outgoing = -ray.direction;
-            let vol = volume_stack.last().unwrap();
+            let vol = scene.eval_volume_material(
+                &current_medium(&volume_stack, INVALID).unwrap().1,
+                &position,
+            );
             let ds = vec_comp_mul!(vol.density, &(one3!() - vol.scattering));
             let dse = vec_comp_mul!(ds, &vol.emission);
             radiance += vec_comp_mul!(weight, &dse);
@@ -342,6 +454,7 @@ pub fn shade_raytrace(
                 &(scattering / (0.5 * scattering_pdf + 0.5 * lights_pdf))
             );
             // setup next iteration
+            ray.differential = None;
             ray.origin = position;
             ray.direction = incoming;
         }
@@ -362,3 +475,86 @@ pub fn shade_raytrace(
     }
     vec4(radiance.x, radiance.y, radiance.z, hit_alpha)
 }
+
+// the medium a ray travels in is the entered one with highest priority, ties
+// go to the most recently entered; `skip` excludes the interface being crossed
+pub fn current_medium(
+    volume_stack: &[(usize, MaterialPoint)],
+    skip: usize,
+) -> Option<&(usize, MaterialPoint)> {
+    volume_stack
+        .iter()
+        .filter(|(instance, _)| *instance != skip)
+        .max_by_key(|(_, material)| material.priority)
+}
+
+// crossing the boundary of an instance leaves its medium if the ray was
+// inside and enters it otherwise
+pub fn toggle_medium(
+    volume_stack: &mut Vec<(usize, MaterialPoint)>,
+    instance: usize,
+    material: MaterialPoint,
+) {
+    match volume_stack
+        .iter()
+        .position(|(inside, _)| *inside == instance)
+    {
+        Some(idx) => {
+            volume_stack.remove(idx);
+        }
+        None => volume_stack.push((instance, material)),
+    }
+}
+
+// random walk inside the medium of a single instance, the ray is left at the
+// last scattering event and the returned weight includes the transmittance
+// up to the boundary
+fn trace_subsurface(
+    scene: &Scene,
+    bvh: &BvhData<'_>,
+    medium: &MaterialPoint,
+    instance: usize,
+    ray: &mut Ray,
+    rng: &Mutex<SmallRng>,
+) -> Vec3 {
+    let mut weight = one3!();
+    for step in 0..SUBSURFACE_MAX_STEPS {
+        let intersection =
+            bvh.intersect_instance(scene, instance, Ray::new(ray.origin, ray.direction));
+        if !intersection.hit {
+            return zero3!();
+        }
+        let distance = medium.sample_transmittance(intersection.distance, rand1(rng), rand1(rng));
+        let transmittance = medium.eval_transmittance(distance)
+            / medium.sample_transmittance_pdf(distance, intersection.distance);
+        weight = vec_comp_mul!(weight, &transmittance);
+        if distance >= intersection.distance {
+            return weight;
+        }
+
+        // scatter inside the medium
+        let outgoing = -ray.direction;
+        let incoming = medium.sample_scattering(&outgoing, &rand2(rng));
+        let scattering = medium.eval_scattering(&outgoing, &incoming)
+            / medium.sample_scattering_pdf(&outgoing, &incoming);
+        weight = vec_comp_mul!(weight, &scattering);
+        ray.origin += ray.direction * distance;
+        ray.direction = incoming;
+        ray.differential = None;
+
+        // check weight
+        if is_null(&weight, epsilon()) || !is_finite(&weight) {
+            return zero3!();
+        }
+
+        // russian roulette
+        if step > 3 {
+            let rr_prob = min2_scalar(weight.max(), 0.99);
+            if rand1(rng) >= rr_prob {
+                return zero3!();
+            }
+            weight *= 1.0 / rr_prob;
+        }
+    }
+    zero3!()
+}
@@ -68,6 +68,9 @@ pub struct RaytraceParams {
     pub exposure: f32,
     pub filmic: bool,
     pub clamp: f32,
+    // texture cache budget in megabytes, zero loads textures with the scene
+    pub texture_cache: usize,
+    pub texture_convert: bool,
 }
 
 impl RaytraceParams {
@@ -93,6 +96,8 @@ impl RaytraceParams {
             samples: clap::value_t!(args.value_of("samples"), i32).unwrap(),
             bounces: clap::value_t!(args.value_of("bounces"), i32).unwrap(),
             clamp: clap::value_t!(args.value_of("clamp"), f32).unwrap(),
+            texture_cache: clap::value_t!(args.value_of("texture_cache"), usize).unwrap(),
+            texture_convert: clap::value_t!(args.value_of("texture_convert"), bool).unwrap(),
             noparallel,
             shader,
             ..Default::default()
@@ -116,7 +121,7 @@ impl RaytraceParams {
                     .long("--scene")
                     .takes_value(true)
                     .required(true)
-                    .help("JSON scene file"),
+                    .help("JSON, glTF, GLB or OBJ scene file"),
             )
             .arg(
                 Arg::with_name("output")
@@ -172,6 +177,20 @@ impl RaytraceParams {
                     .default_value("false")
                     .help("disable threading"),
             )
+            .arg(
+                Arg::with_name("texture_cache")
+                    .long("--texture-cache")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("texture cache budget in MB, 0 loads all textures"),
+            )
+            .arg(
+                Arg::with_name("texture_convert")
+                    .long("--texture-convert")
+                    .takes_value(true)
+                    .default_value("false")
+                    .help("keep the tiled files of cached textures next to them"),
+            )
             .get_matches()
     }
 }
@@ -189,6 +208,8 @@ impl Default for RaytraceParams {
             exposure: 0.0,
             filmic: false,
             clamp: 10.0,
+            texture_cache: 0,
+            texture_convert: false,
         }
     }
 }
@@ -416,6 +437,11 @@ pub fn mean3(vec: &Vec3) -> f32 {
     glm::comp_add(&vec) / 3.0
 }
 
+#[inline(always)]
+pub fn luminance(color: &Vec3) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
 #[inline(always)]
 pub fn inverse_frame(frame: &Mat3x4, non_rigid: bool) -> Mat3x4 {
     let rotation = make_mat3(
@@ -477,10 +503,12 @@ pub fn is_finite(weight: &Vec3) -> bool {
 
 #[inline(always)]
 pub fn is_delta(material: &MaterialPoint) -> bool {
-    (material.m_type == MaterialType::Reflective && material.roughness == 0.0)
-        || (material.m_type == MaterialType::Refractive && material.roughness == 0.0)
-        || (material.m_type == MaterialType::Transparent && material.roughness == 0.0)
-        || (material.m_type == MaterialType::Volumetric)
+    use MaterialType::*;
+    (matches!(
+        material.m_type,
+        Reflective | Conductor | Refractive | Subsurface | Transparent
+    ) && material.roughness == 0.0)
+        || material.m_type == Volumetric
 }
 
 #[inline(always)]
@@ -488,6 +516,16 @@ pub fn is_volumetric(material: &MaterialPoint) -> bool {
     material.m_type == MaterialType::Refractive
         || material.m_type == MaterialType::Volumetric
         || material.m_type == MaterialType::Subsurface
+        || (material.m_type == MaterialType::Principled && material.transmission > 0.0)
+}
+
+#[inline(always)]
+pub fn medium_ior(material: &MaterialPoint) -> f32 {
+    if material.m_type == MaterialType::Volumetric {
+        1.0
+    } else {
+        material.ior
+    }
 }
 
 #[inline(always)]
@@ -511,3 +549,170 @@ pub fn srgb_to_rgb(color: Vec4) -> Vec4 {
         compute_srgb(color.w),
     )
 }
+
+#[inline(always)]
+pub fn rgb_to_srgb(color: Vec4) -> Vec4 {
+    let compute_srgb = |rgb: f32| -> f32 {
+        if rgb <= 0.0031308 {
+            rgb * 12.92
+        } else {
+            (1.0 + 0.055) * rgb.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    vec4(
+        compute_srgb(color.x),
+        compute_srgb(color.y),
+        compute_srgb(color.z),
+        compute_srgb(color.w),
+    )
+}
+
+// integer hash of a lattice point [Jarzynski and Olano 2020, pcg3d]
+#[inline(always)]
+fn hash3(i: i32, j: i32, k: i32) -> u32 {
+    let (mut x, mut y, mut z) = (i as u32, j as u32, k as u32);
+    x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+    y = y.wrapping_mul(1664525).wrapping_add(1013904223);
+    z = z.wrapping_mul(1664525).wrapping_add(1013904223);
+    x = x.wrapping_add(y.wrapping_mul(z));
+    y = y.wrapping_add(z.wrapping_mul(x));
+    z = z.wrapping_add(x.wrapping_mul(y));
+    x ^= x >> 16;
+    y ^= y >> 16;
+    z ^= z >> 16;
+    x.wrapping_add(y.wrapping_mul(z))
+        .wrapping_add(z.wrapping_mul(x))
+}
+
+// dot product with one of the twelve edge gradients of improved perlin noise
+#[inline(always)]
+fn noise_gradient(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// improved perlin noise in [-1, 1] [Perlin 2002]
+pub fn perlin_noise(p: &Vec3) -> f32 {
+    let (i, j, k) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (x, y, z) = (p.x - i, p.y - j, p.z - k);
+    let (i, j, k) = (i as i32, j as i32, k as i32);
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let corner = |di: i32, dj: i32, dk: i32| {
+        noise_gradient(
+            hash3(i + di, j + dj, k + dk),
+            x - di as f32,
+            y - dj as f32,
+            z - dk as f32,
+        )
+    };
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+// simplex noise in about [-1, 1] [Gustavson 2005]
+pub fn simplex_noise(p: &Vec3) -> f32 {
+    const F3: f32 = 1.0 / 3.0;
+    const G3: f32 = 1.0 / 6.0;
+    // skew to find the simplex cell
+    let s = (p.x + p.y + p.z) * F3;
+    let (i, j, k) = ((p.x + s).floor(), (p.y + s).floor(), (p.z + s).floor());
+    let t = (i + j + k) * G3;
+    let x0 = vec3(p.x - (i - t), p.y - (j - t), p.z - (k - t));
+    // offsets of the second and third corners, ordered by magnitude
+    let (o1, o2) = if x0.x >= x0.y {
+        if x0.y >= x0.z {
+            ((1, 0, 0), (1, 1, 0))
+        } else if x0.x >= x0.z {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else if x0.y < x0.z {
+        ((0, 0, 1), (0, 1, 1))
+    } else if x0.x < x0.z {
+        ((0, 1, 0), (0, 1, 1))
+    } else {
+        ((0, 1, 0), (1, 1, 0))
+    };
+    let (i, j, k) = (i as i32, j as i32, k as i32);
+    let mut sum = 0.0;
+    for (n, (di, dj, dk)) in [(0, 0, 0), o1, o2, (1, 1, 1)].into_iter().enumerate() {
+        let x = x0 - vec3(di as f32, dj as f32, dk as f32) + vec3(G3, G3, G3) * n as f32;
+        let t = 0.6 - glm::dot(&x, &x);
+        if t > 0.0 {
+            sum += t * t * t * t * noise_gradient(hash3(i + di, j + dj, k + dk), x.x, x.y, x.z);
+        }
+    }
+    32.0 * sum
+}
+
+// fractal sum of noise octaves normalized to [-1, 1], or of their absolute
+// values normalized to [0, 1] for turbulence
+pub fn fractal_noise(
+    p: &Vec3,
+    octaves: u32,
+    lacunarity: f32,
+    gain: f32,
+    simplex: bool,
+    turbulence: bool,
+) -> f32 {
+    let (mut sum, mut norm) = (0.0, 0.0);
+    let (mut frequency, mut amplitude) = (1.0, 1.0);
+    for _ in 0..u32::max(octaves, 1) {
+        let q = p * frequency;
+        let noise = if simplex {
+            simplex_noise(&q)
+        } else {
+            perlin_noise(&q)
+        };
+        sum += amplitude * if turbulence { noise.abs() } else { noise };
+        norm += amplitude;
+        frequency *= lacunarity;
+        amplitude *= gain;
+    }
+    sum / norm
+}
+
+// distance to the closest feature point of a jittered grid [Worley 1996]
+pub fn voronoi_noise(p: &Vec3, jitter: f32) -> f32 {
+    let (i, j, k) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+    let mut distance = f32::MAX;
+    for dk in -1..=1 {
+        for dj in -1..=1 {
+            for di in -1..=1 {
+                let hash = hash3(i + di, j + dj, k + dk);
+                let offset = vec3(
+                    (hash & 1023) as f32 / 1023.0,
+                    ((hash >> 10) & 1023) as f32 / 1023.0,
+                    ((hash >> 20) & 1023) as f32 / 1023.0,
+                );
+                let feature = vec3((i + di) as f32, (j + dj) as f32, (k + dk) as f32)
+                    + vec3(0.5, 0.5, 0.5)
+                    + (offset - vec3(0.5, 0.5, 0.5)) * jitter;
+                distance = f32::min(distance, glm::distance(p, &feature));
+            }
+        }
+    }
+    distance
+}
@@ -0,0 +1,170 @@
+// Shading normals of a unit quad bumped by height maps with known slopes.
+extern crate nalgebra_glm as glm;
+
+use glm::{normalize, vec2, vec3, vec4, Vec3};
+use rtrace::bvh::BvhIntersection;
+use rtrace::scene::Scene;
+use rtrace::scene_components::*;
+use rtrace::trace::Ray;
+
+// quad on z = 0 facing +z with texcoords equal to its positions
+fn quad_scene(texture: Texture, bump_strength: f32) -> Scene {
+    let mut scene = Scene::default();
+    scene.shapes.push(Shape {
+        quads: vec![vec4(0, 1, 2, 3)],
+        positions: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ],
+        texcoords: vec![
+            vec2(0.0, 0.0),
+            vec2(1.0, 0.0),
+            vec2(1.0, 1.0),
+            vec2(0.0, 1.0),
+        ],
+        ..Default::default()
+    });
+    scene.materials.push(Material {
+        bump_tex: 0,
+        bump_strength,
+        ..Default::default()
+    });
+    scene.textures.push(texture);
+    scene.instances.push(Instance {
+        shape: 0,
+        material: 0,
+        ..Default::default()
+    });
+    scene
+}
+
+fn shading_normal(scene: &Scene) -> Vec3 {
+    shading_normal_towards(scene, vec3(0.0, 0.0, 1.0))
+}
+
+fn shading_normal_towards(scene: &Scene, outgoing: Vec3) -> Vec3 {
+    let intersection = BvhIntersection {
+        instance: 0,
+        element: 0,
+        uv: vec2(0.3, 0.4),
+        hit: true,
+        ..Default::default()
+    };
+    let material = scene.eval_material(&intersection, &Ray::default());
+    scene.eval_shading_normal(&intersection, &outgoing, &material)
+}
+
+// height rising by one along the given texcoord axis
+fn ramp(direction: Vec3) -> Texture {
+    Texture {
+        procedural: Some(Procedural {
+            p_type: ProceduralType::Gradient,
+            direction,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn procedural_slope_tilts_normal() {
+    let normal = shading_normal(&quad_scene(ramp(vec3(1.0, 0.0, 0.0)), 0.5));
+    assert!((normal - normalize(&vec3(-0.5, 0.0, 1.0))).norm() < 1e-3);
+    let normal = shading_normal(&quad_scene(ramp(vec3(0.0, 1.0, 0.0)), 2.0));
+    assert!((normal - normalize(&vec3(0.0, -2.0, 1.0))).norm() < 1e-3);
+}
+
+// heights rising by one along v over the image
+fn image_ramp() -> Texture {
+    Texture {
+        width: 1,
+        height: 16,
+        linear: true,
+        wrap: WrapMode::Clamp,
+        hdr: (0..16)
+            .map(|j| image::Rgba([j as f32 / 16.0, 0.0, 0.0, 1.0]))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn image_slope_tilts_normal() {
+    let normal = shading_normal(&quad_scene(image_ramp(), 1.0));
+    assert!((normal - normalize(&vec3(0.0, -1.0, 1.0))).norm() < 1e-3);
+}
+
+#[test]
+fn projected_slope_tilts_normal() {
+    // planar texcoords at twice the positions ignore the shape texcoords
+    let mut scene = quad_scene(image_ramp(), 1.0);
+    scene.materials[0].projection = Projection {
+        p_type: ProjectionType::Planar,
+        scale: 2.0,
+        ..Default::default()
+    };
+    let normal = shading_normal(&scene);
+    assert!((normal - normalize(&vec3(0.0, -2.0, 1.0))).norm() < 1e-3);
+}
+
+#[test]
+fn flat_heights_keep_normal() {
+    let flat = Texture {
+        procedural: Some(Procedural {
+            p_type: ProceduralType::Checker,
+            scale: 0.1,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let normal = shading_normal(&quad_scene(flat, 10.0));
+    assert!((normal - vec3(0.0, 0.0, 1.0)).norm() < 1e-6);
+    let normal = shading_normal(&quad_scene(ramp(vec3(1.0, 0.0, 0.0)), 0.0));
+    assert!((normal - vec3(0.0, 0.0, 1.0)).norm() < 1e-6);
+}
+
+#[test]
+fn projected_bump_needs_no_texcoords() {
+    for p_type in [
+        ProjectionType::Planar,
+        ProjectionType::Box,
+        ProjectionType::Triplanar,
+    ] {
+        let mut scene = quad_scene(image_ramp(), 1.0);
+        scene.shapes[0].texcoords.clear();
+        scene.materials[0].projection = Projection {
+            p_type,
+            scale: 2.0,
+            ..Default::default()
+        };
+        let normal = shading_normal(&scene);
+        assert!(normal.iter().all(|c| c.is_finite()), "{:?}", p_type);
+        assert!(
+            (normal - normalize(&vec3(0.0, -2.0, 1.0))).norm() < 1e-3,
+            "{:?} {}",
+            p_type,
+            normal
+        );
+    }
+}
+
+#[test]
+fn only_transmitting_points_keep_normal_from_behind() {
+    let below = vec3(0.0, 0.0, -1.0);
+    let mut scene = quad_scene(ramp(vec3(1.0, 0.0, 0.0)), 0.0);
+    scene.materials[0].m_type = MaterialType::Principled;
+    scene.materials[0].transmission = 0.5;
+    let normal = shading_normal_towards(&scene, below);
+    assert!((normal - vec3(0.0, 0.0, 1.0)).norm() < 1e-6);
+    // a transmission graph that evaluates to zero makes an opaque point
+    scene.materials[0].nodes = vec![Node {
+        n_type: NodeType::Value,
+        value: vec4(0.0, 0.0, 0.0, 1.0),
+        ..Default::default()
+    }];
+    scene.materials[0].transmission_node = 0;
+    let normal = shading_normal_towards(&scene, below);
+    assert!((normal - vec3(0.0, 0.0, -1.0)).norm() < 1e-6);
+}
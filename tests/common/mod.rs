@@ -0,0 +1,9 @@
+// Helpers shared by the integration tests.
+use std::path::PathBuf;
+
+// path in a temporary directory of each test binary, created on first use
+pub fn scratch(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(concat!("rtrace-", env!("CARGO_CRATE_NAME")));
+    std::fs::create_dir_all(&directory).unwrap();
+    directory.join(name)
+}
@@ -0,0 +1,62 @@
+// Complex IOR of conductors, from named presets or measured spectra.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::vec3;
+use rtrace::scene::Scene;
+use rtrace::scene_components::{conductor_preset, Material, MaterialType};
+
+#[test]
+fn presets_match_spectra_sampled_at_rgb_wavelengths() {
+    for name in ["gold", "copper", "aluminium", "silver", "chrome"] {
+        let (eta, etak) = conductor_preset(name).unwrap();
+        let mut preset = Material {
+            m_type: MaterialType::Conductor,
+            conductor: name.to_string(),
+            ..Default::default()
+        };
+        preset.init_conductor().unwrap();
+        assert_eq!((preset.eta, preset.etak), (eta, etak));
+
+        // the same values as spectra sampled at 630, 532 and 465 nm, with
+        // linear interpolation between the measured wavelengths
+        let mut measured = Material {
+            m_type: MaterialType::Conductor,
+            eta_spectrum: vec![
+                vec3(455.0, eta.z - 0.1, etak.z - 0.1),
+                vec3(475.0, eta.z + 0.1, etak.z + 0.1),
+                vec3(532.0, eta.y, etak.y),
+                vec3(600.0, eta.x + 0.2, etak.x + 0.2),
+                vec3(660.0, eta.x - 0.2, etak.x - 0.2),
+            ],
+            ..Default::default()
+        };
+        measured.init_conductor().unwrap();
+        assert!((measured.eta - eta).amax() < 1e-5, "{}", name);
+        assert!((measured.etak - etak).amax() < 1e-5, "{}", name);
+    }
+}
+
+#[test]
+fn unknown_presets_name_the_scene() {
+    let mut material = Material {
+        m_type: MaterialType::Conductor,
+        conductor: "unobtainium".to_string(),
+        ..Default::default()
+    };
+    assert!(material.init_conductor().is_err());
+
+    let path = scratch("unobtainium.json");
+    let json = r#"{ "materials": [{ "type": "conductor", "conductor": "unobtainium" }] }"#;
+    std::fs::write(&path, json).unwrap();
+    let error = std::panic::catch_unwind(|| Scene::from_file(&path, None)).unwrap_err();
+    let message = error.downcast_ref::<String>().unwrap();
+    assert!(message.contains(&*path.to_string_lossy()), "{}", message);
+    assert!(
+        message.contains("unknown conductor unobtainium"),
+        "{}",
+        message
+    );
+}
@@ -0,0 +1,99 @@
+// Checks of the curve bases used to sweep line shapes.
+extern crate nalgebra_glm as glm;
+
+use glm::{vec2, vec3};
+use rtrace::scene_components::{CurveType, Shape};
+
+const CURVES: [CurveType; 5] = [
+    CurveType::Flat,
+    CurveType::Round,
+    CurveType::Bezier,
+    CurveType::Bspline,
+    CurveType::Catmullrom,
+];
+
+#[test]
+fn bases_partition_unity() {
+    for curve in CURVES {
+        for i in 0..=10 {
+            let (weights, derivatives) = curve.basis(i as f32 / 10.0);
+            assert!((weights.sum() - 1.0).abs() < 1e-5, "{:?}", curve);
+            assert!(derivatives.sum().abs() < 1e-5, "{:?}", curve);
+        }
+    }
+}
+
+#[test]
+fn derivatives_match_finite_differences() {
+    let h = 1e-3;
+    for curve in CURVES {
+        for i in 1..10 {
+            let u = i as f32 / 10.0;
+            let (_, derivatives) = curve.basis(u);
+            let difference = (curve.basis(u + h).0 - curve.basis(u - h).0) / (2.0 * h);
+            assert!((derivatives - difference).amax() < 1e-2, "{:?}", curve);
+        }
+    }
+}
+
+fn strand(curve: CurveType) -> Shape {
+    Shape {
+        lines: vec![glm::vec2(0, 1), glm::vec2(1, 2), glm::vec2(2, 3)],
+        positions: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(3.0, 1.0, 0.0),
+        ],
+        radius: vec![0.1, 0.2, 0.3, 0.4],
+        curve,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn catmullrom_interpolates_strand() {
+    let shape = strand(CurveType::Catmullrom);
+    assert_eq!(shape.curve_controls(0), [0, 0, 1, 2]);
+    assert_eq!(shape.curve_controls(1), [0, 1, 2, 3]);
+    assert_eq!(shape.curve_controls(2), [1, 2, 3, 3]);
+    for element in 0..3 {
+        let line = shape.lines[element];
+        let (start, _, radius) = shape.eval_curve(element, 0.0);
+        let end = shape.eval_position(element, &vec2(1.0, 0.0));
+        assert!((start - shape.positions[line.x as usize]).amax() < 1e-5);
+        assert!((end - shape.positions[line.y as usize]).amax() < 1e-5);
+        assert!((radius - shape.radius[line.x as usize]).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn bezier_uses_handles_after_the_first_point() {
+    let shape = Shape {
+        lines: vec![glm::vec2(0, 3)],
+        ..strand(CurveType::Bezier)
+    };
+    assert_eq!(shape.curve_controls(0), [0, 1, 2, 3]);
+    let (start, tangent, _) = shape.eval_curve(0, 0.0);
+    assert!((start - vec3(0.0, 0.0, 0.0)).amax() < 1e-5);
+    assert!((tangent - glm::normalize(&vec3(1.0, 1.0, 0.0))).amax() < 1e-5);
+    let (middle, tangent, _) = shape.eval_curve(0, 0.5);
+    assert!((middle - vec3(1.5, 0.5, 0.0)).amax() < 1e-5);
+    assert!((tangent - vec3(1.0, 0.0, 0.0)).amax() < 1e-5);
+}
+
+#[test]
+fn bezier_rejects_lines_without_handles() {
+    assert!(strand(CurveType::Bezier).check_curves().is_err());
+    assert!(strand(CurveType::Catmullrom).check_curves().is_ok());
+    let shape = Shape {
+        lines: vec![glm::vec2(0, 3)],
+        ..strand(CurveType::Bezier)
+    };
+    assert!(shape.check_curves().is_ok());
+    let shape = Shape {
+        lines: vec![glm::vec2(1, 4)],
+        ..strand(CurveType::Bezier)
+    };
+    assert!(shape.check_curves().is_err());
+}
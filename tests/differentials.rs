@@ -0,0 +1,99 @@
+// Ray differentials carried through delta bounces.
+extern crate nalgebra_glm as glm;
+
+use glm::{normalize, vec3, Vec3};
+use rtrace::scene_components::MaterialType;
+use rtrace::shading::MaterialPoint;
+use rtrace::trace::{specular_differential, Ray, RayDifferential};
+
+fn close(a: &Vec3, b: &Vec3) -> bool {
+    (a - b).norm() < 1e-5
+}
+
+// where a ray crosses the z = 0 plane and its mirrored direction
+fn mirrored(origin: &Vec3, direction: &Vec3) -> (Vec3, Vec3) {
+    let position = origin + direction * (-origin.z / direction.z);
+    (position, vec3(direction.x, direction.y, -direction.z))
+}
+
+#[test]
+fn mirror_reflects_offset_rays() {
+    let mirror = MaterialPoint {
+        m_type: MaterialType::Reflective,
+        roughness: 0.0,
+        ..Default::default()
+    };
+    let normal = vec3(0.0, 0.0, 1.0);
+    let origin = vec3(-1.0, 0.0, 1.0);
+    let ray = Ray {
+        differential: Some(RayDifferential {
+            rx_origin: origin,
+            rx_direction: normalize(&vec3(1.02, 0.0, -1.0)),
+            ry_origin: origin,
+            ry_direction: normalize(&vec3(1.0, 0.02, -1.0)),
+        }),
+        ..Ray::new(origin, normalize(&vec3(1.0, 0.0, -1.0)))
+    };
+    let (position, incoming) = mirrored(&ray.origin, &ray.direction);
+    let reflected = specular_differential(&ray, &position, &normal, &incoming, &mirror).unwrap();
+
+    // the mirror is flat, so the offset rays are reflected exactly
+    let differential = ray.differential.unwrap();
+    let (rx_origin, rx_direction) = mirrored(&differential.rx_origin, &differential.rx_direction);
+    assert!(close(&reflected.rx_origin, &rx_origin));
+    assert!(close(&reflected.rx_direction, &rx_direction));
+    let (ry_origin, ry_direction) = mirrored(&differential.ry_origin, &differential.ry_direction);
+    assert!(close(&reflected.ry_origin, &ry_origin));
+    assert!(close(&reflected.ry_direction, &ry_direction));
+}
+
+#[test]
+fn transmissive_materials_refract_offset_rays_to_first_order() {
+    let glass = MaterialPoint {
+        m_type: MaterialType::Refractive,
+        roughness: 0.0,
+        ior: 1.5,
+        ..Default::default()
+    };
+    let skin = MaterialPoint {
+        m_type: MaterialType::Subsurface,
+        ..glass.clone()
+    };
+    let principled = MaterialPoint {
+        m_type: MaterialType::Principled,
+        transmission: 1.0,
+        ..glass.clone()
+    };
+    let normal = vec3(0.0, 0.0, 1.0);
+    let refracted = |direction: &Vec3| glm::refract_vec(direction, &normal, 1.0 / 1.5);
+    let origin = vec3(-1.0, 0.0, 1.0);
+    let ray = Ray {
+        differential: Some(RayDifferential {
+            rx_origin: origin,
+            rx_direction: normalize(&vec3(1.002, 0.0, -1.0)),
+            ry_origin: origin,
+            ry_direction: normalize(&vec3(1.0, 0.002, -1.0)),
+        }),
+        ..Ray::new(origin, normalize(&vec3(1.0, 0.0, -1.0)))
+    };
+    let (position, _) = mirrored(&ray.origin, &ray.direction);
+    let incoming = refracted(&ray.direction);
+    let differential = ray.differential.as_ref().unwrap();
+    for material in [glass, skin, principled] {
+        let transmitted =
+            specular_differential(&ray, &position, &normal, &incoming, &material).unwrap();
+        for (direction, expected) in [
+            (transmitted.rx_direction, differential.rx_direction),
+            (transmitted.ry_direction, differential.ry_direction),
+        ] {
+            let expected = refracted(&expected);
+            assert!(
+                (direction - expected).norm() < 1e-5,
+                "{:?} {} {}",
+                material.m_type,
+                direction,
+                expected
+            );
+        }
+    }
+}
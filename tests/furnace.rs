@@ -0,0 +1,114 @@
+// Whole scenes traced in a white furnace, where closed objects that neither
+// absorb nor emit look as bright as the environment.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::{vec3, Vec3};
+use parking_lot::Mutex;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rtrace::bvh::BvhData;
+use rtrace::scene::Scene;
+use rtrace::trace::{shade_raytrace, Ray};
+use rtrace::utils::RaytraceParams;
+
+// a unit sphere of the given material under a white environment
+fn furnace(name: &str, material: &str) -> Scene {
+    let json = format!(
+        r#"{{
+            "environments": [{{ "emission": [1, 1, 1] }}],
+            "shapes": [{{ "primitive": {{ "type": "sphere", "radius": 1 }} }}],
+            "materials": [{}],
+            "instances": [{{ "shape": 0, "material": 0 }}]
+        }}"#,
+        material
+    );
+    let path = scratch(name);
+    std::fs::write(&path, json).unwrap();
+    Scene::from_file(&path, None)
+}
+
+// mean radiance of rays hitting the sphere head on
+fn mean_radiance(scene: &Scene, paths: usize) -> Vec3 {
+    let device = embree::Device::new();
+    let bvh = BvhData::from_scene(&device, scene, false);
+    let params = RaytraceParams {
+        bounces: 256,
+        clamp: 0.0,
+        ..Default::default()
+    };
+    let rng = Mutex::new(SmallRng::seed_from_u64(5));
+    let mut radiance = Vec3::zeros();
+    for _ in 0..paths {
+        let (x, y) = loop {
+            let (x, y) = rng.lock().gen::<(f32, f32)>();
+            let (x, y) = (2.0 * x - 1.0, 2.0 * y - 1.0);
+            if x * x + y * y < 0.8 {
+                break (x, y);
+            }
+        };
+        let mut ray = Ray::new(vec3(x, y, 5.0), vec3(0.0, 0.0, -1.0));
+        radiance += shade_raytrace(scene, &bvh, &mut ray, &rng, &params).xyz() / paths as f32;
+    }
+    radiance
+}
+
+#[test]
+fn white_subsurface_conserves_energy() {
+    for (name, ior) in [("index_matched.json", 1.0), ("glass.json", 1.5)] {
+        let material = format!(
+            r#"{{ "type": "subsurface", "color": [1, 1, 1], "ior": {}, "trdepth": 0.2 }}"#,
+            ior
+        );
+        let radiance = mean_radiance(&furnace(name, &material), 20_000);
+        assert!(
+            (radiance - vec3(1.0, 1.0, 1.0)).amax() < 0.02,
+            "{}",
+            radiance
+        );
+    }
+}
+
+#[test]
+fn subsurface_radius_is_scaled_per_channel() {
+    // with a grey albedo, channels that scatter sooner take more steps to
+    // leave the sphere and are absorbed more, down to the albedo itself
+    let material = r#"{ "type": "subsurface", "color": [0.5, 0.5, 0.5], "ior": 1.0,
+        "trdepth": 0.5, "scatter_radius": [1, 0.1, 0.1] }"#;
+    let radiance = mean_radiance(&furnace("grey.json", material), 20_000);
+    assert!(radiance.x > radiance.y + 0.2, "{}", radiance);
+    assert!((radiance.y - 0.5).abs() < 0.1, "{}", radiance);
+}
+
+#[test]
+fn principled_glass_matches_refractive() {
+    // paths leaving the sphere refract through the inner side of the
+    // surface, which needs the unflipped normal and the medium stack; rough
+    // glass only loses the energy of the single scattering microfacets
+    for (roughness, albedo) in [(0.05, 0.98), (0.3, 0.95)] {
+        let refractive = format!(
+            r#"{{ "type": "refractive", "color": [1, 1, 1], "ior": 1.5,
+                "roughness": {} }}"#,
+            roughness
+        );
+        let principled = format!(
+            r#"{{ "type": "principled", "color": [1, 1, 1], "ior": 1.5,
+                "roughness": {}, "transmission": 1 }}"#,
+            roughness
+        );
+        let expected = mean_radiance(&furnace("refractive.json", &refractive), 20_000);
+        let radiance = mean_radiance(&furnace("principled.json", &principled), 20_000);
+        assert!(
+            (radiance - expected).amax() < 1e-3,
+            "{} {}",
+            radiance,
+            expected
+        );
+        assert!(
+            radiance.min() > albedo && radiance.max() < 1.01,
+            "{}",
+            radiance
+        );
+    }
+}
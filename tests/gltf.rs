@@ -0,0 +1,347 @@
+// Scenes imported from glTF files and GLB containers written on the fly.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::{vec2, vec3, Vec3};
+use rtrace::model_io::gltf::read_gltf;
+use rtrace::scene::Scene;
+use rtrace::scene_components::*;
+use rtrace::utils::transform_point;
+use serde_json::json;
+use std::path::PathBuf;
+
+fn base64(data: &[u8]) -> String {
+    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(alphabet[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+// a unit quad as positions, texcoords and 16-bit indices, followed by four
+// normalized 8-bit colors
+fn quad_buffer() -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for value in [
+        0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, // positions
+        0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, // texcoords
+    ] {
+        buffer.extend(value.to_le_bytes());
+    }
+    for index in [0u16, 1, 2, 0, 2, 3] {
+        buffer.extend(index.to_le_bytes());
+    }
+    buffer.extend([255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+    buffer
+}
+
+// two instances of the quad under a translated parent, one with a clearcoat
+// material and one with none, and a camera
+fn quad_gltf(buffer: serde_json::Value) -> serde_json::Value {
+    json!({
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0]}],
+        "nodes": [
+            {"translation": [0.0, 0.0, -2.0], "children": [1, 2, 3]},
+            {"mesh": 0, "scale": [2.0, 2.0, 2.0]},
+            {"mesh": 1, "matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 5, 0, 0, 1]},
+            {"camera": 0, "translation": [0.0, 0.0, 4.0]}
+        ],
+        "meshes": [
+            {"primitives": [{
+                "attributes": {"POSITION": 0, "TEXCOORD_0": 1, "COLOR_0": 3},
+                "indices": 2,
+                "material": 0
+            }]},
+            {"primitives": [{"attributes": {"POSITION": 0}, "mode": 6}]}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3"},
+            {"bufferView": 0, "byteOffset": 48, "componentType": 5126, "count": 4, "type": "VEC2"},
+            {"bufferView": 1, "componentType": 5123, "count": 6, "type": "SCALAR"},
+            {"bufferView": 2, "componentType": 5121, "normalized": true, "count": 4, "type": "VEC3"}
+        ],
+        "bufferViews": [
+            {"buffer": 0, "byteLength": 80},
+            {"buffer": 0, "byteOffset": 80, "byteLength": 12},
+            {"buffer": 0, "byteOffset": 92, "byteLength": 12}
+        ],
+        "buffers": [buffer],
+        "materials": [{
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [0.5, 0.25, 1.0, 0.5],
+                "metallicFactor": 0.0,
+                "roughnessFactor": 0.3
+            },
+            "emissiveFactor": [1.0, 0.5, 0.0],
+            "alphaMode": "BLEND",
+            "extensions": {
+                "KHR_materials_emissive_strength": {"emissiveStrength": 4.0},
+                "KHR_materials_clearcoat": {"clearcoatFactor": 0.8, "clearcoatRoughnessFactor": 0.1}
+            }
+        }],
+        "cameras": [{
+            "type": "perspective",
+            "perspective": {"yfov": 0.5, "aspectRatio": 2.0, "znear": 0.1}
+        }]
+    })
+}
+
+// glb container with the json and the buffer as binary chunk
+fn write_glb(name: &str, gltf: &serde_json::Value, buffer: &[u8]) -> PathBuf {
+    let mut json = gltf.to_string().into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    let mut glb = Vec::new();
+    glb.extend(b"glTF");
+    glb.extend(2u32.to_le_bytes());
+    glb.extend(((12 + 8 + json.len() + 8 + buffer.len()) as u32).to_le_bytes());
+    glb.extend((json.len() as u32).to_le_bytes());
+    glb.extend(b"JSON");
+    glb.extend(&json);
+    glb.extend((buffer.len() as u32).to_le_bytes());
+    glb.extend(b"BIN\0");
+    glb.extend(buffer);
+    let path = scratch(name);
+    std::fs::write(&path, glb).unwrap();
+    path
+}
+
+fn close(a: &Vec3, b: &Vec3) -> bool {
+    (a - b).norm() < 1e-5
+}
+
+#[test]
+fn gltf_maps_meshes_nodes_materials_and_cameras() {
+    let buffer = quad_buffer();
+    let uri = format!("data:application/octet-stream;base64,{}", base64(&buffer));
+    let gltf = quad_gltf(json!({"uri": uri, "byteLength": buffer.len()}));
+    let path = scratch("quad.gltf");
+    std::fs::write(&path, gltf.to_string()).unwrap();
+    let scene = Scene::from_file(&path, None);
+
+    assert_eq!(scene.shapes.len(), 2);
+    let shape = &scene.shapes[0];
+    assert_eq!(
+        shape.triangles,
+        vec![glm::vec3(0, 1, 2), glm::vec3(0, 2, 3)]
+    );
+    assert_eq!(shape.texcoords[1], vec2(1.0, 1.0));
+    assert_eq!(shape.colors[2], glm::vec4(0.0, 0.0, 1.0, 1.0));
+    // tangents are only generated for normal mapped shapes
+    assert!(shape.tangents.is_empty());
+    // fans share their first vertex
+    assert_eq!(
+        scene.shapes[1].triangles,
+        vec![glm::vec3(0, 1, 2), glm::vec3(0, 2, 3)]
+    );
+
+    // node transforms compose down the hierarchy
+    assert_eq!(scene.instances.len(), 2);
+    let corner = vec3(1.0, 1.0, 0.0);
+    let frame = &scene.instances[0].frame;
+    assert!(close(
+        &transform_point(frame, &corner),
+        &vec3(2.0, 2.0, -2.0)
+    ));
+    let frame = &scene.instances[1].frame;
+    assert!(close(
+        &transform_point(frame, &corner),
+        &vec3(6.0, 1.0, -2.0)
+    ));
+
+    let material = &scene.materials[scene.instances[0].material];
+    assert_eq!(material.m_type, MaterialType::Principled);
+    assert!(close(&material.color, &vec3(0.5, 0.25, 1.0)));
+    assert_eq!((material.opacity, material.metallic), (0.5, 0.0));
+    assert_eq!(material.roughness, 0.3);
+    assert!(close(&material.emission, &vec3(4.0, 2.0, 0.0)));
+    assert_eq!(material.clearcoat, 0.8);
+    // primitives without a material get the glTF default
+    let material = &scene.materials[scene.instances[1].material];
+    assert_eq!(material.m_type, MaterialType::Gltfpbr);
+    assert_eq!((material.metallic, material.roughness), (1.0, 1.0));
+    assert!(close(&material.color, &vec3(1.0, 1.0, 1.0)));
+
+    let camera = &scene.cameras[0];
+    assert!(close(&camera.frame.column(3).into(), &vec3(0.0, 0.0, 2.0)));
+    assert_eq!(camera.aspect, 2.0);
+    // the vertical field of view is kept
+    let film_height = camera.film / camera.aspect;
+    assert!((2.0 * f32::atan(film_height / (2.0 * camera.lens)) - 0.5).abs() < 1e-5);
+}
+
+#[test]
+fn glb_reads_binary_chunk_and_embedded_images() {
+    let mut buffer = quad_buffer();
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(4, 2, |i, j| {
+        image::Rgba([(i * 60) as u8, (j * 200) as u8, 0, 255])
+    }))
+    .write_to(&mut png, image::ImageOutputFormat::Png)
+    .unwrap();
+    let image_offset = buffer.len();
+    buffer.extend(&png);
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+    let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+    gltf["bufferViews"].as_array_mut().unwrap().push(json!({
+        "buffer": 0, "byteOffset": image_offset, "byteLength": png.len()
+    }));
+    gltf["images"] = json!([{"bufferView": 3, "mimeType": "image/png"}]);
+    gltf["samplers"] = json!([{"wrapS": 33071}]);
+    gltf["textures"] = json!([{"source": 0, "sampler": 0}]);
+    gltf["materials"][0]["pbrMetallicRoughness"]["baseColorTexture"] = json!({"index": 0});
+    let scene = Scene::from_file(&write_glb("quad.glb", &gltf, &buffer), None);
+
+    assert_eq!(scene.shapes[0].positions[2], vec3(1.0, 1.0, 0.0));
+    let material = &scene.materials[scene.instances[0].material];
+    assert_eq!(material.color_tex, 0);
+    let texture = &scene.textures[0];
+    assert_eq!((texture.width, texture.height), (4, 2));
+    assert_eq!(texture.wrap, WrapMode::Clamp);
+    assert_eq!(
+        texture.lookup(3, 1, false),
+        glm::vec4(180.0, 200.0, 0.0, 255.0) / 255.0
+    );
+    assert!(texture.mip_count() > 1);
+}
+
+#[test]
+fn mistyped_or_oversized_accessors_are_rejected() {
+    let buffer = quad_buffer();
+    let read = |name: &str, gltf: &serde_json::Value| {
+        read_gltf(&write_glb(name, gltf, &buffer), &mut Scene::default())
+    };
+    assert!(read(
+        "accessors.glb",
+        &quad_gltf(json!({"byteLength": buffer.len()}))
+    )
+    .is_ok());
+    // positions read as texcoords and the other way around
+    for (accessor, a_type) in [(0, "VEC2"), (1, "SCALAR"), (1, "VEC3"), (2, "VEC2")] {
+        let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+        gltf["accessors"][accessor]["type"] = json!(a_type);
+        assert!(
+            read("accessors.glb", &gltf).is_err(),
+            "{} {}",
+            accessor,
+            a_type
+        );
+    }
+    // counts beyond the view, and large enough to overflow its size
+    for count in [7, usize::MAX / 2] {
+        let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+        gltf["accessors"][0]["count"] = json!(count);
+        assert!(read("accessors.glb", &gltf).is_err(), "{}", count);
+    }
+    // sparse substitutions would be silently dropped
+    let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+    gltf["accessors"][0]["sparse"] = json!({
+        "count": 1,
+        "indices": {"bufferView": 2, "componentType": 5123},
+        "values": {"bufferView": 0}
+    });
+    let error = read("accessors.glb", &gltf).unwrap_err();
+    assert_eq!(error.to_string(), "unsupported sparse accessor");
+}
+
+#[test]
+fn samplers_wrap_with_the_more_restrictive_axis() {
+    let buffer = quad_buffer();
+    let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+    gltf["images"] = json!([{"uri": "color.png"}]);
+    gltf["samplers"] = json!([
+        {},
+        {"wrapT": 33648},
+        {"wrapS": 33648, "wrapT": 33071},
+        {"wrapS": 33071, "wrapT": 10497}
+    ]);
+    gltf["textures"] = json!([
+        {"source": 0},
+        {"source": 0, "sampler": 0},
+        {"source": 0, "sampler": 1},
+        {"source": 0, "sampler": 2},
+        {"source": 0, "sampler": 3}
+    ]);
+    let mut scene = Scene::default();
+    read_gltf(&write_glb("samplers.glb", &gltf, &buffer), &mut scene).unwrap();
+    let wraps: Vec<_> = scene.textures.iter().map(|texture| texture.wrap).collect();
+    assert_eq!(
+        wraps,
+        [
+            WrapMode::Repeat,
+            WrapMode::Repeat,
+            WrapMode::Mirror,
+            WrapMode::Clamp,
+            WrapMode::Clamp
+        ]
+    );
+}
+
+#[test]
+fn clearcoat_roughness_has_its_own_texture() {
+    let buffer = quad_buffer();
+    let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+    gltf["images"] = json!([{"uri": "clearcoat.png"}, {"uri": "roughness.png"}]);
+    gltf["textures"] = json!([{"source": 0}, {"source": 1}]);
+    let clearcoat = &mut gltf["materials"][0]["extensions"]["KHR_materials_clearcoat"];
+    clearcoat["clearcoatTexture"] = json!({"index": 0});
+    clearcoat["clearcoatRoughnessTexture"] = json!({"index": 1});
+    let mut scene = Scene::default();
+    read_gltf(&write_glb("clearcoat.glb", &gltf, &buffer), &mut scene).unwrap();
+    let material = &scene.materials[0];
+    assert_eq!(material.clearcoat_tex, 0);
+    assert_eq!(material.clearcoat_roughness_tex, 1);
+}
+
+#[test]
+fn glb_without_cameras_gets_a_framing_camera() {
+    let buffer = quad_buffer();
+    let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+    gltf["nodes"].as_array_mut().unwrap().pop();
+    gltf["nodes"][0]["children"] = json!([1, 2]);
+    gltf.as_object_mut().unwrap().remove("cameras");
+    let scene = Scene::from_file(&write_glb("cameraless.glb", &gltf, &buffer), None);
+
+    // the instances span x in [0, 6] and y in [0, 2] on z = -2
+    assert_eq!(scene.cameras.len(), 1);
+    let camera = &scene.cameras[0];
+    let origin: Vec3 = camera.frame.column(3).into();
+    assert!(close(
+        &vec3(origin.x, origin.y, -2.0),
+        &vec3(3.0, 1.0, -2.0)
+    ));
+    assert!((camera.focus - (origin.z + 2.0)).abs() < 1e-5 && camera.focus > 0.0);
+}
+
+#[test]
+fn empty_scenes_get_a_finite_camera() {
+    let scene = Scene::default();
+    let camera = scene.framing_camera();
+    assert!(camera.frame.iter().all(|value| value.is_finite()));
+    assert!(camera.focus.is_finite() && camera.focus > 0.0);
+
+    // a glb with nodes but no meshes
+    let buffer = quad_buffer();
+    let mut gltf = quad_gltf(json!({"byteLength": buffer.len()}));
+    gltf["nodes"] = json!([{"translation": [1.0, 2.0, 3.0]}]);
+    gltf.as_object_mut().unwrap().remove("cameras");
+    let scene = Scene::from_file(&write_glb("empty.glb", &gltf, &buffer), None);
+    assert!(scene.instances.is_empty());
+    let origin: Vec3 = scene.cameras[0].frame.column(3).into();
+    assert!(origin.iter().all(|value| value.is_finite()));
+}
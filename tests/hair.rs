@@ -0,0 +1,180 @@
+// Energy and sampling checks for the hair lobe, following the tests of the
+// reference implementation in pbrt.
+extern crate nalgebra_glm as glm;
+
+use glm::{vec2, vec3, Vec3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rtrace::bvh::BvhIntersection;
+use rtrace::scene::Scene;
+use rtrace::scene_components::*;
+use rtrace::shading::MaterialPoint;
+use rtrace::trace::Ray;
+use std::f32::consts::PI;
+
+const SAMPLES: usize = 200_000;
+
+fn hair(roughness: f32, azimuthal_roughness: f32, offset: f32) -> MaterialPoint {
+    MaterialPoint {
+        m_type: MaterialType::Hair,
+        // a white color maps to no absorption
+        color: vec3(1.0, 1.0, 1.0),
+        roughness,
+        azimuthal_roughness,
+        ior: 1.55,
+        tangent: vec3(1.0, 0.0, 0.0),
+        hair_offset: offset,
+        ..Default::default()
+    }
+}
+
+fn sample_sphere(rn: (f32, f32)) -> Vec3 {
+    let z = 1.0 - 2.0 * rn.0;
+    let r = f32::sqrt(f32::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * PI * rn.1;
+    vec3(r * f32::cos(phi), r * f32::sin(phi), z)
+}
+
+fn outgoing_directions() -> Vec<Vec3> {
+    vec![
+        vec3(0.0, 0.0, 1.0),
+        glm::normalize(&vec3(0.6, 0.1, 0.8)),
+        glm::normalize(&vec3(-0.3, 0.5, 0.4)),
+    ]
+}
+
+fn parameters() -> Vec<(f32, f32, f32)> {
+    let mut parameters = vec![];
+    for beta_m in [0.2, 0.5, 0.8] {
+        for beta_n in [0.3, 0.7] {
+            for offset in [-0.7, 0.0, 0.4] {
+                parameters.push((beta_m * beta_m, beta_n, offset));
+            }
+        }
+    }
+    parameters
+}
+
+#[test]
+fn hair_white_furnace() {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let mut rng = SmallRng::seed_from_u64(5);
+    for (roughness, azimuthal_roughness, offset) in parameters() {
+        let material = hair(roughness, azimuthal_roughness, offset);
+        for outgoing in outgoing_directions() {
+            let mut sum = Vec3::zeros();
+            for _ in 0..SAMPLES {
+                let incoming = sample_sphere((rng.gen(), rng.gen()));
+                sum += material.eval_bsdfcos(&normal, &outgoing, &incoming);
+            }
+            let albedo = sum * (4.0 * PI / SAMPLES as f32);
+            for c in albedo.iter() {
+                assert!(
+                    (c - 1.0).abs() < 0.05,
+                    "roughness {} offset {}: albedo {}",
+                    roughness,
+                    offset,
+                    c
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn hair_sampling_weights_are_one() {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let mut rng = SmallRng::seed_from_u64(9);
+    for (roughness, azimuthal_roughness, offset) in parameters() {
+        let material = hair(roughness, azimuthal_roughness, offset);
+        for outgoing in outgoing_directions() {
+            for _ in 0..2_000 {
+                let rn = vec2(rng.gen::<f32>(), rng.gen::<f32>());
+                let incoming = material.sample_bsdfcos(&normal, &outgoing, rng.gen(), &rn);
+                let pdf = material.sample_bsdfcos_pdf(&normal, &outgoing, &incoming);
+                if pdf <= 0.0 {
+                    continue;
+                }
+                let weight = material.eval_bsdfcos(&normal, &outgoing, &incoming) / pdf;
+                for c in weight.iter() {
+                    assert!((c - 1.0).abs() < 0.01, "sample weight {}", c);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn hair_pdf_integrates_to_one() {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let mut rng = SmallRng::seed_from_u64(13);
+    let material = MaterialPoint {
+        melanin: 1.3,
+        melanin_redness: 0.3,
+        ..hair(0.09, 0.3, 0.2)
+    };
+    for outgoing in outgoing_directions() {
+        let mut sum = 0.0;
+        for _ in 0..SAMPLES {
+            let incoming = sample_sphere((rng.gen(), rng.gen()));
+            sum += material.sample_bsdfcos_pdf(&normal, &outgoing, &incoming);
+        }
+        let integral = sum * 4.0 * PI / SAMPLES as f32;
+        assert!(
+            (integral - 1.0).abs() < 0.05,
+            "pdf integrates to {}",
+            integral
+        );
+    }
+}
+
+#[test]
+fn melanin_absorbs_blue_more_than_red() {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let mut rng = SmallRng::seed_from_u64(17);
+    let material = MaterialPoint {
+        melanin: 0.5,
+        ..hair(0.09, 0.3, 0.0)
+    };
+    let outgoing = vec3(0.0, 0.0, 1.0);
+    let mut sum = Vec3::zeros();
+    for _ in 0..SAMPLES {
+        let incoming = sample_sphere((rng.gen(), rng.gen()));
+        sum += material.eval_bsdfcos(&normal, &outgoing, &incoming);
+    }
+    let albedo = sum * (4.0 * PI / SAMPLES as f32);
+    assert!(albedo.x < 1.0 && albedo.z < albedo.y && albedo.y < albedo.x);
+}
+
+#[test]
+fn offset_comes_from_flat_curves_only() {
+    let offset = |curve: CurveType| {
+        let mut scene = Scene::default();
+        scene.shapes.push(Shape {
+            lines: vec![glm::vec2(0, 1)],
+            positions: vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0)],
+            radius: vec![0.1, 0.1],
+            curve,
+            ..Default::default()
+        });
+        scene.materials.push(Material {
+            m_type: MaterialType::Hair,
+            ..Default::default()
+        });
+        scene.instances.push(Instance {
+            shape: 0,
+            material: 0,
+            ..Default::default()
+        });
+        let intersection = BvhIntersection {
+            instance: 0,
+            element: 0,
+            uv: vec2(0.5, 0.9),
+            hit: true,
+            ..Default::default()
+        };
+        let ray = Ray::new(vec3(0.5, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        scene.eval_material(&intersection, &ray).hair_offset
+    };
+    assert!((offset(CurveType::Flat) - 0.8).abs() < 1e-5);
+    assert_eq!(offset(CurveType::Round), 0.0);
+}
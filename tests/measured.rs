@@ -0,0 +1,150 @@
+// Measured brdfs: table evaluation, MERL parsing and importance sampling.
+extern crate nalgebra_glm as glm;
+
+use glm::{normalize, vec2, vec3, Vec3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rtrace::model_io::brdf::read_merl;
+use rtrace::scene_components::{MaterialType, MeasuredBrdf};
+use rtrace::shading::MaterialPoint;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// tabulates a function of the half and difference angles in the MERL layout
+fn tabulate(f: impl Fn(f32, f32) -> Vec3) -> MeasuredBrdf {
+    let mut brdf = MeasuredBrdf::default();
+    for i in 0..brdf.theta_half {
+        let theta_half = (i as f32 / brdf.theta_half as f32).powi(2) * PI / 2.0;
+        for j in 0..brdf.theta_diff {
+            let theta_diff = j as f32 / brdf.theta_diff as f32 * PI / 2.0;
+            for _ in 0..brdf.phi_diff {
+                brdf.values.push(f(theta_half, theta_diff));
+            }
+        }
+    }
+    brdf.init_sampling();
+    brdf
+}
+
+fn material(brdf: MeasuredBrdf) -> MaterialPoint {
+    MaterialPoint {
+        m_type: MaterialType::Measured,
+        roughness: 1.0,
+        brdf: Some(Arc::new(brdf)),
+        ..Default::default()
+    }
+}
+
+// albedo estimated with the material sampling and with uniform directions
+fn albedos(material: &MaterialPoint, outgoing: &Vec3) -> (f32, f32) {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let mut rng = SmallRng::seed_from_u64(3);
+    let samples = 200_000;
+    let (mut sampled, mut uniform) = (0.0, 0.0);
+    for _ in 0..samples {
+        let rn = vec2(rng.gen::<f32>(), rng.gen::<f32>());
+        let incoming = material.sample_bsdfcos(&normal, outgoing, rng.gen::<f32>(), &rn);
+        let pdf = material.sample_bsdfcos_pdf(&normal, outgoing, &incoming);
+        if pdf > 0.0 {
+            sampled += material.eval_bsdfcos(&normal, outgoing, &incoming).x / pdf;
+        }
+        let z = rng.gen::<f32>();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let r = f32::sqrt(1.0 - z * z);
+        let incoming = vec3(r * f32::cos(phi), r * f32::sin(phi), z);
+        uniform += material.eval_bsdfcos(&normal, outgoing, &incoming).x * 2.0 * PI;
+    }
+    (sampled / samples as f32, uniform / samples as f32)
+}
+
+#[test]
+fn constant_table_is_lambertian() {
+    let material = material(tabulate(|_, _| vec3(0.5, 0.5, 0.5) / PI));
+    let normal = vec3(0.0, 0.0, 1.0);
+    let outgoing = normalize(&vec3(0.3, -0.2, 0.9));
+    let incoming = normalize(&vec3(-0.6, 0.1, 0.4));
+    let value = material.eval_bsdfcos(&normal, &outgoing, &incoming);
+    assert!(
+        (value.x - 0.5 / PI * incoming.z).abs() < 1e-5,
+        "{}",
+        value.x
+    );
+    let (sampled, _) = albedos(&material, &outgoing);
+    assert!((sampled - 0.5).abs() < 0.01, "{}", sampled);
+}
+
+#[test]
+fn glossy_table_is_importance_sampled() {
+    let material = material(tabulate(|theta_half, _| {
+        let lobe = f32::exp(-theta_half * theta_half / 0.02) * 20.0 + 0.05;
+        vec3(lobe, lobe, lobe)
+    }));
+    for outgoing in [vec3(0.0, 0.0, 1.0), normalize(&vec3(0.6, 0.3, 0.5))] {
+        let (sampled, uniform) = albedos(&material, &outgoing);
+        assert!(
+            (sampled - uniform).abs() < 0.03 * uniform,
+            "sampled {} uniform {}",
+            sampled,
+            uniform
+        );
+    }
+}
+
+#[test]
+fn merl_binary_is_parsed() {
+    let (nh, nd, np) = (2i32, 3, 4);
+    let samples = (nh * nd * np) as usize;
+    let mut bytes = Vec::new();
+    for value in [nh, nd, np] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for channel in 0..3 {
+        for i in 0..samples {
+            let value = if i == 5 {
+                -1.0
+            } else {
+                (channel * 100 + i) as f64
+            };
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    let mut brdf = MeasuredBrdf::default();
+    read_merl(&mut bytes.as_slice(), &mut brdf).unwrap();
+    assert_eq!((brdf.theta_half, brdf.theta_diff, brdf.phi_diff), (2, 3, 4));
+    assert_eq!(brdf.values.len(), samples);
+    assert_eq!(brdf.values[5], vec3(0.0, 0.0, 0.0));
+    let value = brdf.lookup(1, 2, 3);
+    let expected = vec3(23.0 / 1500.0, 123.0 * 1.15 / 1500.0, 223.0 * 1.66 / 1500.0);
+    assert!((value - expected).norm() < 1e-6, "{:?}", value);
+
+    let truncated = &bytes[..bytes.len() - 8];
+    assert!(read_merl(&mut &truncated[..], &mut MeasuredBrdf::default()).is_err());
+
+    // resolutions whose product overflows are rejected before reading
+    let mut header = Vec::new();
+    for value in [i32::MAX, i32::MAX, 4] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    assert!(read_merl(&mut header.as_slice(), &mut MeasuredBrdf::default()).is_err());
+}
+
+#[test]
+fn angles_follow_the_table_layout() {
+    let brdf = tabulate(|theta_half, theta_diff| vec3(theta_half, theta_diff, 0.0));
+    // mirror configuration, the halfway vector is the normal
+    let theta = 0.5_f32;
+    let outgoing = vec3(f32::sin(theta), 0.0, f32::cos(theta));
+    let incoming = vec3(-f32::sin(theta), 0.0, f32::cos(theta));
+    let value = brdf.eval(&outgoing, &incoming);
+    assert!(
+        value.x.abs() < 1e-3 && (value.y - theta).abs() < 1e-2,
+        "{:?}",
+        value
+    );
+    // retro-reflection, the difference angle vanishes
+    let value = brdf.eval(&outgoing, &outgoing);
+    assert!(
+        (value.x - theta).abs() < 1e-2 && value.y.abs() < 1e-2,
+        "{:?}",
+        value
+    );
+}
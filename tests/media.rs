@@ -0,0 +1,95 @@
+// Nested participating media: which medium a ray travels in, how crossing
+// interfaces updates the stack and how refraction sees the enclosing index.
+extern crate nalgebra_glm as glm;
+
+use glm::{dot, normalize, vec3};
+use rtrace::scene_components::MaterialType;
+use rtrace::shading::MaterialPoint;
+use rtrace::trace::{current_medium, toggle_medium};
+
+fn medium(priority: i32, ior: f32) -> MaterialPoint {
+    MaterialPoint {
+        m_type: MaterialType::Refractive,
+        roughness: 0.0,
+        priority,
+        ior,
+        ..Default::default()
+    }
+}
+
+fn current(stack: &[(usize, MaterialPoint)], skip: usize) -> Option<usize> {
+    current_medium(stack, skip).map(|(instance, _)| *instance)
+}
+
+#[test]
+fn highest_priority_medium_is_current() {
+    let stack = vec![
+        (0, medium(2, 1.33)),
+        (1, medium(1, 1.5)),
+        (2, medium(2, 1.2)),
+    ];
+    // ties go to the medium entered last
+    assert_eq!(current(&stack, usize::MAX), Some(2));
+    assert_eq!(current(&stack, 2), Some(0));
+    assert_eq!(current(&stack[..2], usize::MAX), Some(0));
+    assert_eq!(current(&stack[1..2], usize::MAX), Some(1));
+    assert_eq!(current(&stack[1..2], 1), None);
+    assert_eq!(current(&[], usize::MAX), None);
+}
+
+#[test]
+fn overlapping_media_toggle_by_priority() {
+    // a glass (priority 1) dipped into water (priority 2): its boundaries
+    // inside the water are skipped, but still tracked on the stack
+    let water = medium(2, 1.33);
+    let glass = medium(1, 1.5);
+    let mut stack = Vec::new();
+
+    toggle_medium(&mut stack, 0, water.clone());
+    assert_eq!(current(&stack, usize::MAX), Some(0));
+
+    let outer = current_medium(&stack, 1).map(|(_, outer)| outer.priority);
+    assert!(outer > Some(glass.priority));
+    toggle_medium(&mut stack, 1, glass.clone());
+    assert_eq!(stack.len(), 2);
+    assert_eq!(current(&stack, usize::MAX), Some(0));
+
+    // leaving the water above the surface, the glass takes over
+    toggle_medium(&mut stack, 0, water);
+    assert_eq!(current(&stack, usize::MAX), Some(1));
+    assert_eq!(current(&stack, 1), None);
+
+    // leaving the glass empties the stack
+    toggle_medium(&mut stack, 1, glass);
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn refraction_uses_the_enclosing_index() {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let outgoing = normalize(&vec3(0.6, 0.0, 0.8));
+
+    // matching indices pass straight through
+    let glass = MaterialPoint {
+        outer_ior: 1.5,
+        ..medium(0, 1.5)
+    };
+    let incoming = glass.sample_delta(&normal, &outgoing, 1.0);
+    assert!((incoming + outgoing).norm() < 1e-5);
+    let weight = glass.eval_delta(&normal, &outgoing, &incoming)
+        / glass.sample_delta_pdf(&normal, &outgoing, &incoming);
+    assert!((weight - vec3(1.0, 1.0, 1.0)).amax() < 1e-5);
+
+    // snell's law against water instead of air, entering and leaving
+    let glass = MaterialPoint {
+        outer_ior: 1.33,
+        ..medium(0, 1.5)
+    };
+    for (outgoing, eta) in [(outgoing, 1.33 / 1.5), (-outgoing, 1.5 / 1.33)] {
+        let incoming = glass.sample_delta(&normal, &outgoing, 1.0);
+        assert!(dot(&normal, &incoming) * dot(&normal, &outgoing) < 0.0);
+        let sin_outgoing = (1.0 - outgoing.z * outgoing.z).sqrt();
+        let sin_incoming = (1.0 - incoming.z * incoming.z).sqrt();
+        assert!((sin_incoming - eta * sin_outgoing).abs() < 1e-5);
+    }
+}
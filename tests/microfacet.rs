@@ -0,0 +1,291 @@
+// Statistical checks that the microfacet lobes sample directions with the
+// density reported by `sample_bsdfcos_pdf`.
+extern crate nalgebra_glm as glm;
+
+use glm::{normalize, vec2, vec3, Vec3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rtrace::scene_components::MaterialType;
+use rtrace::shading::MaterialPoint;
+use std::f32::consts::PI;
+
+const THETA_BINS: usize = 16;
+const PHI_BINS: usize = 32;
+const SAMPLES: usize = 400_000;
+
+fn bin_of(direction: &Vec3) -> usize {
+    let z = direction.z.clamp(-1.0, 1.0);
+    let phi = f32::atan2(direction.y, direction.x) + PI;
+    let i = usize::min(
+        ((z + 1.0) / 2.0 * THETA_BINS as f32) as usize,
+        THETA_BINS - 1,
+    );
+    let j = usize::min((phi / (2.0 * PI) * PHI_BINS as f32) as usize, PHI_BINS - 1);
+    i * PHI_BINS + j
+}
+
+// compares the histogram of sampled directions against the pdf integrated
+// over the same bins, parametrized by (cos theta, phi) so bins have equal area
+fn check_lobe(material: &MaterialPoint, outgoing: &Vec3) {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let mut rng = SmallRng::seed_from_u64(7);
+
+    let mut histogram = vec![0.0_f64; THETA_BINS * PHI_BINS];
+    for _ in 0..SAMPLES {
+        let rn = vec2(rng.gen::<f32>(), rng.gen::<f32>());
+        let incoming = material.sample_bsdfcos(&normal, outgoing, rng.gen::<f32>(), &rn);
+        if incoming == Vec3::zeros() || !incoming.iter().all(|c| c.is_finite()) {
+            continue;
+        }
+        histogram[bin_of(&incoming)] += 1.0 / SAMPLES as f64;
+    }
+
+    let substeps = 16;
+    let bin_area = (2.0 / THETA_BINS as f64) * (2.0 * std::f64::consts::PI / PHI_BINS as f64);
+    let mut integrated = vec![0.0_f64; THETA_BINS * PHI_BINS];
+    for (idx, value) in integrated.iter_mut().enumerate() {
+        let (i, j) = (idx / PHI_BINS, idx % PHI_BINS);
+        let mut sum = 0.0;
+        for si in 0..substeps {
+            for sj in 0..substeps {
+                let z = -1.0
+                    + 2.0 * (i as f32 + (si as f32 + 0.5) / substeps as f32) / THETA_BINS as f32;
+                let phi = -PI
+                    + 2.0 * PI * (j as f32 + (sj as f32 + 0.5) / substeps as f32) / PHI_BINS as f32;
+                let r = f32::sqrt(f32::max(0.0, 1.0 - z * z));
+                let incoming = vec3(r * f32::cos(phi), r * f32::sin(phi), z);
+                sum += material.sample_bsdfcos_pdf(&normal, outgoing, &incoming) as f64;
+            }
+        }
+        *value = sum / (substeps * substeps) as f64 * bin_area;
+    }
+
+    let sampled_total: f64 = histogram.iter().sum();
+    let integrated_total: f64 = integrated.iter().sum();
+    assert!(
+        (sampled_total - integrated_total).abs() < 0.02,
+        "{:?}: sampled mass {} but pdf integrates to {}",
+        material.m_type,
+        sampled_total,
+        integrated_total
+    );
+    for (idx, (sampled, expected)) in histogram.iter().zip(integrated.iter()).enumerate() {
+        assert!(
+            (sampled - expected).abs() < 0.005 + 0.1 * expected,
+            "{:?}: bin {} sampled {} but pdf gives {}",
+            material.m_type,
+            idx,
+            sampled,
+            expected
+        );
+    }
+}
+
+fn outgoing_directions() -> Vec<Vec3> {
+    vec![
+        vec3(0.0, 0.0, 1.0),
+        normalize(&vec3(0.5, 0.2, 0.8)),
+        normalize(&vec3(-0.9, 0.1, 0.25)),
+    ]
+}
+
+#[test]
+fn reflective_pdf_matches_sampling() {
+    let material = MaterialPoint {
+        m_type: MaterialType::Reflective,
+        color: vec3(0.9, 0.6, 0.3),
+        roughness: 0.3,
+        ..Default::default()
+    };
+    for outgoing in outgoing_directions() {
+        check_lobe(&material, &outgoing);
+    }
+}
+
+#[test]
+fn glossy_pdf_matches_sampling() {
+    let material = MaterialPoint {
+        m_type: MaterialType::Glossy,
+        color: vec3(0.5, 0.5, 0.5),
+        roughness: 0.2,
+        ior: 1.5,
+        ..Default::default()
+    };
+    for outgoing in outgoing_directions() {
+        check_lobe(&material, &outgoing);
+    }
+}
+
+#[test]
+fn gltfpbr_pdf_matches_sampling() {
+    let material = MaterialPoint {
+        m_type: MaterialType::Gltfpbr,
+        color: vec3(0.8, 0.2, 0.2),
+        roughness: 0.25,
+        metallic: 0.5,
+        ior: 1.5,
+        ..Default::default()
+    };
+    for outgoing in outgoing_directions() {
+        check_lobe(&material, &outgoing);
+    }
+}
+
+#[test]
+fn anisotropic_conductor_pdf_matches_sampling() {
+    let material = MaterialPoint {
+        m_type: MaterialType::Conductor,
+        roughness: 0.3,
+        anisotropy: 0.8,
+        tangent: normalize(&vec3(1.0, 1.0, 0.0)),
+        eta: vec3(0.143119, 0.374957, 1.44248),
+        etak: vec3(3.98316, 2.38572, 1.60322),
+        ..Default::default()
+    };
+    for outgoing in outgoing_directions() {
+        check_lobe(&material, &outgoing);
+    }
+}
+
+#[test]
+fn refractive_pdf_matches_sampling() {
+    let material = MaterialPoint {
+        m_type: MaterialType::Refractive,
+        color: vec3(1.0, 1.0, 1.0),
+        // transmission compresses the lobe, keep it wide enough for the bins
+        roughness: 0.6,
+        ior: 1.5,
+        ..Default::default()
+    };
+    for outgoing in outgoing_directions() {
+        check_lobe(&material, &outgoing);
+        check_lobe(&material, &(-outgoing));
+    }
+}
+
+#[test]
+fn transparent_pdf_matches_sampling() {
+    let material = MaterialPoint {
+        m_type: MaterialType::Transparent,
+        color: vec3(1.0, 1.0, 1.0),
+        roughness: 0.3,
+        ior: 1.5,
+        ..Default::default()
+    };
+    for outgoing in outgoing_directions() {
+        check_lobe(&material, &outgoing);
+    }
+}
+
+// white furnace: the average sample weight of a white lossless lobe is its
+// albedo, which should be one once multiple scattering is compensated
+fn furnace(material: &MaterialPoint, outgoing: &Vec3) -> f64 {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let mut rng = SmallRng::seed_from_u64(11);
+    let mut sum = 0.0;
+    for _ in 0..SAMPLES {
+        let rn = vec2(rng.gen::<f32>(), rng.gen::<f32>());
+        let incoming = material.sample_bsdfcos(&normal, outgoing, rng.gen::<f32>(), &rn);
+        if incoming == Vec3::zeros() {
+            continue;
+        }
+        let pdf = material.sample_bsdfcos_pdf(&normal, outgoing, &incoming);
+        if pdf > 0.0 {
+            let weight = material.eval_bsdfcos(&normal, outgoing, &incoming) / pdf;
+            sum += weight.mean() as f64;
+        }
+    }
+    sum / SAMPLES as f64
+}
+
+#[test]
+fn rough_metal_white_furnace() {
+    for roughness in [0.25, 0.5, 1.0] {
+        let reflective = MaterialPoint {
+            m_type: MaterialType::Reflective,
+            color: vec3(1.0, 1.0, 1.0),
+            roughness,
+            ..Default::default()
+        };
+        let gltfpbr = MaterialPoint {
+            m_type: MaterialType::Gltfpbr,
+            color: vec3(1.0, 1.0, 1.0),
+            roughness,
+            metallic: 1.0,
+            ior: 1.5,
+            ..Default::default()
+        };
+        for material in [reflective, gltfpbr] {
+            for outgoing in outgoing_directions() {
+                let albedo = furnace(&material, &outgoing);
+                assert!(
+                    (albedo - 1.0).abs() < 0.02,
+                    "{:?}: roughness {} gives albedo {}",
+                    material.m_type,
+                    roughness,
+                    albedo
+                );
+            }
+        }
+    }
+}
+
+fn principled() -> MaterialPoint {
+    MaterialPoint {
+        m_type: MaterialType::Principled,
+        color: vec3(0.9, 0.7, 0.5),
+        roughness: 0.3,
+        metallic: 0.2,
+        ior: 1.5,
+        sheen: vec3(0.5, 0.5, 0.5),
+        sheen_roughness: 0.25,
+        clearcoat: 0.8,
+        clearcoat_roughness: 0.05,
+        transmission: 0.5,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn principled_pdf_matches_sampling() {
+    // transmission compresses the lobe, keep it wide enough for the bins
+    let material = MaterialPoint {
+        roughness: 0.6,
+        ..principled()
+    };
+    for outgoing in outgoing_directions() {
+        check_lobe(&material, &outgoing);
+        check_lobe(&material, &(-outgoing));
+    }
+}
+
+#[test]
+fn principled_conserves_energy() {
+    let material = MaterialPoint {
+        color: vec3(1.0, 1.0, 1.0),
+        sheen: vec3(1.0, 1.0, 1.0),
+        clearcoat: 1.0,
+        metallic: 0.0,
+        transmission: 0.0,
+        ..principled()
+    };
+    // refraction compresses the radiance coming from inside by the squared
+    // ior, which bounds what glass may lose from below
+    let glass = MaterialPoint {
+        transmission: 1.0,
+        ..material.clone()
+    };
+    for outgoing in outgoing_directions() {
+        let albedo = furnace(&material, &outgoing);
+        assert!(
+            albedo > 0.9 && albedo < 1.02,
+            "principled gives albedo {}",
+            albedo
+        );
+        let albedo = furnace(&glass, &outgoing);
+        assert!(
+            albedo > 0.95 / (glass.ior * glass.ior) as f64 && albedo < 1.02,
+            "principled glass gives albedo {}",
+            albedo
+        );
+    }
+}
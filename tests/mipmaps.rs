@@ -0,0 +1,93 @@
+// Checks of the mip pyramid and of the footprint filtered texture lookups.
+extern crate nalgebra_glm as glm;
+
+use glm::{vec2, Vec2};
+use rtrace::scene_components::Texture;
+
+fn texture(width: u32, height: u32, value: impl Fn(u32, u32) -> f32) -> Texture {
+    let mut hdr = Vec::with_capacity((width * height) as usize);
+    for j in 0..height {
+        for i in 0..width {
+            let v = value(i, j);
+            hdr.push(image::Rgba([v, v, v, 1.0]));
+        }
+    }
+    let mut texture = Texture {
+        width,
+        height,
+        linear: true,
+        hdr,
+        ..Default::default()
+    };
+    texture.build_mips();
+    texture
+}
+
+#[test]
+fn pyramid_goes_down_to_one_texel() {
+    let texture = texture(37, 8, |_, _| 0.25);
+    let sizes: Vec<(u32, u32)> = texture.mips.iter().map(|m| (m.width, m.height)).collect();
+    assert_eq!(sizes, vec![(18, 4), (9, 2), (4, 1), (2, 1), (1, 1)]);
+    for mip in &texture.mips {
+        for (i, j) in (0..mip.height).flat_map(|j| (0..mip.width).map(move |i| (i, j))) {
+            assert!((mip.texel(i, j).x - 0.25).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn srgb_pyramid_averages_in_linear_space() {
+    let bytes = image::RgbaImage::from_fn(4, 4, |i, j| {
+        let v = if (i + j) % 2 == 0 { 0 } else { 255 };
+        image::Rgba([v, v, v, 255])
+    });
+    let mut texture = Texture {
+        width: 4,
+        height: 4,
+        bytes,
+        ..Default::default()
+    };
+    texture.build_mips();
+    // half of the linear intensity, encoded back to srgb
+    let expected = 1.055 * 0.5f32.powf(1.0 / 2.4) - 0.055;
+    for mip in &texture.mips {
+        assert_eq!(mip.data.len(), (mip.width * mip.height * 4) as usize);
+        assert!((mip.texel(0, 0).x - expected).abs() < 1.0 / 255.0);
+    }
+}
+
+#[test]
+fn zero_footprint_is_bilinear() {
+    let texture = texture(16, 16, |i, j| ((i + j) % 2) as f32);
+    let uv = vec2(3.0 / 16.0, 5.0 / 16.0);
+    let value = texture.eval_filtered(&uv, &Vec2::zeros(), &Vec2::zeros(), false);
+    assert!((value.x - 0.0).abs() < 1e-6);
+}
+
+#[test]
+fn wide_footprints_average_the_checkerboard() {
+    let texture = texture(64, 64, |i, j| ((i + j) % 2) as f32);
+    for k in 0..10 {
+        let uv = vec2(0.1 * k as f32 + 0.03, 0.07 * k as f32 + 0.01);
+        let isotropic = texture.eval_filtered(&uv, &vec2(0.1, 0.0), &vec2(0.0, 0.1), false);
+        assert!(
+            (isotropic.x - 0.5).abs() < 0.05,
+            "trilinear {}",
+            isotropic.x
+        );
+        let anisotropic = texture.eval_filtered(&uv, &vec2(0.2, 0.0), &vec2(0.0, 0.03), false);
+        assert!((anisotropic.x - 0.5).abs() < 0.05, "ewa {}", anisotropic.x);
+    }
+}
+
+#[test]
+fn ewa_preserves_detail_across_the_minor_axis() {
+    // stripes along x, a footprint elongated along x keeps them apart while a
+    // trilinear lookup of the same width would blur them to gray
+    let texture = texture(64, 64, |_, j| if (j / 8) % 2 == 0 { 0.0 } else { 1.0 });
+    let (dark, bright) = (vec2(0.3, 4.0 / 64.0), vec2(0.3, 12.0 / 64.0));
+    let (duvdx, duvdy) = (vec2(0.25, 0.0), vec2(0.0, 1.0 / 64.0));
+    let dark = texture.eval_filtered(&dark, &duvdx, &duvdy, false);
+    let bright = texture.eval_filtered(&bright, &duvdx, &duvdy, false);
+    assert!(dark.x < 0.25 && bright.x > 0.75, "{} {}", dark.x, bright.x);
+}
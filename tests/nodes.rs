@@ -0,0 +1,228 @@
+// Evaluation of material node graphs at a shading point.
+extern crate nalgebra_glm as glm;
+
+use glm::{vec2, vec3, vec4, Vec4};
+use rtrace::scene::Scene;
+use rtrace::scene_components::*;
+
+fn close(a: &Vec4, b: &Vec4) -> bool {
+    (a - b).norm() < 1e-5
+}
+
+// a scene with a single checker texture, black and white with four cells
+// along each side of the unit square
+fn checker_scene() -> Scene {
+    let mut scene = Scene::default();
+    scene.textures.push(Texture {
+        procedural: Some(Procedural {
+            p_type: ProceduralType::Checker,
+            scale: 4.0,
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    scene
+}
+
+fn value(value: Vec4) -> Node {
+    Node {
+        n_type: NodeType::Value,
+        value,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn math_and_mix_combine_inputs() {
+    let scene = Scene::default();
+    let material = Material {
+        nodes: vec![
+            value(vec4(0.2, 0.4, 0.6, 1.0)),
+            value(vec4(0.5, 0.5, 0.5, 1.0)),
+            Node {
+                n_type: NodeType::Math,
+                operation: MathOperation::Add,
+                inputs: vec![0, 1],
+                ..Default::default()
+            },
+            Node {
+                n_type: NodeType::Math,
+                operation: MathOperation::Clamp,
+                inputs: vec![2],
+                ..Default::default()
+            },
+            Node {
+                n_type: NodeType::Mix,
+                inputs: vec![0, 1],
+                value: vec4(0.25, 0.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            Node {
+                n_type: NodeType::Split,
+                inputs: vec![0],
+                channel: 2,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    material.check_nodes().unwrap();
+    let values = scene.eval_nodes(&material, &TexturePoint::default());
+    assert!(close(&values[2], &vec4(0.7, 0.9, 1.1, 2.0)));
+    assert!(close(&values[3], &vec4(0.7, 0.9, 1.0, 1.0)));
+    assert!(close(&values[4], &vec4(0.275, 0.425, 0.575, 1.0)));
+    assert!(close(&values[5], &vec4(0.6, 0.6, 0.6, 0.6)));
+}
+
+#[test]
+fn ramp_maps_texture_lookups() {
+    let scene = checker_scene();
+    let material = Material {
+        nodes: vec![
+            Node {
+                n_type: NodeType::Texture,
+                texture: 0,
+                ..Default::default()
+            },
+            Node {
+                n_type: NodeType::Ramp,
+                inputs: vec![0],
+                ramp_colors: vec![vec4(1.0, 0.0, 0.0, 1.0), vec4(0.0, 0.0, 1.0, 1.0)],
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    let point = TexturePoint {
+        texcoord: vec2(0.3, 0.1),
+        ..Default::default()
+    };
+    let values = scene.eval_nodes(&material, &point);
+    assert!(close(&values[0], &vec4(1.0, 1.0, 1.0, 1.0)));
+    assert!(close(&values[1], &vec4(0.0, 0.0, 1.0, 1.0)));
+}
+
+#[test]
+fn transforms_move_lookups() {
+    let scene = checker_scene();
+    let material = Material {
+        nodes: vec![
+            Node {
+                n_type: NodeType::Transform,
+                offset: vec2(0.25, 0.0),
+                ..Default::default()
+            },
+            Node {
+                n_type: NodeType::Texture,
+                texture: 0,
+                inputs: vec![0],
+                ..Default::default()
+            },
+            Node {
+                n_type: NodeType::Transform,
+                rotation: 90.0,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    let point = TexturePoint {
+        texcoord: vec2(0.1, 0.1),
+        ..Default::default()
+    };
+    let values = scene.eval_nodes(&material, &point);
+    assert!(close(&values[0], &vec4(0.35, 0.1, 0.0, 1.0)));
+    // shifted by one cell the lookup lands on the other color
+    assert!(close(&values[1], &vec4(1.0, 1.0, 1.0, 1.0)));
+    assert!(close(&values[2], &vec4(-0.1, 0.1, 0.0, 1.0)));
+}
+
+#[test]
+fn triplanar_blends_by_normal() {
+    let scene = checker_scene();
+    let material = Material {
+        nodes: vec![Node {
+            n_type: NodeType::Triplanar,
+            texture: 0,
+            space: TextureSpace::Object,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    // the yz projection is white and the xy one black at this position
+    let mut point = TexturePoint {
+        local_position: vec3(0.1, 0.1, 0.3),
+        local_normal: vec3(1.0, 0.0, 0.0),
+        ..Default::default()
+    };
+    let values = scene.eval_nodes(&material, &point);
+    assert!(close(&values[0], &vec4(1.0, 1.0, 1.0, 1.0)));
+    point.local_normal = vec3(0.0, 0.0, -1.0);
+    let values = scene.eval_nodes(&material, &point);
+    assert!(close(&values[0], &vec4(0.0, 0.0, 0.0, 1.0)));
+    point.local_normal = glm::normalize(&vec3(1.0, 0.0, 1.0));
+    let values = scene.eval_nodes(&material, &point);
+    assert!(close(&values[0], &vec4(0.5, 0.5, 0.5, 1.0)));
+}
+
+#[test]
+fn inputs_must_precede_nodes() {
+    let material = Material {
+        nodes: vec![Node {
+            n_type: NodeType::Math,
+            inputs: vec![0],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let error = material.check_nodes().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn triplanar_filters_each_projection() {
+    // a texel sized checker image, averaged to gray by wide footprints
+    let (width, height) = (64, 64);
+    let hdr = (0..width * height)
+        .map(|k| {
+            let v = ((k % width + k / width) % 2) as f32;
+            image::Rgba([v, v, v, 1.0])
+        })
+        .collect();
+    let mut texture = Texture {
+        width,
+        height,
+        linear: true,
+        hdr,
+        ..Default::default()
+    };
+    texture.build_mips();
+    let mut scene = Scene::default();
+    scene.textures.push(texture);
+    let material = Material {
+        nodes: vec![Node {
+            n_type: NodeType::Triplanar,
+            texture: 0,
+            space: TextureSpace::World,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let mut point = TexturePoint {
+        position: vec3(1.0 / 64.0, 3.0 / 64.0, 5.0 / 64.0),
+        normal: glm::normalize(&vec3(1.0, 0.0, 1.0)),
+        ..Default::default()
+    };
+    // without differentials both projections hit a black texel
+    let values = scene.eval_nodes(&material, &point);
+    assert!(close(&values[0], &vec4(0.0, 0.0, 0.0, 1.0)));
+    // a footprint along z is filtered on the yz projection and collapses to
+    // a point on the xy one
+    point.dpdx = vec3(0.0, 0.0, 0.1);
+    let values = scene.eval_nodes(&material, &point);
+    assert!((values[0].x - 0.25).abs() < 0.05, "{}", values[0].x);
+    point.dpdx = vec3(0.1, 0.1, 0.1);
+    point.dpdy = vec3(-0.1, 0.1, -0.1);
+    let values = scene.eval_nodes(&material, &point);
+    assert!((values[0].x - 0.5).abs() < 0.05, "{}", values[0].x);
+}
@@ -0,0 +1,204 @@
+// Shapes and scenes read from Wavefront OBJ and MTL files.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::{vec2, vec3, vec4};
+use rtrace::model_io::obj::read_shape;
+use rtrace::scene::Scene;
+use rtrace::scene_components::*;
+use std::path::PathBuf;
+
+fn scratch_file(name: &str, contents: &str) -> PathBuf {
+    let path = scratch(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn polygons_are_welded_and_fanned() {
+    // a quad with one normal for all corners, and a pentagon given with
+    // negative indices that shares an edge with it
+    let path = scratch_file(
+        "polygons.obj",
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 2 0 0\nv 2 1 0\nv 1 2 0\n\
+         vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+         vn 0 0 1\n\
+         f 1/1/1 2/2/1 3/3/1 4/4/1\n\
+         f -6/1/1 -3/2/1 -2/3/1 -1/4/1 3/3/1\n",
+    );
+    let mut shape = Shape::default();
+    read_shape(&path, &mut shape).unwrap();
+    // the corner 3/3/1 is shared, 2/1/1 differs from 2/2/1 in its texcoord
+    assert_eq!(shape.positions.len(), 8);
+    assert_eq!(shape.positions[4], vec3(1.0, 0.0, 0.0));
+    assert_eq!(shape.texcoords[1], vec2(1.0, 1.0));
+    assert_eq!(shape.normals, vec![vec3(0.0, 0.0, 1.0); 8]);
+    assert!(shape.triangles.is_empty());
+    assert_eq!(
+        shape.quads,
+        vec![
+            vec4(0, 1, 2, 3),
+            vec4(4, 5, 6, 6),
+            vec4(4, 6, 7, 7),
+            vec4(4, 7, 2, 2),
+        ]
+    );
+}
+
+#[test]
+fn triangles_stay_triangles() {
+    // texcoords on some vertices only are dropped
+    let path = scratch_file(
+        "triangles.obj",
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0.5 0.5\n\
+         f 1/1 2 3\nf 1 3 4\n",
+    );
+    let mut shape = Shape::default();
+    read_shape(&path, &mut shape).unwrap();
+    assert_eq!(shape.triangles, vec![vec3(0, 1, 2), vec3(0, 2, 3)]);
+    assert!(shape.texcoords.is_empty() && shape.normals.is_empty());
+
+    let path = scratch_file("invalid.obj", "v 0 0 0\nf 1 2 3\n");
+    assert!(read_shape(&path, &mut shape).is_err());
+}
+
+#[test]
+fn scenes_map_mtl_materials() {
+    image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 128, 0, 255]))
+        .save(scratch("albedo.png"))
+        .unwrap();
+    scratch_file(
+        "scene.mtl",
+        "newmtl red\nKd 0.8 0.1 0.1\nKs 0.04 0.04 0.04\nNs 100\nd 0.5\n\
+         map_Kd albedo.png\nmap_bump -bm 0.3 albedo.png\n\
+         newmtl lamp\nKd 0 0 0\nKe 10 10 10\n\
+         newmtl metal\nKd 0 0 0\nKs 0.9 0.6 0.3\n",
+    );
+    let path = scratch_file(
+        "scene.obj",
+        "mtllib scene.mtl\n\
+         v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 0 1 1\n\
+         usemtl red\nf 1 2 3 4\n\
+         o light\nusemtl lamp\nf 5 6 7\n\
+         o chrome\nusemtl metal\nf 1 5 6\n\
+         o rest\nusemtl missing\nf 1 2 5\n",
+    );
+    let scene = Scene::from_file(&path, None);
+    assert_eq!((scene.shapes.len(), scene.instances.len()), (4, 4));
+    assert_eq!(scene.shapes[1].triangles, vec![vec3(0, 1, 2)]);
+
+    let red = &scene.materials[scene.instances[0].material];
+    assert_eq!(red.m_type, MaterialType::Principled);
+    assert_eq!(red.color, vec3(0.8, 0.1, 0.1));
+    // a ks of 0.04 is 0.32% reflectance, against the 4% of the default index
+    assert_eq!(red.metallic, 0.0);
+    assert!((red.specular - 0.08).abs() < 1e-5);
+    assert!((red.roughness - f32::powf(2.0 / 102.0, 0.25)).abs() < 1e-6);
+    assert_eq!(red.opacity, 0.5);
+    // one texture for both maps of the same file
+    assert_eq!(scene.textures.len(), 1);
+    assert_eq!(
+        (red.color_tex, red.bump_tex, red.bump_strength),
+        (0, 0, 0.3)
+    );
+    assert_eq!(scene.textures[0].width, 2);
+
+    let lamp = &scene.materials[scene.instances[1].material];
+    assert_eq!(lamp.emission, vec3(10.0, 10.0, 10.0));
+    let metal = &scene.materials[scene.instances[2].material];
+    assert_eq!(metal.m_type, MaterialType::Reflective);
+    assert_eq!(metal.color, vec3(0.9, 0.6, 0.3));
+    let default = &scene.materials[scene.instances[3].material];
+    assert_eq!(default.m_type, MaterialType::Matte);
+    assert_eq!(default.color, vec3(0.8, 0.8, 0.8));
+
+    // the camera looks at the scene from +z
+    let camera = &scene.cameras[0];
+    let origin: glm::Vec3 = camera.frame.column(3).into();
+    assert_eq!((origin.x, origin.y), (0.5, 0.5));
+    assert!(origin.z > 1.0 && (camera.focus - (origin.z - 0.5)).abs() < 1e-5);
+}
+
+#[test]
+fn blender_materials_keep_their_diffuse_color() {
+    scratch_file(
+        "blender.mtl",
+        "newmtl Material\nNs 250.000000\nKa 1.000000 1.000000 1.000000\n\
+         Kd 0.800000 0.800000 0.800000\nKs 0.500000 0.500000 0.500000\n\
+         Ke 0.000000 0.000000 0.000000\nNi 1.450000\nd 1.000000\nillum 2\n",
+    );
+    let path = scratch_file(
+        "blender.obj",
+        "mtllib blender.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nusemtl Material\nf 1 2 3\n",
+    );
+    let scene = Scene::from_file(&path, None);
+    let material = &scene.materials[scene.instances[0].material];
+    assert_eq!(material.m_type, MaterialType::Principled);
+    assert_eq!(material.color, vec3(0.8, 0.8, 0.8));
+    assert_eq!(material.ior, 1.45);
+    // the specular of 0.5 keeps the 4% reflectance whatever the index
+    let reflectivity = f32::powi((1.45 - 1.0) / (1.45 + 1.0), 2);
+    assert_eq!(material.metallic, 0.0);
+    assert!((material.specular * reflectivity - 0.04).abs() < 1e-5);
+    assert!((material.roughness - f32::powf(2.0 / 252.0, 0.25)).abs() < 1e-6);
+}
+
+#[test]
+fn missing_libraries_fall_back_to_the_default_material() {
+    let _ = std::fs::remove_file(scratch("nowhere.mtl"));
+    let path = scratch_file(
+        "orphan.obj",
+        "mtllib nowhere.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nusemtl red\nf 1 2 3\n",
+    );
+    let scene = Scene::from_file(&path, None);
+    assert_eq!(scene.materials.len(), 1);
+    let material = &scene.materials[scene.instances[0].material];
+    assert_eq!(material.m_type, MaterialType::Matte);
+    assert_eq!(material.color, vec3(0.8, 0.8, 0.8));
+
+    // libraries that exist still have to parse
+    let path = scratch_file(
+        "broken.obj",
+        "mtllib broken.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n",
+    );
+    scratch_file("broken.mtl", "Kd 1 1 1\n");
+    assert!(std::panic::catch_unwind(|| Scene::from_file(&path, None)).is_err());
+}
+
+#[test]
+fn lines_and_points_get_shapes_of_their_own() {
+    // a bare line and point do not take the attributes away from the face
+    let path = scratch_file(
+        "mixed.obj",
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\n\
+         f 1/1 2/2 3/3\nl 3 4 1\np 4\n",
+    );
+    let scene = Scene::from_file(&path, None);
+    assert_eq!((scene.shapes.len(), scene.instances.len()), (3, 3));
+    let faces = &scene.shapes[0];
+    assert_eq!(faces.triangles, vec![vec3(0, 1, 2)]);
+    assert_eq!(faces.texcoords.len(), 3);
+    let lines = &scene.shapes[1];
+    assert_eq!(lines.lines, vec![vec2(0, 1), vec2(1, 2)]);
+    assert_eq!(lines.positions[1], vec3(0.0, 1.0, 0.0));
+    assert!(lines.texcoords.is_empty());
+    let points = &scene.shapes[2];
+    assert_eq!(points.points, vec![0]);
+    assert_eq!(points.positions, vec![vec3(0.0, 1.0, 0.0)]);
+    // a single shape holds a single kind of element
+    assert!(read_shape(&path, &mut Shape::default()).is_err());
+}
+
+#[test]
+fn shape_uris_match_the_extension_in_any_case() {
+    scratch_file("upper.OBJ", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n");
+    let path = scratch_file(
+        "upper.json",
+        r#"{"shapes": [{"uri": "upper.OBJ"}], "materials": [{}],
+            "instances": [{"shape": 0, "material": 0}], "cameras": [{}]}"#,
+    );
+    let scene = Scene::from_file(&path, None);
+    assert_eq!(scene.shapes[0].triangles, vec![vec3(0, 1, 2)]);
+}
@@ -0,0 +1,80 @@
+// The same mesh read from ply fixtures in ascii and both binary endiannesses,
+// each storing its properties with different types and names.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::{vec2, vec3, vec4};
+use rtrace::model_io::ply::read_shape;
+use rtrace::scene_components::Shape;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn check_mesh(name: &str) {
+    let mut shape = Shape::default();
+    read_shape(&fixture(name), &mut shape).unwrap();
+    assert_eq!(shape.positions.len(), 7);
+    assert_eq!(shape.positions[6], vec3(1.0, 2.0, 0.0));
+    assert_eq!(shape.normals, vec![vec3(0.0, 0.0, 1.0); 7]);
+    // texcoords are half the positions, flipped vertically
+    assert_eq!(shape.texcoords[5], vec2(1.0, 0.5));
+    assert_eq!(shape.colors[0], vec4(1.0, 0.0, 0.0, 1.0));
+    assert_eq!(shape.colors[4], vec4(0.0, 1.0, 0.0, 1.0));
+    // the pentagon is fanned and stored next to the quad
+    assert!(shape.triangles.is_empty());
+    assert_eq!(
+        shape.quads,
+        vec![
+            vec4(0, 1, 2, 3),
+            vec4(1, 4, 5, 5),
+            vec4(1, 5, 6, 6),
+            vec4(1, 6, 2, 2),
+        ]
+    );
+}
+
+#[test]
+fn ascii_mesh() {
+    check_mesh("mesh_ascii.ply");
+}
+
+#[test]
+fn binary_little_endian_mesh() {
+    check_mesh("mesh_binary_little_endian.ply");
+}
+
+#[test]
+fn binary_big_endian_mesh() {
+    check_mesh("mesh_binary_big_endian.ply");
+}
+
+// a triangle with the given face property and face
+fn read_triangle(name: &str, property: &str, face: &str) -> std::io::Result<()> {
+    let path = scratch(name);
+    let contents = format!(
+        "ply\nformat ascii 1.0\nelement vertex 3\n\
+         property float x\nproperty float y\nproperty float z\n\
+         element face 1\n{}\nend_header\n0 0 0\n1 0 0\n0 1 0\n{}\n",
+        property, face
+    );
+    std::fs::write(&path, contents).unwrap();
+    read_shape(&path, &mut Shape::default())
+}
+
+#[test]
+fn invalid_files_are_errors() {
+    let indices = "property list uchar int vertex_indices";
+    assert!(read_triangle("valid.ply", indices, "3 0 1 2").is_ok());
+    assert!(read_triangle("bounds.ply", indices, "3 0 1 3").is_err());
+    assert!(read_triangle("line.ply", indices, "2 0 1").is_err());
+    let floats = "property list uchar float vertex_indices";
+    assert!(read_triangle("float.ply", floats, "3 0 1 2").is_err());
+    let missing = scratch("missing.ply");
+    assert!(read_shape(&missing, &mut Shape::default()).is_err());
+}
@@ -0,0 +1,151 @@
+// Consistency checks of the analytic shapes between ray intersection and
+// surface evaluation.
+extern crate nalgebra_glm as glm;
+
+use glm::{vec2, vec3, Vec3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rtrace::scene_components::{Primitive, PrimitiveType};
+
+fn primitives() -> Vec<Primitive> {
+    vec![
+        Primitive {
+            p_type: PrimitiveType::Sphere,
+            radius: 0.7,
+            ..Default::default()
+        },
+        Primitive {
+            p_type: PrimitiveType::Disk,
+            radius: 1.2,
+            ..Default::default()
+        },
+        Primitive {
+            p_type: PrimitiveType::Cylinder,
+            radius: 0.5,
+            height: 1.5,
+            ..Default::default()
+        },
+        Primitive {
+            p_type: PrimitiveType::Rectangle,
+            size: vec2(2.0, 0.5),
+            ..Default::default()
+        },
+        Primitive {
+            p_type: PrimitiveType::Plane,
+            ..Default::default()
+        },
+    ]
+}
+
+fn random_direction(rng: &mut SmallRng) -> Vec3 {
+    let z = 1.0 - 2.0 * rng.gen::<f32>();
+    let r = f32::sqrt(1.0 - z * z);
+    let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+    vec3(r * f32::cos(phi), r * f32::sin(phi), z)
+}
+
+#[test]
+fn hits_lie_on_the_evaluated_surface() {
+    let mut rng = SmallRng::seed_from_u64(3);
+    for primitive in primitives() {
+        let mut hits = 0;
+        for _ in 0..10_000 {
+            // rays from outside the bounds aimed at points near the shape
+            let target = vec3(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 2.0
+                - vec3(1.0, 1.0, 1.0);
+            let origin = target - random_direction(&mut rng) * 5.0;
+            let direction = glm::normalize(&(target - origin));
+            let Some((distance, uv, normal)) =
+                primitive.intersect(&origin, &direction, 0.0, f32::MAX)
+            else {
+                continue;
+            };
+            hits += 1;
+            let position = origin + direction * distance;
+            assert!(
+                glm::distance(&position, &primitive.eval_position(&uv)) < 1e-3,
+                "{:?} at {:?}",
+                primitive.p_type,
+                uv
+            );
+            assert!(
+                glm::distance(&glm::normalize(&normal), &primitive.eval_normal(&uv)) < 1e-3,
+                "{:?} at {:?}",
+                primitive.p_type,
+                uv
+            );
+            let (lower, upper) = primitive.bounds();
+            assert!((position - lower).min() > -1e-4 && (upper - position).min() > -1e-4);
+        }
+        assert!(hits > 100, "{:?} hit {} times", primitive.p_type, hits);
+    }
+}
+
+#[test]
+fn intersection_respects_ray_extent() {
+    let sphere = Primitive::default();
+    let origin = vec3(0.0, 0.0, -3.0);
+    let direction = vec3(0.0, 0.0, 1.0);
+    let (near, ..) = sphere
+        .intersect(&origin, &direction, 0.0, f32::MAX)
+        .unwrap();
+    assert!((near - 2.0).abs() < 1e-5);
+    let (far, ..) = sphere
+        .intersect(&origin, &direction, 2.5, f32::MAX)
+        .unwrap();
+    assert!((far - 4.0).abs() < 1e-5);
+    assert!(sphere.intersect(&origin, &direction, 0.0, 1.5).is_none());
+}
+
+#[test]
+fn sampled_uvs_are_uniform_in_area() {
+    // the fraction of samples in a band must match its share of the area
+    let mut rng = SmallRng::seed_from_u64(19);
+    let samples = 100_000;
+    let sphere = Primitive::default();
+    let disk = Primitive {
+        p_type: PrimitiveType::Disk,
+        ..Default::default()
+    };
+    let (mut cap, mut center) = (0, 0);
+    for _ in 0..samples {
+        let ruv = vec2(rng.gen::<f32>(), rng.gen::<f32>());
+        if sphere.eval_position(&sphere.sample_uv(&ruv)).z > 0.5 {
+            cap += 1;
+        }
+        if glm::length(&disk.eval_position(&disk.sample_uv(&ruv))) < 0.5 {
+            center += 1;
+        }
+    }
+    // a cap of height 0.5 covers a quarter of the sphere, the inner half
+    // radius a quarter of the disk
+    assert!((cap as f32 / samples as f32 - 0.25).abs() < 0.01);
+    assert!((center as f32 / samples as f32 - 0.25).abs() < 0.01);
+}
+
+#[test]
+fn instance_frames_scale_the_area() {
+    let frame = glm::Mat3x4::from_columns(&[
+        vec3(2.0, 0.0, 0.0),
+        vec3(0.0, 0.0, 2.0),
+        vec3(0.0, -3.0, 0.0),
+        vec3(1.0, 1.0, 1.0),
+    ]);
+    let areas = primitives()
+        .iter()
+        .map(|primitive| primitive.area_in(&frame) / primitive.area())
+        .collect::<Vec<_>>();
+    // planar shapes scale by their two axes, cylinders by their radial and
+    // height axes
+    assert!((areas[1] - 4.0).abs() < 1e-5);
+    assert!((areas[2] - 6.0).abs() < 1e-5);
+    assert!((areas[3] - 4.0).abs() < 1e-5);
+    let rotation = glm::Mat3x4::from_columns(&[
+        vec3(0.0, 1.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+        vec3(1.0, 0.0, 0.0),
+        vec3(4.0, 0.0, 0.0),
+    ]);
+    for primitive in &primitives()[..4] {
+        assert!((primitive.area_in(&rotation) - primitive.area()).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,110 @@
+// Ranges and shapes of the procedural texture patterns.
+extern crate nalgebra_glm as glm;
+
+use glm::{vec3, vec4, Vec3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rtrace::scene_components::{Procedural, ProceduralType};
+use rtrace::utils::voronoi_noise;
+
+fn random_points(count: usize) -> Vec<Vec3> {
+    let mut rng = SmallRng::seed_from_u64(5);
+    (0..count)
+        .map(|_| {
+            vec3(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 20.0
+                - vec3(10.0, 10.0, 10.0)
+        })
+        .collect()
+}
+
+#[test]
+fn checker_alternates_between_cells() {
+    let checker = Procedural {
+        p_type: ProceduralType::Checker,
+        scale: 4.0,
+        ..Default::default()
+    };
+    let a = checker.eval(&vec3(0.1, 0.1, 0.0), true);
+    let b = checker.eval(&vec3(0.35, 0.1, 0.0), true);
+    let c = checker.eval(&vec3(0.35, 0.35, 0.0), true);
+    assert_eq!(a, checker.color0);
+    assert_eq!(b, checker.color1);
+    assert_eq!(c, checker.color0);
+    // planar lookups ignore z, solid ones alternate along it
+    assert_eq!(checker.eval(&vec3(0.1, 0.1, 0.3), true), checker.color0);
+    assert_eq!(checker.eval(&vec3(0.1, 0.1, 0.3), false), checker.color1);
+}
+
+#[test]
+fn noise_stays_in_range() {
+    for p_type in [ProceduralType::Fbm, ProceduralType::Turbulence] {
+        for simplex in [false, true] {
+            let noise = Procedural {
+                p_type,
+                simplex,
+                color0: vec4(0.0, 0.0, 0.0, 0.0),
+                color1: vec4(1.0, 1.0, 1.0, 1.0),
+                ..Default::default()
+            };
+            let values: Vec<f32> = random_points(20_000)
+                .iter()
+                .map(|p| noise.eval(p, false).x)
+                .collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            let (min, max) = values
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+            assert!((0.0..=1.0).contains(&min) && (0.0..=1.0).contains(&max));
+            // the pattern is not flat
+            assert!(
+                max - min > 0.3,
+                "{:?} {}: spans {} to {}",
+                p_type,
+                simplex,
+                min,
+                max
+            );
+            if p_type == ProceduralType::Fbm {
+                assert!((mean - 0.5).abs() < 0.1, "fbm {}: mean {}", simplex, mean);
+            }
+        }
+    }
+}
+
+#[test]
+fn voronoi_is_continuous() {
+    // without jitter the features sit at the cell centers
+    assert!(voronoi_noise(&vec3(2.5, -3.5, 0.5), 0.0) < 1e-6);
+    let step = vec3(1e-3, 1e-3, 1e-3);
+    for point in random_points(2000) {
+        let a = voronoi_noise(&point, 1.0);
+        let b = voronoi_noise(&(point + step), 1.0);
+        assert!((a - b).abs() <= glm::length(&step) + 1e-5);
+    }
+}
+
+#[test]
+fn gradient_interpolates_ramp() {
+    let gradient = Procedural {
+        p_type: ProceduralType::Gradient,
+        ramp_positions: vec![0.0, 0.5, 1.0],
+        ramp_colors: vec![
+            vec4(0.0, 0.0, 0.0, 1.0),
+            vec4(1.0, 0.0, 0.0, 1.0),
+            vec4(1.0, 1.0, 0.0, 1.0),
+        ],
+        ..Default::default()
+    };
+    let color = gradient.eval(&vec3(0.25, 0.7, 0.0), true);
+    assert!((color - vec4(0.5, 0.0, 0.0, 1.0)).norm() < 1e-6);
+    let color = gradient.eval(&vec3(0.75, 0.0, 0.0), true);
+    assert!((color - vec4(1.0, 0.5, 0.0, 1.0)).norm() < 1e-6);
+    // outside the ramp the end colors are held
+    assert_eq!(
+        gradient.eval(&vec3(-1.0, 0.0, 0.0), true),
+        gradient.ramp_colors[0]
+    );
+    assert_eq!(
+        gradient.eval(&vec3(2.0, 0.0, 0.0), true),
+        gradient.ramp_colors[2]
+    );
+}
@@ -0,0 +1,83 @@
+// Texcoords and blend weights of the projections for shapes without
+// texcoords.
+extern crate nalgebra_glm as glm;
+
+use glm::{normalize, vec2, vec3, Vec2};
+use rtrace::scene_components::{Projection, ProjectionType};
+
+fn projection(p_type: ProjectionType) -> Projection {
+    Projection {
+        p_type,
+        ..Default::default()
+    }
+}
+
+fn close(a: &Vec2, b: &Vec2) -> bool {
+    (a - b).norm() < 1e-5
+}
+
+#[test]
+fn planar_and_cylindrical_texcoords() {
+    let position = vec3(0.3, -0.2, 0.7);
+    let planar = Projection {
+        scale: 2.0,
+        ..projection(ProjectionType::Planar)
+    };
+    assert!(close(&planar.eval_texcoord(&position, 0), &vec2(0.6, -0.4)));
+    let cylindrical = projection(ProjectionType::Cylindrical);
+    let texcoord = cylindrical.eval_texcoord(&vec3(0.0, 2.0, 0.7), 0);
+    assert!(close(&texcoord, &vec2(0.75, 0.7)));
+    assert!(cylindrical.is_periodic() && !planar.is_periodic());
+}
+
+#[test]
+fn spherical_texcoords_span_the_unit_square() {
+    let spherical = projection(ProjectionType::Spherical);
+    assert!(close(
+        &spherical.eval_texcoord(&vec3(0.0, 0.0, 3.0), 0),
+        &vec2(0.5, 0.0)
+    ));
+    assert!(close(
+        &spherical.eval_texcoord(&vec3(-1.0, 0.0, 0.0), 0),
+        &vec2(1.0, 0.5)
+    ));
+    // the radius does not matter
+    let a = spherical.eval_texcoord(&vec3(0.2, 0.5, -0.3), 0);
+    let b = spherical.eval_texcoord(&vec3(0.4, 1.0, -0.6), 0);
+    assert!(close(&a, &b));
+}
+
+#[test]
+fn box_picks_the_facing_plane() {
+    let box_projection = projection(ProjectionType::Box);
+    let weights = box_projection.eval_weights(&normalize(&vec3(0.2, -0.9, 0.3)));
+    assert_eq!(weights, vec3(0.0, 1.0, 0.0));
+    let position = vec3(0.1, 0.2, 0.3);
+    assert!(close(
+        &box_projection.eval_texcoord(&position, 1),
+        &vec2(0.3, 0.1)
+    ));
+}
+
+#[test]
+fn triplanar_weights_sharpen() {
+    let normal = normalize(&vec3(1.0, 0.5, 0.0));
+    let soft = Projection {
+        sharpness: 1.0,
+        ..projection(ProjectionType::Triplanar)
+    };
+    let sharp = Projection {
+        sharpness: 8.0,
+        ..projection(ProjectionType::Triplanar)
+    };
+    let (soft, sharp) = (soft.eval_weights(&normal), sharp.eval_weights(&normal));
+    assert!((soft.sum() - 1.0).abs() < 1e-5 && (sharp.sum() - 1.0).abs() < 1e-5);
+    assert!((soft.x - 2.0 / 3.0).abs() < 1e-5);
+    assert!(sharp.x > soft.x && sharp.x > 0.99);
+    assert_eq!(sharp.z, 0.0);
+    // uv mapped materials read a single point
+    assert_eq!(
+        projection(ProjectionType::Uv).eval_weights(&normal),
+        vec3(1.0, 0.0, 0.0)
+    );
+}
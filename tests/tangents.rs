@@ -0,0 +1,198 @@
+// Vertex tangents generated for shapes with texcoords and their use in
+// normal mapping.
+extern crate nalgebra_glm as glm;
+
+use glm::{normalize, vec2, vec3, vec4, Vec2, Vec3, Vec4};
+use rtrace::bvh::BvhIntersection;
+use rtrace::scene::Scene;
+use rtrace::scene_components::*;
+use rtrace::trace::Ray;
+
+// unit quad on z = 0 facing +z
+fn quad(texcoords: Vec<Vec2>, tangents: Vec<Vec4>) -> Shape {
+    Shape {
+        quads: vec![vec4(0, 1, 2, 3)],
+        positions: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ],
+        normals: vec![vec3(0.0, 0.0, 1.0); 4],
+        texcoords,
+        tangents,
+        ..Default::default()
+    }
+}
+
+fn aligned() -> Vec<Vec2> {
+    vec![
+        vec2(0.0, 0.0),
+        vec2(1.0, 0.0),
+        vec2(1.0, 1.0),
+        vec2(0.0, 1.0),
+    ]
+}
+
+#[test]
+fn tangents_follow_texcoords() {
+    let mut shape = quad(aligned(), vec![]);
+    shape.compute_tangents();
+    assert_eq!(shape.tangents.len(), 4);
+    for tangent in &shape.tangents {
+        assert!((tangent - vec4(1.0, 0.0, 0.0, 1.0)).norm() < 1e-5);
+    }
+    // mirroring u flips the tangent and the handedness
+    let mirrored = aligned().iter().map(|uv| vec2(1.0 - uv.x, uv.y)).collect();
+    let mut shape = quad(mirrored, vec![]);
+    shape.compute_tangents();
+    for tangent in &shape.tangents {
+        assert!((tangent - vec4(-1.0, 0.0, 0.0, -1.0)).norm() < 1e-5);
+    }
+}
+
+#[test]
+fn mirrored_seams_split_vertices() {
+    // two quads sharing an edge, with u running back on the right one
+    let mut shape = Shape {
+        quads: vec![vec4(0, 1, 4, 3), vec4(1, 2, 5, 4)],
+        positions: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(2.0, 1.0, 0.0),
+        ],
+        normals: vec![vec3(0.0, 0.0, 1.0); 6],
+        texcoords: vec![
+            vec2(0.0, 0.0),
+            vec2(1.0, 0.0),
+            vec2(0.0, 0.0),
+            vec2(0.0, 1.0),
+            vec2(1.0, 1.0),
+            vec2(0.0, 1.0),
+        ],
+        ..Default::default()
+    };
+    shape.compute_tangents();
+    assert_eq!(shape.positions.len(), 8);
+    assert_eq!(shape.tangents.len(), 8);
+    for (quad, expected) in shape
+        .quads
+        .iter()
+        .zip([vec4(1.0, 0.0, 0.0, 1.0), vec4(-1.0, 0.0, 0.0, -1.0)])
+    {
+        for &vertex in quad.iter() {
+            let vertex = vertex as usize;
+            assert!((shape.tangents[vertex] - expected).norm() < 1e-5);
+        }
+    }
+    // split vertices keep their position
+    assert_eq!(
+        shape.positions[shape.quads[1].x as usize],
+        vec3(1.0, 0.0, 0.0)
+    );
+    assert_eq!(
+        shape.positions[shape.quads[1].w as usize],
+        vec3(1.0, 1.0, 0.0)
+    );
+}
+
+#[test]
+fn supplied_tangents_are_kept() {
+    let tangents = vec![vec4(0.0, 1.0, 0.0, -1.0); 4];
+    let mut shape = quad(aligned(), tangents.clone());
+    shape.compute_tangents();
+    assert_eq!(shape.tangents, tangents);
+    // nothing to orient without texcoords
+    let mut shape = quad(vec![], vec![]);
+    shape.compute_tangents();
+    assert!(shape.tangents.is_empty());
+}
+
+fn normal_mapped(tangents: Vec<Vec4>, texel: [u8; 4]) -> Vec3 {
+    normal_mapped_shape(quad(aligned(), tangents), Projection::default(), texel)
+}
+
+// shading normal seen from the side the shape faces
+fn normal_mapped_shape(shape: Shape, projection: Projection, texel: [u8; 4]) -> Vec3 {
+    let outgoing = shape.normals[0];
+    let mut scene = Scene::default();
+    scene.shapes.push(shape);
+    scene.materials.push(Material {
+        normal_tex: 0,
+        projection,
+        ..Default::default()
+    });
+    scene.textures.push(Texture {
+        width: 1,
+        height: 1,
+        bytes: image::RgbaImage::from_pixel(1, 1, image::Rgba(texel)),
+        ..Default::default()
+    });
+    scene.instances.push(Instance {
+        shape: 0,
+        material: 0,
+        ..Default::default()
+    });
+    let intersection = BvhIntersection {
+        instance: 0,
+        element: 0,
+        uv: vec2(0.3, 0.4),
+        hit: true,
+        ..Default::default()
+    };
+    let material = scene.eval_material(&intersection, &Ray::default());
+    scene.eval_shading_normal(&intersection, &outgoing, &material)
+}
+
+#[test]
+fn normal_maps_use_vertex_tangents() {
+    // a normal tilted towards the tangent
+    let texel = [204, 128, 255, 255];
+    let normal = normal_mapped(vec![], texel);
+    assert!((normal - normalize(&vec3(0.6, 0.0, 1.0))).norm() < 1e-2);
+    let normal = normal_mapped(vec![vec4(0.0, 1.0, 0.0, 1.0); 4], texel);
+    assert!((normal - normalize(&vec3(0.0, 0.6, 1.0))).norm() < 1e-2);
+
+    // a normal tilted towards the bitangent follows the handedness
+    let texel = [128, 204, 255, 255];
+    let right = normal_mapped(vec![vec4(1.0, 0.0, 0.0, 1.0); 4], texel);
+    let left = normal_mapped(vec![vec4(1.0, 0.0, 0.0, -1.0); 4], texel);
+    assert!((right - normal_mapped(vec![], texel)).norm() < 1e-5);
+    assert!((right.y + left.y).abs() < 1e-5 && right.y.abs() > 0.4);
+}
+
+#[test]
+fn projected_normal_maps_need_no_texcoords() {
+    let texel = [204, 128, 255, 255];
+    let expected = normalize(&vec3(0.6, 0.0, 1.0));
+    for p_type in [ProjectionType::Planar, ProjectionType::Triplanar] {
+        let projection = Projection {
+            p_type,
+            ..Default::default()
+        };
+        let normal = normal_mapped_shape(quad(vec![], vec![]), projection, texel);
+        assert!((normal - expected).norm() < 1e-2, "{:?} {}", p_type, normal);
+    }
+
+    // a quad facing +x takes the frame of the yz projection, u along y
+    let mut shape = quad(vec![], vec![]);
+    shape.positions = shape
+        .positions
+        .iter()
+        .map(|p| vec3(0.0, p.x, p.y))
+        .collect();
+    shape.normals = vec![vec3(1.0, 0.0, 0.0); 4];
+    let projection = Projection {
+        p_type: ProjectionType::Triplanar,
+        ..Default::default()
+    };
+    let normal = normal_mapped_shape(shape, projection, texel);
+    assert!(
+        (normal - normalize(&vec3(1.0, 0.6, 0.0))).norm() < 1e-2,
+        "{}",
+        normal
+    );
+}
@@ -0,0 +1,232 @@
+// Lookups of textures loaded on demand through the texture cache against the
+// same textures decoded whole.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::{vec2, Vec4};
+use rtrace::model_io::texture::{find_udim_tiles, read_image};
+use rtrace::scene_components::Texture;
+use rtrace::texture_cache::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// image with a pattern, without the cache file of earlier runs
+fn scratch_image(name: &str) -> PathBuf {
+    scratch_image_sized(name, 150, 70)
+}
+
+fn scratch_image_sized(name: &str, width: u32, height: u32) -> PathBuf {
+    let path = scratch(name);
+    image::RgbaImage::from_fn(width, height, |i, j| {
+        image::Rgba([
+            (i * 7 % 256) as u8,
+            (j * 13 % 256) as u8,
+            ((i + j) % 256) as u8,
+            255,
+        ])
+    })
+    .save(&path)
+    .unwrap();
+    let _ = std::fs::remove_file(scratch(&format!("{}.rtx", name)));
+    path
+}
+
+fn eager(path: &Path) -> Texture {
+    let mut texture = Texture::default();
+    read_image(path, &mut texture).unwrap();
+    texture.build_mips();
+    texture
+}
+
+fn lazy(path: &Path, cache: &Arc<TextureCache>) -> Texture {
+    let mut texture = Texture::default();
+    texture.set_lazy(LazyTexture::open(path, cache.clone()).unwrap());
+    texture
+}
+
+// lookup from a thread of its own, which has no tiles at hand
+fn lookup_on_thread(texture: &Texture, i: u32, j: u32) -> Vec4 {
+    std::thread::scope(|scope| scope.spawn(|| texture.lookup(i, j, false)).join().unwrap())
+}
+
+// compares full resolution texels and filtered lookups over the pyramid
+fn check_matches(eager: &Texture, lazy: &Texture, tolerance: f32) {
+    assert_eq!((eager.width, eager.height), (lazy.width, lazy.height));
+    assert_eq!(eager.mip_count(), lazy.mip_count());
+    for j in (0..eager.height).step_by(3) {
+        for i in (0..eager.width).step_by(5) {
+            assert_eq!(eager.lookup(i, j, true), lazy.lookup(i, j, true));
+        }
+    }
+    for k in 0..50 {
+        let uv = vec2(k as f32 * 0.173 % 1.0, k as f32 * 0.311 % 1.0);
+        let footprint = 0.002 * (k + 1) as f32;
+        let (duvdx, duvdy) = (vec2(footprint, 0.0), vec2(0.0, footprint * 0.7));
+        let a = eager.eval_filtered(&uv, &duvdx, &duvdy, false);
+        let b = lazy.eval_filtered(&uv, &duvdx, &duvdy, false);
+        assert!((a - b).amax() <= tolerance, "{} {}", a, b);
+    }
+}
+
+#[test]
+fn converted_tiles_match_decoded_texture() {
+    let path = scratch_image("converted.png");
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget: 1 << 30,
+        convert: true,
+    }));
+    let texture = lazy(&path, &cache);
+    assert!(path.with_extension("png.rtx").exists());
+    // mips are stored with the precision of the image
+    check_matches(&eager(&path), &texture, 1.0 / 255.0);
+    let stats = cache.stats();
+    assert!(stats.misses > 0 && stats.hits > stats.misses);
+    assert_eq!(stats.evictions, 0);
+}
+
+#[test]
+fn unconverted_images_stay_within_the_budget() {
+    // four times the budget at full resolution
+    let path = scratch_image_sized("unconverted.png", 256, 192);
+    let budget = 3 * (TILE_SIZE * TILE_SIZE * 4) as usize;
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget,
+        convert: false,
+    }));
+    let texture = lazy(&path, &cache);
+    assert_eq!(cache.stats(), CacheStats::default());
+    check_matches(&eager(&path), &texture, 1.0 / 255.0);
+    // tiles are read from a temporary cache file, not from a decoded copy
+    // kept aside, and nothing is written next to the image
+    assert!(!path.with_extension("png.rtx").exists());
+    for j in 0..texture.height {
+        for i in 0..texture.width {
+            texture.lookup(i, j, false);
+            assert!(cache.stats().memory <= budget);
+        }
+    }
+    let stats = cache.stats();
+    assert!(stats.misses > 1 && stats.evictions > 0);
+}
+
+#[test]
+fn concurrent_misses_load_once() {
+    let path = scratch_image("concurrent.png");
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget: 1 << 30,
+        convert: false,
+    }));
+    let texture = lazy(&path, &cache);
+    let expected = eager(&path).lookup(3, 5, false);
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..8)
+            .map(|_| scope.spawn(|| texture.lookup(3, 5, false)))
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), expected);
+        }
+    });
+    assert_eq!(cache.stats().misses, 1);
+}
+
+#[test]
+fn udim_tiles_load_through_the_cache() {
+    let path = scratch_image("udim.1001.png");
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget: 1 << 30,
+        convert: false,
+    }));
+    let mut tiles = find_udim_tiles(&path.with_file_name("udim.<UDIM>.png")).unwrap();
+    for tile in tiles.values_mut() {
+        tile.cache = Some(cache.clone());
+    }
+    let texture = Texture {
+        tiles,
+        ..Default::default()
+    };
+    let (tile, _) = texture.eval_tile(&vec2(0.5, 0.5)).unwrap();
+    assert!(tile.lazy.is_some());
+    assert_eq!(tile.lookup(3, 5, false), eager(&path).lookup(3, 5, false));
+    assert_eq!(cache.stats().misses, 1);
+}
+
+#[test]
+fn budget_evicts_least_recently_used_tiles() {
+    let path = scratch_image("budget.png");
+    let budget = 3 * (TILE_SIZE * TILE_SIZE * 4) as usize;
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget,
+        convert: true,
+    }));
+    let texture = lazy(&path, &cache);
+    check_matches(&eager(&path), &texture, 1.0 / 255.0);
+    let stats = cache.stats();
+    assert!(stats.evictions > 0);
+    assert!(stats.memory <= budget);
+
+    // a recently used tile stays cached for other threads
+    lookup_on_thread(&texture, 0, 0);
+    let misses = cache.stats().misses;
+    lookup_on_thread(&texture, 1, 1);
+    assert_eq!(cache.stats().misses, misses);
+}
+
+#[test]
+fn conversions_make_room_within_the_budget() {
+    let budget = 3 * (TILE_SIZE * TILE_SIZE * 4) as usize;
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget,
+        convert: false,
+    }));
+    let first = lazy(&scratch_image("room_first.png"), &cache);
+    let second = lazy(&scratch_image("room_second.png"), &cache);
+    first.lookup(0, 0, false);
+    assert_eq!(cache.stats().evictions, 0);
+    // the two decoded levels of the second image do not fit next to the
+    // tile of the first one
+    second.lookup(0, 0, false);
+    let stats = cache.stats();
+    assert!(stats.evictions > 0);
+    assert!(stats.memory <= budget);
+}
+
+#[test]
+fn truncated_cache_files_are_converted_again() {
+    let path = scratch_image("truncated.png");
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget: 1 << 30,
+        convert: true,
+    }));
+    let tiled = path.with_extension("png.rtx");
+    drop(lazy(&path, &cache));
+    let length = std::fs::metadata(&tiled).unwrap().len();
+    // a conversion cut short, newer than its image
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&tiled)
+        .unwrap();
+    file.set_len(length / 2).unwrap();
+    drop(file);
+    let texture = lazy(&path, &cache);
+    assert_eq!(std::fs::metadata(&tiled).unwrap().len(), length);
+    check_matches(&eager(&path), &texture, 1.0 / 255.0);
+}
+
+#[test]
+fn tiles_that_fail_to_load_are_white() {
+    let path = scratch_image("vanishing.png");
+    let cache = Arc::new(TextureCache::new(CacheOptions {
+        budget: 1 << 30,
+        convert: false,
+    }));
+    let texture = lazy(&path, &cache);
+    // the image breaks after the scene is loaded, before its first lookup
+    std::fs::write(&path, b"not an image").unwrap();
+    assert_eq!(texture.lookup(3, 5, false), glm::vec4(1.0, 1.0, 1.0, 1.0));
+    assert_eq!(texture.lookup(3, 5, false), glm::vec4(1.0, 1.0, 1.0, 1.0));
+    // the failure is remembered, the tile is not read again
+    assert_eq!(cache.stats().misses, 1);
+    assert_eq!(cache.stats().memory, 0);
+}
@@ -0,0 +1,237 @@
+// Round trips of the texture formats through the image readers, and the
+// lookups of wrap modes and udim tile sets.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::vec2;
+use rtrace::model_io::obj::read_shape;
+use rtrace::model_io::texture::{find_udim_tiles, read_image, read_texture};
+use rtrace::scene::Scene;
+use rtrace::scene_components::{Shape, Texture, WrapMode};
+
+#[test]
+fn sixteen_bit_png_keeps_precision() {
+    let path = scratch("ramp16.png");
+    let image =
+        image::ImageBuffer::from_fn(256, 1, |i, _| image::Rgb([i as u16 * 256 + 1, 1000, 65535]));
+    image.save(&path).unwrap();
+
+    let mut texture = Texture::default();
+    read_image(&path, &mut texture).unwrap();
+    assert_eq!((texture.width, texture.height), (256, 1));
+    assert!(texture.bytes.is_empty() && !texture.shorts.is_empty());
+    let color = texture.lookup(7, 0, false);
+    assert!((color.x - (7.0 * 256.0 + 1.0) / 65535.0).abs() < 1e-6);
+    assert!((color.y - 1000.0 / 65535.0).abs() < 1e-6);
+    assert!((color.w - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn grayscale_fills_all_channels() {
+    let path = scratch("roughness.png");
+    image::GrayImage::from_fn(4, 4, |i, j| image::Luma([(i * 16 + j) as u8]))
+        .save(&path)
+        .unwrap();
+
+    let mut texture = Texture::default();
+    read_image(&path, &mut texture).unwrap();
+    let color = texture.lookup(2, 3, false);
+    let expected = 35.0 / 255.0;
+    assert!((color.x - expected).abs() < 1e-6);
+    assert!((color.y - expected).abs() < 1e-6);
+    assert!((color.z - expected).abs() < 1e-6);
+    assert!((color.w - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn eight_bit_formats_load() {
+    let image = image::RgbaImage::from_fn(8, 8, |i, j| {
+        image::Rgba([i as u8 * 30, j as u8 * 30, 90, 255])
+    });
+    for name in ["texture.tga", "texture.tiff", "texture.bmp", "texture.jpg"] {
+        let path = scratch(name);
+        if name.ends_with("jpg") {
+            image::DynamicImage::ImageRgba8(image.clone())
+                .into_rgb8()
+                .save(&path)
+                .unwrap();
+        } else {
+            image.save(&path).unwrap();
+        }
+        let mut texture = Texture::default();
+        read_image(&path, &mut texture).unwrap();
+        assert_eq!((texture.width, texture.height), (8, 8), "{}", name);
+        let color = texture.lookup(3, 5, false);
+        // jpeg is lossy
+        let tolerance = if name.ends_with("jpg") { 0.1 } else { 1e-6 };
+        assert!((color.x - 90.0 / 255.0).abs() < tolerance, "{}", name);
+        assert!((color.y - 150.0 / 255.0).abs() < tolerance, "{}", name);
+    }
+}
+
+#[test]
+fn unknown_formats_fail() {
+    let path = scratch("texture.unknown");
+    std::fs::write(&path, b"not an image").unwrap();
+    let mut texture = Texture::default();
+    assert!(read_image(&path, &mut texture).is_err());
+}
+
+#[test]
+fn exr_keeps_alpha() {
+    let path = scratch("alpha.exr");
+    exr::prelude::write_rgba_file(&path, 3, 2, |x, y| (x as f32, y as f32, 4.0f32, 0.25f32))
+        .unwrap();
+    let mut texture = Texture::default();
+    read_texture(&path, &mut texture).unwrap();
+    assert_eq!((texture.width, texture.height), (3, 2));
+    assert!(texture.linear);
+    assert_eq!(texture.lookup(2, 1, false), glm::vec4(2.0, 1.0, 4.0, 0.25));
+}
+
+#[test]
+fn luminance_exr_fills_all_channels() {
+    use exr::prelude::*;
+    let path = scratch("luminance.exr");
+    let values = (0..6).map(|i| i as f32 * 0.5).collect();
+    let channels = AnyChannels::sort(SmallVec::from_vec(vec![AnyChannel::new(
+        "Y",
+        FlatSamples::F32(values),
+    )]));
+    Image::from_channels((3, 2), channels)
+        .write()
+        .to_file(&path)
+        .unwrap();
+    let mut texture = Texture::default();
+    read_texture(&path, &mut texture).unwrap();
+    assert_eq!(texture.lookup(1, 1, false), glm::vec4(2.0, 2.0, 2.0, 1.0));
+}
+
+#[test]
+fn float_tiff_is_hdr() {
+    use tiff::encoder::{colortype::RGB32Float, TiffEncoder};
+    let path = scratch("float.tiff");
+    let values = (0..12).map(|i| i as f32 * 10.0).collect::<Vec<_>>();
+    TiffEncoder::new(std::fs::File::create(&path).unwrap())
+        .unwrap()
+        .write_image::<RGB32Float>(2, 2, &values)
+        .unwrap();
+    let mut texture = Texture::default();
+    read_texture(&path, &mut texture).unwrap();
+    assert!(texture.bytes.is_empty() && texture.linear);
+    assert_eq!(
+        texture.lookup(1, 1, false),
+        glm::vec4(90.0, 100.0, 110.0, 1.0)
+    );
+}
+
+// four texels wide ramp, nearest lookups read the texel index back
+fn ramp(wrap: WrapMode) -> Texture {
+    Texture {
+        width: 4,
+        height: 1,
+        linear: true,
+        wrap,
+        hdr: (0..4)
+            .map(|i| image::Rgba([i as f32, 0.0, 0.0, 1.0]))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn wrap_modes_map_texcoords() {
+    let texel = |texture: &Texture, u: f32| texture.eval(&vec2(u, 0.5), false, true).x;
+    let repeat = ramp(WrapMode::Repeat);
+    assert_eq!(texel(&repeat, 1.3), 1.0);
+    assert_eq!(texel(&repeat, -0.1), 3.0);
+    let clamp = ramp(WrapMode::Clamp);
+    assert_eq!(texel(&clamp, 1.3), 3.0);
+    assert_eq!(texel(&clamp, -0.1), 0.0);
+    let mirror = ramp(WrapMode::Mirror);
+    assert_eq!(texel(&mirror, 1.1), 3.0);
+    assert_eq!(texel(&mirror, 1.6), 1.0);
+    assert_eq!(texel(&mirror, -0.1), 0.0);
+    // bilinear lookups do not blend across a clamped edge
+    assert_eq!(clamp.eval(&vec2(0.99, 0.5), false, false).x, 3.0);
+}
+
+#[test]
+fn udim_tiles_load_on_lookup() {
+    let directory = scratch("udim");
+    std::fs::create_dir_all(&directory).unwrap();
+    // the top row of each tile is one brighter than its bottom row
+    for (tile, value) in [(1001, 10), (1002, 20), (1011, 30)] {
+        image::GrayImage::from_fn(1, 2, |_, j| image::Luma([value + 1 - j as u8]))
+            .save(directory.join(format!("albedo.{}.png", tile)))
+            .unwrap();
+    }
+    std::fs::write(directory.join("albedo.notes.png"), b"not a tile").unwrap();
+
+    let texture = Texture {
+        tiles: find_udim_tiles(&directory.join("albedo.<UDIM>.png")).unwrap(),
+        ..Default::default()
+    };
+    assert_eq!(texture.tiles.len(), 3);
+    assert!(texture
+        .tiles
+        .values()
+        .all(|tile| tile.texture.get().is_none()));
+
+    // texcoords in udim space as read from a mesh, which flips them
+    let path = directory.join("tiles.obj");
+    std::fs::write(
+        &path,
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+         vt 0.5 0.25\nvt 1.5 0.75\nvt 0.5 1.25\nvt 2.5 0.5\n\
+         f 1/1 2/2 3/3 4/4\n",
+    )
+    .unwrap();
+    let mut shape = Shape::default();
+    read_shape(&path, &mut shape).unwrap();
+    let value = |index: usize| {
+        texture
+            .eval_tile(&shape.texcoords[index])
+            .map(|(tile, uv)| (tile.eval(&uv, false, true).x * 255.0).round())
+    };
+    assert_eq!(value(0), Some(10.0));
+    assert_eq!(value(1), Some(21.0));
+    assert_eq!(value(2), Some(30.0));
+    assert_eq!(value(3), None);
+    assert_eq!(
+        texture
+            .tiles
+            .values()
+            .filter(|t| t.texture.get().is_some())
+            .count(),
+        3
+    );
+    assert!(find_udim_tiles(&directory.join("missing.<UDIM>.png")).is_err());
+}
+
+#[test]
+fn broken_udim_tiles_fail_at_load_or_show_as_missing() {
+    let directory = scratch("udim_broken");
+    std::fs::create_dir_all(&directory).unwrap();
+    let mut png = Vec::new();
+    image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(64, 64, |i, j| {
+        image::Luma([(i * j) as u8])
+    }))
+    .write_to(&mut png, image::ImageOutputFormat::Png)
+    .unwrap();
+    // the header is intact but the pixels are cut short
+    std::fs::write(directory.join("cut.1001.png"), &png[..png.len() / 2]).unwrap();
+    let texture = Texture {
+        tiles: find_udim_tiles(&directory.join("cut.<UDIM>.png")).unwrap(),
+        ..Default::default()
+    };
+    assert!(texture.eval_tile(&vec2(0.5, 0.5)).is_none());
+
+    // tiles that are not images at all stop the scene from loading
+    std::fs::write(directory.join("junk.1001.png"), b"not an image").unwrap();
+    let path = directory.join("junk.json");
+    std::fs::write(&path, r#"{"textures": [{"uri": "junk.<UDIM>.png"}]}"#).unwrap();
+    assert!(std::panic::catch_unwind(|| Scene::from_file(&path, None)).is_err());
+}
@@ -0,0 +1,104 @@
+// Sanity checks of the thin film reflectance against closed-form cases and
+// of its tabulated rgb response.
+extern crate nalgebra_glm as glm;
+
+use glm::{vec3, Vec3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rtrace::scene_components::MaterialType;
+use rtrace::shading::{fresnel_thin_film, thin_film_reflectance, FilmResponse, MaterialPoint};
+use std::sync::Arc;
+
+#[test]
+fn vanishing_film_is_plain_fresnel() {
+    let reflectance = thin_film_reflectance(1.0, 1.33, 1.5, 0.0, 0.0, 1.0, 550.0);
+    let expected = ((1.5 - 1.0) / (1.5 + 1.0)) * ((1.5 - 1.0) / (1.5 + 1.0));
+    assert!((reflectance - expected).abs() < 1e-5, "{}", reflectance);
+}
+
+#[test]
+fn quarter_wave_coating_cancels_reflection() {
+    // a film with ior sqrt(n) and optical thickness of a quarter wavelength
+    let (base, wavelength) = (1.5_f32, 550.0);
+    let film = base.sqrt();
+    let thickness = wavelength / (4.0 * film);
+    let coated = thin_film_reflectance(1.0, film, base, 0.0, thickness, 1.0, wavelength);
+    assert!(coated < 1e-5, "{}", coated);
+    // half wave films are invisible instead
+    let absent = thin_film_reflectance(1.0, film, base, 0.0, 2.0 * thickness, 1.0, wavelength);
+    let bare = thin_film_reflectance(1.0, 1.0, base, 0.0, 0.0, 1.0, wavelength);
+    assert!((absent - bare).abs() < 1e-5, "{} {}", absent, bare);
+}
+
+#[test]
+fn total_internal_reflection_is_preserved() {
+    let reflectance = thin_film_reflectance(1.5, 1.33, 1.0, 0.0, 300.0, 0.2, 550.0);
+    assert!((reflectance - 1.0).abs() < 1e-4, "{}", reflectance);
+}
+
+#[test]
+fn tabulated_response_matches_integration() {
+    let film = FilmResponse::new(1.33, 600.0);
+    let bases = [
+        (1.0, vec3(1.5, 1.5, 1.5), Vec3::zeros()),
+        (1.5, vec3(1.0, 1.0, 1.0), Vec3::zeros()),
+        (1.2, vec3(0.2, 0.4, 1.4), vec3(3.9, 2.4, 1.6)),
+    ];
+    let mut rng = SmallRng::seed_from_u64(7);
+    for (eta_i, eta, etak) in bases {
+        for _ in 0..200 {
+            let thickness = 600.0 * rng.gen::<f32>();
+            let cosine = rng.gen::<f32>();
+            let tabulated = film.eval(eta_i, &eta, &etak, thickness, cosine);
+            let integrated = fresnel_thin_film(eta_i, 1.33, &eta, &etak, thickness, cosine);
+            assert!(
+                (tabulated - integrated).amax() < 0.01,
+                "{} {} {} {}",
+                thickness,
+                cosine,
+                tabulated,
+                integrated
+            );
+        }
+        // thicker films than the table are integrated
+        let tabulated = film.eval(eta_i, &eta, &etak, 750.0, 0.5);
+        let integrated = fresnel_thin_film(eta_i, 1.33, &eta, &etak, 750.0, 0.5);
+        assert_eq!(tabulated, integrated);
+    }
+}
+
+#[test]
+fn films_see_the_surrounding_medium() {
+    let normal = vec3(0.0, 0.0, 1.0);
+    let reflectance = |material: &MaterialPoint| {
+        material.eval_delta(&normal, &normal, &normal)
+            / material.sample_delta_pdf(&normal, &normal, &normal)
+    };
+    let bare = |outer_ior: f32| MaterialPoint {
+        m_type: MaterialType::Conductor,
+        eta: vec3(0.2, 0.2, 0.2),
+        etak: vec3(3.0, 3.0, 3.0),
+        outer_ior,
+        ..Default::default()
+    };
+    // immersion lowers the index contrast, and a film of the same index as
+    // the medium around it is invisible
+    assert!(reflectance(&bare(1.33)).x < reflectance(&bare(1.0)).x - 0.01);
+    let coated = |outer_ior: f32, film: Option<Arc<FilmResponse>>| MaterialPoint {
+        film_thickness: 300.0,
+        film_ior: 1.33,
+        film,
+        ..bare(outer_ior)
+    };
+    let expected = reflectance(&bare(1.33));
+    for film in [None, Some(Arc::new(FilmResponse::new(1.33, 300.0)))] {
+        let immersed = reflectance(&coated(1.33, film.clone()));
+        assert!(
+            (immersed - expected).amax() < 1e-3,
+            "{} {}",
+            immersed,
+            expected
+        );
+        let in_air = reflectance(&coated(1.0, film));
+        assert!((in_air - expected).amax() > 0.01, "{} {}", in_air, expected);
+    }
+}
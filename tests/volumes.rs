@@ -0,0 +1,126 @@
+// Grid volumes read from disk, their trilinear density lookups and the
+// transmittance estimated by delta and ratio tracking through them.
+extern crate nalgebra_glm as glm;
+
+mod common;
+
+use common::scratch;
+use glm::{vec3, Vec3};
+use parking_lot::Mutex;
+use rand::{rngs::SmallRng, SeedableRng};
+use rtrace::model_io::vol::read_volume;
+use rtrace::scene::Scene;
+use rtrace::scene_components::Volume;
+use rtrace::shading::MaterialPoint;
+use rtrace::trace::Ray;
+
+fn vol_file(encoding: i32, channels: i32, values: &[u8]) -> Vec<u8> {
+    let mut data = b"VOL\x03".to_vec();
+    for value in [encoding, 2, 1, 1, channels] {
+        data.extend(value.to_le_bytes());
+    }
+    for value in [0.0f32, 0.0, 0.0, 2.0, 1.0, 1.0] {
+        data.extend(value.to_le_bytes());
+    }
+    data.extend(values);
+    data
+}
+
+#[test]
+fn vol_and_raw_grids_load() {
+    // two channel floats are averaged per voxel
+    let mut values = Vec::new();
+    for value in [1.0f32, 3.0, 0.5, 0.5] {
+        values.extend(value.to_le_bytes());
+    }
+    let path = scratch("floats.vol");
+    std::fs::write(&path, vol_file(1, 2, &values)).unwrap();
+    let mut volume = Volume::default();
+    read_volume(&path, &mut volume).unwrap();
+    assert_eq!((volume.width, volume.height, volume.depth), (2, 1, 1));
+    assert_eq!(volume.density, vec![2.0, 0.5]);
+    assert_eq!(volume.bbox_max, vec3(2.0, 1.0, 1.0));
+
+    let path = scratch("bytes.vol");
+    std::fs::write(&path, vol_file(3, 1, &[255, 51])).unwrap();
+    read_volume(&path, &mut volume).unwrap();
+    assert_eq!(volume.density, vec![1.0, 0.2]);
+
+    // raw grids take their resolution from the scene
+    let path = scratch("grid.raw");
+    std::fs::write(&path, [0.25f32, 0.75].map(f32::to_le_bytes).concat()).unwrap();
+    let mut volume = Volume {
+        width: 1,
+        height: 2,
+        depth: 1,
+        ..Default::default()
+    };
+    read_volume(&path, &mut volume).unwrap();
+    assert_eq!(volume.density, vec![0.25, 0.75]);
+
+    // truncated grids and unknown formats are errors
+    std::fs::write(&path, 0.25f32.to_le_bytes()).unwrap();
+    assert!(read_volume(&path, &mut volume).is_err());
+    let path = scratch("grid.vdb");
+    std::fs::write(&path, b"").unwrap();
+    assert!(read_volume(&path, &mut volume).is_err());
+}
+
+fn grid_scene(width: u32, density: Vec<f32>) -> Scene {
+    let mut scene = Scene::default();
+    scene.volumes.push(Volume {
+        width,
+        height: width,
+        depth: width,
+        bbox_min: vec3(0.0, 0.0, 0.0),
+        bbox_max: vec3(1.0, 1.0, 1.0),
+        max_density: density.iter().fold(0.0, |a, &b| f32::max(a, b)),
+        density,
+        ..Default::default()
+    });
+    scene
+}
+
+#[test]
+fn densities_interpolate_between_voxel_centers() {
+    let scene = grid_scene(2, (0..8).map(|value| value as f32).collect());
+    let density = |x: f32, y: f32, z: f32| scene.eval_volume(0, &vec3(x, y, z));
+    // voxel centers, x varies fastest
+    assert_eq!(density(0.25, 0.25, 0.25), 0.0);
+    assert_eq!(density(0.75, 0.75, 0.75), 7.0);
+    assert_eq!(density(0.75, 0.25, 0.75), 5.0);
+    assert!((density(0.5, 0.5, 0.5) - 3.5).abs() < 1e-5);
+    assert!((density(0.375, 0.25, 0.25) - 0.25).abs() < 1e-5);
+    assert!((density(0.25, 0.25, 0.5) - 2.0).abs() < 1e-5);
+    // clamped at the border, empty outside the bounds
+    assert_eq!(density(0.1, 0.1, 0.1), 0.0);
+    assert_eq!(density(1.1, 0.5, 0.5), 0.0);
+}
+
+#[test]
+fn tracking_matches_homogeneous_transmittance() {
+    let scene = grid_scene(4, vec![1.0; 64]);
+    let medium = MaterialPoint {
+        density: vec3(1.0, 2.0, 4.0),
+        volume: 0,
+        ..Default::default()
+    };
+    let ray = Ray::new(vec3(0.1, 0.5, 0.5), vec3(1.0, 0.0, 0.0));
+    let distance = 0.6;
+    let expected = glm::exp(&(-medium.density * distance));
+    let rng = Mutex::new(SmallRng::seed_from_u64(23));
+    let samples = 40_000;
+
+    // delta tracking weights the paths that reach the end of the segment
+    let mut delta = Vec3::zeros();
+    let mut ratio = Vec3::zeros();
+    for _ in 0..samples {
+        let (sampled, weight) = scene.sample_volume_distance(&medium, &ray, distance, &rng);
+        if sampled >= distance {
+            delta += weight / samples as f32;
+        }
+        ratio += scene.eval_volume_transmittance(&medium, &ray, distance, &rng) / samples as f32;
+    }
+    assert!((delta - expected).amax() < 0.01, "{} {}", delta, expected);
+    assert!((ratio - expected).amax() < 0.01, "{} {}", ratio, expected);
+}